@@ -0,0 +1,70 @@
+//! Times parsing, flattening, and matching a synthetic `npm ls --all --json`
+//! tree: `cargo bench --bench large_tree [-- NODES]` (default 100000 nodes).
+
+use npm_compromised_scan::{collect_deps, find_matches, CompromisedList};
+use serde_json::{Map, Value};
+use std::time::Instant;
+
+/// A tree of about `budget` nodes, each with up to `fanout` children, drawing
+/// names from a pool so that packages recur at many places and versions.
+fn tree(budget: &mut usize, depth: usize, fanout: usize, seed: &mut u64) -> Value {
+    let mut deps = Map::new();
+    for _ in 0..fanout {
+        if *budget == 0 {
+            break;
+        }
+        *budget -= 1;
+        // xorshift, so the tree is the same on every run
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 7;
+        *seed ^= *seed << 17;
+        let name = format!("pkg-{}", *seed % 5_000);
+        let version = format!("{}.{}.0", *seed % 3, (*seed >> 8) % 2);
+        let mut node = Map::new();
+        node.insert("version".into(), Value::String(version));
+        if depth > 1 {
+            node.insert("dependencies".into(), tree(budget, depth - 1, fanout, seed));
+        }
+        deps.insert(name, Value::Object(node));
+    }
+    Value::Object(deps)
+}
+
+fn main() {
+    let nodes = std::env::args()
+        .skip(1)
+        .find_map(|a| a.parse().ok())
+        .unwrap_or(100_000);
+    let mut budget = nodes;
+    let mut seed = 0x2545_f491_4f6c_dd1d;
+    let mut root = Map::new();
+    while budget > 0 {
+        let deps = tree(&mut budget, 24, 6, &mut seed);
+        let top = root.entry("dependencies").or_insert_with(|| Value::Object(Map::new()));
+        top.as_object_mut().unwrap().extend(deps.as_object().unwrap().clone());
+    }
+    let json = serde_json::to_string(&Value::Object(root)).unwrap();
+
+    let list: CompromisedList = (0..2_000)
+        .map(|i| match i % 3 {
+            0 => format!("pkg-{i}\n"),
+            1 => format!("pkg-{i}@1.2.3\n"),
+            _ => format!("pkg-{i}@>=2.0.0 <3.0.0\n"),
+        })
+        .collect::<String>()
+        .parse()
+        .unwrap();
+
+    let started = Instant::now();
+    let parsed: Value = serde_json::from_str(&json).unwrap();
+    let parse = started.elapsed();
+    let deps = collect_deps(&parsed);
+    let collect = started.elapsed() - parse;
+    let matches = find_matches(&deps, &list);
+    let matching = started.elapsed() - parse - collect;
+
+    println!("{nodes} nodes, {} bytes of JSON", json.len());
+    println!("parse:   {parse:?}");
+    println!("collect: {collect:?} ({} unique deps)", deps.len());
+    println!("match:   {matching:?} ({} matches)", matches.len());
+}
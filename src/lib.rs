@@ -0,0 +1,2163 @@
+//! Parsing of compromised package lists and matching of npm dependency trees
+//! against them, as used by the `npm-compromised-scan` binary.
+//!
+//! ```
+//! use npm_compromised_scan::{scan, CompromisedList};
+//!
+//! let list: CompromisedList = "left-pad@1.3.0\n".parse().unwrap();
+//! let tree = r#"{"dependencies": {"left-pad": {"version": "1.3.0"}}}"#;
+//! let matches = scan(tree, &list).unwrap();
+//! assert_eq!(matches[0].match_type, "exact");
+//! ```
+
+use chrono::NaiveDate;
+use semver::{Version, VersionReq};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Errors from reading lists and npm output.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Unable to read compromised list file: {path:?}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Invalid entry at line {line}: '{text}' ({reason})")]
+    InvalidEntry {
+        line: usize,
+        text: String,
+        reason: String,
+    },
+    #[error("Invalid OSV list: {0}")]
+    Osv(String),
+    #[error("npm output is not valid JSON")]
+    Json(#[from] serde_json::Error),
+    #[error("No dependency map at root key '{key}' (object-valued keys: {available})")]
+    RootKey { key: String, available: String },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+static WARNING_HANDLER: std::sync::OnceLock<fn(&str)> = std::sync::OnceLock::new();
+
+/// Send the library's warnings, such as versions that do not parse as semver,
+/// to `handler` instead of stderr. Only the first call takes effect.
+pub fn set_warning_handler(handler: fn(&str)) {
+    WARNING_HANDLER.get_or_init(|| handler);
+}
+
+fn warn(message: &str) {
+    match WARNING_HANDLER.get() {
+        Some(handler) => handler(message),
+        None => eprintln!("Warning: {message}"),
+    }
+}
+
+/// One installed package, deduplicated by name and version.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
+pub struct Dep {
+    pub name: String,
+    pub version: String,
+    /// Declared by the root project
+    pub direct: bool,
+    /// Kind of dependency every install location is reached through; `Prod`
+    /// when the input does not say
+    #[serde(default)]
+    pub kind: DepKind,
+}
+
+/// Which kind of dependency a package is, from npm's `dev`, `optional`,
+/// `devOptional`, and `peer` node flags.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(rename_all = "camelCase")]
+pub enum DepKind {
+    #[default]
+    Prod,
+    Dev,
+    Optional,
+    /// Reachable only through dev or optional dependencies
+    DevOptional,
+    Peer,
+}
+
+impl DepKind {
+    /// Kind of an `npm ls --long`, `npm query`, or lockfile node.
+    pub fn of(node: &Value) -> Self {
+        let flag = |key: &str| node.get(key).and_then(|f| f.as_bool()).unwrap_or(false);
+        if flag("dev") {
+            DepKind::Dev
+        } else if flag("devOptional") {
+            DepKind::DevOptional
+        } else if flag("optional") {
+            DepKind::Optional
+        } else if flag("peer") {
+            DepKind::Peer
+        } else {
+            DepKind::Prod
+        }
+    }
+
+    /// Kind of a package installed at two locations of these kinds: left out
+    /// only when both would be. Combinations no kind expresses become `Prod`.
+    pub fn merge(self, other: Self) -> Self {
+        use DepKind::*;
+        match (self, other) {
+            (a, b) if a == b => a,
+            (Dev | Optional | DevOptional, Dev | Optional | DevOptional) => DevOptional,
+            _ => Prod,
+        }
+    }
+
+    /// Whether npm's `--omit` with these types (`dev`, `optional`, `peer`) leaves it out.
+    pub fn is_omitted(self, omit: &[String]) -> bool {
+        let omits = |t: &str| omit.iter().any(|o| o == t);
+        match self {
+            DepKind::Prod => false,
+            DepKind::Dev => omits("dev"),
+            DepKind::Optional => omits("optional"),
+            DepKind::DevOptional => omits("dev") && omits("optional"),
+            DepKind::Peer => omits("peer"),
+        }
+    }
+
+    pub fn is_prod(&self) -> bool {
+        *self == DepKind::Prod
+    }
+}
+
+/// A parsed compromised list, as the sets that deps are matched against.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct CompromisedList {
+    /// `name@version` entries, as (name, version)
+    pub exact: HashSet<(String, String)>,
+    /// `name@<npm range>` entries, as (name, range)
+    pub ranges: Vec<(String, VersionRange)>,
+    /// Every listed name: bare name entries and the names of all other entries
+    pub names: HashSet<String>,
+    /// Names listed on their own, without a version
+    pub bare_names: HashSet<String>,
+    /// Normalized repository URL prefixes of `repo:` entries
+    pub repos: Vec<String>,
+    /// Tarball hashes of integrity entries, the digest possibly truncated
+    pub integrities: Vec<IntegrityEntry>,
+    /// Name pattern entries, tried only for deps no literal entry names
+    pub patterns: Vec<PatternEntry>,
+    /// name -> `>=` range; name matches below it don't fail
+    pub floors: HashMap<String, String>,
+    /// `severity:` annotations, see `CompromisedList::entry_key`
+    pub severities: HashMap<String, Severity>,
+    /// `safe:` annotations, same keys
+    pub safe_versions: HashMap<String, Vec<String>>,
+    /// List file each entry came from, same keys
+    pub sources: HashMap<String, String>,
+    /// OSV advisories each entry came from, same keys
+    pub advisories: HashMap<String, Vec<Advisory>>,
+    /// Entries skipped because `expires:` has passed
+    pub expired: Vec<ExpiredEntry>,
+}
+
+/// `severity:` annotation of a list entry, lowest first.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl Severity {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "low" => Some(Severity::Low),
+            "medium" => Some(Severity::Medium),
+            "high" => Some(Severity::High),
+            "critical" => Some(Severity::Critical),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+/// Severity assumed for matches whose list entry has no `severity:` annotation.
+pub const DEFAULT_SEVERITY: Severity = Severity::High;
+
+/// A list entry skipped because its `expires:` date has passed.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct ExpiredEntry {
+    pub entry: String,
+    pub expired_on: NaiveDate,
+}
+
+impl std::str::FromStr for CompromisedList {
+    type Err = Error;
+
+    /// Parse list text (or OSV JSON) with the default `ParseOptions`.
+    fn from_str(content: &str) -> Result<Self> {
+        let opts = ParseOptions::default();
+        let entries = parse_list_text(content, opts.separator, "(string)", opts.verbose)?;
+        Ok(CompromisedList::from_entries(entries, opts.as_of))
+    }
+}
+
+impl CompromisedList {
+    /// Read and parse a list file with the default `ParseOptions`.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        parse_compromised_file(path.as_ref(), &ParseOptions::default())
+    }
+
+    /// Key of the entry behind a match for per-entry annotations. Entries are
+    /// keyed as written (`name@version`, `name@range`, `repo:url`,
+    /// `integrity:<alg>-<digest>`, `integrity:name@version#<alg>-<digest>`), and each
+    /// name also collects the annotations of every entry for it, which name
+    /// matches use.
+    pub fn entry_key(m: &MatchRecord) -> String {
+        let entry = m.entry.clone().unwrap_or_default();
+        match m.match_type.as_str() {
+            "exact" if entry.is_empty() => format!("{}@{}", m.name, m.version),
+            "exact" | "range" | "pattern" | "basename" | "confusable" => entry,
+            "repo" => format!("repo:{entry}"),
+            "integrity" | "integrity-prefix" => format!("integrity:{entry}"),
+            _ => m.name.clone(),
+        }
+    }
+
+    /// Attach what the list says about the entry behind a match: its severity
+    /// and, with `upgrade_risk`, the nearest safe version.
+    pub fn annotate(&self, m: &mut MatchRecord, upgrade_risk: bool) {
+        let key = Self::entry_key(m);
+        m.severity = self.severities.get(&key).copied();
+        m.list_source = self.sources.get(&key).cloned();
+        m.advisories = self.advisories.get(&key).cloned().unwrap_or_default();
+        if upgrade_risk {
+            let safe = self.safe_versions.get(&key).map_or(&[][..], Vec::as_slice);
+            let (version, risk) = upgrade_advice(&m.version, safe);
+            m.safe_version = version;
+            m.upgrade_risk = Some(risk.to_string());
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exact.is_empty()
+            && self.ranges.is_empty()
+            && self.names.is_empty()
+            && self.repos.is_empty()
+            && self.integrities.is_empty()
+            && self.patterns.is_empty()
+    }
+}
+
+/// An npm-style version range: `||` unions of comparator sets, hyphen ranges
+/// (`1.2.3 - 1.2.8`), x-ranges (`1.2.x`), and the usual `^`/`~`/`>=` operators.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct VersionRange {
+    /// The range as written in the list
+    pub raw: String,
+    alternatives: Vec<VersionReq>,
+}
+
+impl TryFrom<String> for VersionRange {
+    type Error = String;
+
+    fn try_from(raw: String) -> std::result::Result<Self, String> {
+        VersionRange::parse(&raw)
+    }
+}
+
+impl From<VersionRange> for String {
+    fn from(range: VersionRange) -> String {
+        range.raw
+    }
+}
+
+impl VersionRange {
+    pub fn parse(raw: &str) -> std::result::Result<Self, String> {
+        let mut alternatives = Vec::new();
+        for alt in raw.split("||") {
+            let alt = alt.trim();
+            if alt.is_empty() {
+                return Err("Empty alternative in '||' range".into());
+            }
+            let req = npm_comparators_to_semver(alt)?;
+            let parsed = VersionReq::parse(&req)
+                .map_err(|e| format!("Invalid version range '{alt}': {e}"))?;
+            alternatives.push(parsed);
+        }
+        Ok(VersionRange {
+            raw: raw.to_string(),
+            alternatives,
+        })
+    }
+
+    pub fn matches(&self, version: &Version) -> bool {
+        self.alternatives.iter().any(|req| req.matches(version))
+    }
+
+    /// True for a lone lower bound such as `>=1.0.0`.
+    pub fn is_floor(&self) -> bool {
+        match self.alternatives.as_slice() {
+            [req] => matches!(req.comparators.as_slice(), [c] if c.op == semver::Op::GreaterEq),
+            _ => false,
+        }
+    }
+}
+
+/// Translate one npm comparator set (no `||`) into `semver` crate syntax.
+///
+/// npm separates comparators with spaces and treats a bare version as exact,
+/// whereas `semver` uses commas and treats a bare version as a caret range.
+fn npm_comparators_to_semver(set: &str) -> std::result::Result<String, String> {
+    if let Some((lo, hi)) = set.split_once(" - ") {
+        let (lo, hi) = (lo.trim(), hi.trim());
+        if lo.is_empty() || hi.is_empty() || lo.contains(' ') || hi.contains(' ') {
+            return Err(format!("Invalid hyphen range '{set}'"));
+        }
+        return Ok(format!(">={lo}, <={hi}"));
+    }
+
+    let mut comparators = Vec::new();
+    let mut pending_op = String::new();
+    for token in set.split_whitespace() {
+        // Allow a detached operator, e.g. ">= 1.2.0".
+        if token.chars().all(|c| matches!(c, '<' | '>' | '=' | '^' | '~')) {
+            pending_op.push_str(token);
+            continue;
+        }
+        let token = format!("{}{}", std::mem::take(&mut pending_op), token);
+        let is_bare = token.starts_with(|c: char| c.is_ascii_digit() || c == 'v');
+        let is_wildcard = token.split('.').any(|p| matches!(p, "x" | "X" | "*"));
+        if is_bare && !is_wildcard {
+            comparators.push(format!("={}", token.trim_start_matches('v')));
+        } else {
+            comparators.push(token);
+        }
+    }
+    if !pending_op.is_empty() {
+        return Err(format!("Dangling operator '{pending_op}' in range '{set}'"));
+    }
+    Ok(comparators.join(", "))
+}
+
+/// True if a list version is a range rather than a single exact version.
+pub fn looks_like_range(version: &str) -> bool {
+    version.contains(['<', '>', '=', '^', '~', '|', ' '])
+        || version.split('.').any(|p| matches!(p, "x" | "X" | "*"))
+}
+
+/// A dep that matched the list.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct MatchRecord {
+    /// `exact`, `range`, `pattern`, `name`, `repo`, `basename`, or `confusable`;
+    /// `advisory-` prefixed for advisory lists (the binary adds `signature-mismatch`,
+    /// `integrity`, `integrity-prefix`, and `registry`)
+    pub match_type: String,
+    pub name: String,
+    pub version: String,
+    /// Declared by the root project
+    pub direct: bool,
+    /// The list entry that fired, for match types where it is not implied (e.g. range)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entry: Option<String>,
+    /// Floor entry a name match's version falls below; such matches do not fail the scan
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub below_floor: Option<String>,
+    /// Confusable skeleton shared by the installed and listed names (confusable matches)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skeleton: Option<String>,
+    /// Integrity the lockfile records, absent if none (signature-mismatch matches)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub integrity: Option<String>,
+    /// Integrity the known-good file expects (signature-mismatch matches)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_integrity: Option<String>,
+    /// Whether the version is still published, with --verify-reachable (absent if unknown)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reachable: Option<bool>,
+    /// From the entry's `severity:` annotation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub severity: Option<Severity>,
+    /// Nearest of the entry's `safe:` versions, with --show-upgrade-risk
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub safe_version: Option<String>,
+    /// Size of the move to `safe_version` (see `upgrade_advice`), with --show-upgrade-risk
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upgrade_risk: Option<String>,
+    /// --list file (or patch) the matched entry came from; the first one when several list it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub list_source: Option<String>,
+    /// OSV advisories behind the matched entry (for a name match, any entry for the name)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub advisories: Vec<Advisory>,
+    /// Tarball URL the lockfile resolved the package from (integrity and registry matches)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved: Option<String>,
+    /// License the `npm ls --long` tree or lockfile records for the package, as written
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
+    /// Chains of `name@version` from a top-level dependency down to the match
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub paths: Vec<Vec<String>>,
+    /// Paths dropped by --max-paths
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub omitted_paths: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<MatchContext>,
+    /// `scanned_at` of the earliest earlier report holding this match, or `now`
+    /// if none does (binary's --with-first-seen)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_seen: Option<String>,
+    /// `project`, or `global` for globally installed packages
+    pub origin: String,
+    /// Kind of dependency, when the input records it and it is not `prod`
+    #[serde(default, skip_serializing_if = "DepKind::is_prod")]
+    pub dependency_kind: DepKind,
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+impl MatchRecord {
+    pub fn new(match_type: &str, d: &Dep, entry: Option<String>) -> Self {
+        MatchRecord {
+            match_type: match_type.to_string(),
+            name: d.name.clone(),
+            version: d.version.clone(),
+            direct: d.direct,
+            entry,
+            below_floor: None,
+            skeleton: None,
+            integrity: None,
+            expected_integrity: None,
+            reachable: None,
+            severity: None,
+            safe_version: None,
+            upgrade_risk: None,
+            list_source: None,
+            advisories: Vec::new(),
+            resolved: None,
+            license: None,
+            paths: Vec::new(),
+            omitted_paths: 0,
+            context: None,
+            first_seen: None,
+            origin: "project".to_string(),
+            dependency_kind: d.kind,
+        }
+    }
+}
+
+/// Trimmed view of the tree around a match (first occurrence found).
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct MatchContext {
+    /// Root-most first, as name@version
+    pub ancestors: Vec<String>,
+    /// Immediate dependencies, as name@version
+    pub children: Vec<String>,
+}
+
+/// Settings that affect how list files are read.
+#[derive(Debug)]
+pub struct ParseOptions {
+    /// Entries with an earlier `expires:` date are skipped
+    pub as_of: NaiveDate,
+    /// Between name and version, '@' by default
+    pub separator: char,
+    /// Report skipped non-npm OSV packages on stderr
+    pub verbose: bool,
+}
+
+impl Default for ParseOptions {
+    /// Today's date, '@', quiet.
+    fn default() -> Self {
+        ParseOptions {
+            as_of: chrono::Utc::now().date_naive(),
+            separator: '@',
+            verbose: false,
+        }
+    }
+}
+
+/// A curated list compiled into the crate, for incidents where no list file
+/// is at hand.
+#[derive(Debug, Clone, Copy)]
+pub struct Preset {
+    pub name: &'static str,
+    /// Incident the entries cover and where they were taken from
+    pub origin: &'static str,
+    /// When the preset was last brought up to date (YYYY-MM-DD)
+    pub date: &'static str,
+    /// List text, in the line format
+    pub text: &'static str,
+}
+
+impl Preset {
+    /// The preset called `name`, if any.
+    pub fn find(name: &str) -> Option<&'static Preset> {
+        PRESETS.iter().find(|p| p.name == name)
+    }
+
+    /// Entries of the preset, parsed like any list with the default `@` separator.
+    pub fn entries(&self) -> Result<Vec<ListEntry>> {
+        parse_list_text(self.text, '@', &format!("preset:{}", self.name), false)
+    }
+}
+
+/// Presets in the order `--preset list` shows them.
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "chalk-debug-2025-09",
+        origin: "chalk, debug, and 18 other packages published from a phished maintainer account on 2025-09-08",
+        date: "2025-09-09",
+        text: include_str!("../presets/chalk-debug-2025-09.txt"),
+    },
+    Preset {
+        name: "event-stream-2018-11",
+        origin: "event-stream 3.3.6 and its flatmap-stream 0.1.1 payload, disclosed 2018-11-26",
+        date: "2018-11-27",
+        text: include_str!("../presets/event-stream-2018-11.txt"),
+    },
+    Preset {
+        name: "ua-parser-js-2021-10",
+        origin: "ua-parser-js versions published from a hijacked maintainer account on 2021-10-22",
+        date: "2021-10-23",
+        text: include_str!("../presets/ua-parser-js-2021-10.txt"),
+    },
+];
+
+/// Parse the compromised list file.
+///
+/// Rules:
+/// - Ignore blank lines and lines starting with '#'
+/// - Distinguish name-only vs exact (name@version or @scope/name@version)
+///   vs range (name@<npm range>, e.g. `chalk@>=5.6.0 <5.6.2`)
+/// - Trailing `key:value` annotations are supported: `expires:YYYY-MM-DD`
+///   drops the entry once `opts.as_of` is past that date
+pub fn parse_compromised_file(path: &Path, opts: &ParseOptions) -> Result<CompromisedList> {
+    let content = fs::read_to_string(path).map_err(|source| Error::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let source = path.display().to_string();
+    let entries = parse_list_text(&content, opts.separator, &source, opts.verbose)?;
+    Ok(CompromisedList::from_entries(entries, opts.as_of))
+}
+
+/// Entries of a list in either format: OSV JSON when it starts with `{` or
+/// `[`, the line format otherwise.
+pub fn parse_list_text(
+    content: &str,
+    separator: char,
+    source: &str,
+    verbose: bool,
+) -> Result<Vec<ListEntry>> {
+    if !content.trim_start().starts_with(['{', '[']) {
+        return parse_list_entries(content, separator, source);
+    }
+    let (entries, skipped, withdrawn) = parse_osv_entries(content, source)?;
+    if verbose && skipped > 0 {
+        eprintln!(
+            "Skipped {} non-npm affected package(s) in OSV list {}",
+            skipped, source
+        );
+    }
+    if verbose && withdrawn > 0 {
+        eprintln!("Skipped {} withdrawn advisory(ies) in OSV list {}", withdrawn, source);
+    }
+    Ok(entries)
+}
+
+/// Entries from OSV JSON: one vulnerability, an array of them, or an object
+/// with a `vulns` array. Each `affected` package with ecosystem `npm` becomes
+/// an exact entry per listed version and a range entry per SEMVER/ECOSYSTEM
+/// range, or a name entry when it lists neither; each entry records the
+/// vulnerability's `id` and `summary`. Vulnerabilities with a `withdrawn` date
+/// are skipped. Returns the entries, the number of affected packages skipped
+/// for another ecosystem, and the number of withdrawn vulnerabilities.
+pub fn parse_osv_entries(content: &str, source: &str) -> Result<(Vec<ListEntry>, usize, usize)> {
+    let v: Value =
+        serde_json::from_str(content).map_err(|e| Error::Osv(format!("not valid JSON: {e}")))?;
+    let vulns = match &v {
+        Value::Array(vulns) => vulns.as_slice(),
+        Value::Object(obj) => match obj.get("vulns").and_then(Value::as_array) {
+            Some(vulns) => vulns.as_slice(),
+            None => std::slice::from_ref(&v),
+        },
+        _ => return Err(Error::Osv("expected an object or an array".into())),
+    };
+    let mut entries = Vec::new();
+    let mut skipped = 0;
+    let mut withdrawn = 0;
+    for vuln in vulns {
+        let id = vuln.get("id").and_then(Value::as_str).unwrap_or("(no id)");
+        if vuln.get("withdrawn").is_some_and(|w| !w.is_null()) {
+            withdrawn += 1;
+            continue;
+        }
+        let advisory = Advisory {
+            id: id.to_string(),
+            summary: vuln.get("summary").and_then(Value::as_str).map(str::to_string),
+        };
+        for affected in vuln.get("affected").and_then(Value::as_array).into_iter().flatten() {
+            let package = affected.get("package");
+            let field = |key: &str| package.and_then(|p| p.get(key)).and_then(Value::as_str);
+            if field("ecosystem") != Some("npm") {
+                skipped += 1;
+                continue;
+            }
+            let name = field("name")
+                .ok_or_else(|| Error::Osv(format!("{id}: affected npm package has no name")))?;
+            let mut specs: Vec<String> = affected
+                .get("versions")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+                .map(|version| format!("{name}@{version}"))
+                .collect();
+            for range in affected.get("ranges").and_then(Value::as_array).into_iter().flatten() {
+                let kind = range.get("type").and_then(Value::as_str);
+                if !matches!(kind, Some("SEMVER" | "ECOSYSTEM")) {
+                    continue;
+                }
+                if let Some(range) = osv_events_to_range(range) {
+                    specs.push(format!("{name}@{range}"));
+                }
+            }
+            if specs.is_empty() {
+                specs.push(name.to_string());
+            }
+            for spec in specs {
+                let mut entry = parse_list_line(&spec, '@', source)
+                    .map_err(|reason| Error::Osv(format!("{id}: '{spec}' ({reason})")))?;
+                entry.advisory = Some(advisory.clone());
+                entries.push(entry);
+            }
+        }
+    }
+    Ok((entries, skipped, withdrawn))
+}
+
+/// An npm range for the `events` of an OSV range: each `introduced` opens an
+/// interval that the next `fixed` (exclusive) or `last_affected` (inclusive)
+/// closes. `introduced: "0"` means from the first version.
+fn osv_events_to_range(range: &Value) -> Option<String> {
+    let mut intervals = Vec::new();
+    let mut open: Option<String> = None;
+    for event in range.get("events").and_then(Value::as_array).into_iter().flatten() {
+        let at = |key: &str| event.get(key).and_then(Value::as_str);
+        if let Some(introduced) = at("introduced") {
+            open = Some(format!(">={}", if introduced == "0" { "0.0.0" } else { introduced }));
+        } else if let Some(lower) = open.take() {
+            if let Some(fixed) = at("fixed") {
+                intervals.push(format!("{lower} <{fixed}"));
+            } else if let Some(last) = at("last_affected") {
+                intervals.push(format!("{lower} <={last}"));
+            } else {
+                open = Some(lower);
+            }
+        }
+    }
+    intervals.extend(open);
+    (!intervals.is_empty()).then(|| intervals.join(" || "))
+}
+
+/// One validated list line.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct ListEntry {
+    /// The line without its annotations
+    pub spec: String,
+    pub entry: CompEntry,
+    pub expires: Option<NaiveDate>,
+    pub severity: Option<Severity>,
+    pub safe: Vec<String>,
+    /// List file (or URL, or patch) the line came from
+    pub source: String,
+    /// OSV advisory the entry was read from
+    pub advisory: Option<Advisory>,
+}
+
+/// The OSV vulnerability an entry was read from.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Advisory {
+    /// e.g. `GHSA-xxxx-xxxx-xxxx` or `MAL-2024-1234`
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+}
+
+/// Validate list text line by line, rejecting the first invalid entry.
+pub fn parse_list_entries(content: &str, separator: char, source: &str) -> Result<Vec<ListEntry>> {
+    let mut entries = Vec::new();
+    for (lineno, raw_line) in content.lines().enumerate() {
+        let line = raw_line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |reason: String| Error::InvalidEntry {
+            line: lineno + 1,
+            text: line.to_string(),
+            reason,
+        };
+        entries.push(parse_list_line(line, separator, source).map_err(invalid)?);
+    }
+    Ok(entries)
+}
+
+/// Parse one non-blank, non-comment list line.
+pub fn parse_list_line(
+    line: &str,
+    separator: char,
+    source: &str,
+) -> std::result::Result<ListEntry, String> {
+    let (spec, annotations) = split_entry_annotations(line);
+    let EntryAnnotations {
+        expires,
+        severity,
+        safe,
+    } = parse_entry_annotations(annotations)?;
+    let entry = parse_compromised_entry(spec, separator);
+    if let CompEntry::Invalid(reason) = entry {
+        return Err(reason);
+    }
+    Ok(ListEntry {
+        spec: spec.to_string(),
+        entry,
+        expires,
+        severity,
+        safe,
+        source: source.to_string(),
+        advisory: None,
+    })
+}
+
+impl CompromisedList {
+    /// Build the match sets from validated entries, skipping those expired before `as_of`.
+    pub fn from_entries(entries: Vec<ListEntry>, as_of: NaiveDate) -> CompromisedList {
+        let mut exact = HashSet::new();
+        let mut ranges: Vec<(String, VersionRange)> = Vec::new();
+        let mut names = HashSet::new();
+        let mut repos = Vec::new();
+        let mut integrities = Vec::new();
+        let mut patterns: Vec<PatternEntry> = Vec::new();
+        let mut severities: HashMap<String, Severity> = HashMap::new();
+        let mut safe_versions: HashMap<String, Vec<String>> = HashMap::new();
+        let mut sources: HashMap<String, String> = HashMap::new();
+        let mut advisories: HashMap<String, Vec<Advisory>> = HashMap::new();
+        let mut bare_names = HashSet::new();
+        let mut expired = Vec::new();
+
+        for ListEntry {
+            spec,
+            entry,
+            expires,
+            severity,
+            safe,
+            source,
+            advisory,
+        } in entries
+        {
+            if let Some(date) = expires.filter(|d| *d < as_of) {
+                expired.push(ExpiredEntry {
+                    entry: spec,
+                    expired_on: date,
+                });
+                continue;
+            }
+
+            // The entry as written, plus its name, which collects every entry's annotations.
+            let keys = match &entry {
+                CompEntry::Name(name) => vec![name.clone()],
+                CompEntry::Exact { name, version } => {
+                    vec![format!("{name}@{version}"), name.clone()]
+                }
+                CompEntry::Range { name, range } => {
+                    vec![format!("{name}@{}", range.raw), name.clone()]
+                }
+                CompEntry::Repo(url) => vec![format!("repo:{url}")],
+                CompEntry::Integrity(integrity) => vec![format!("integrity:{}", integrity.spec())],
+                CompEntry::Pattern(pattern) => vec![pattern.spec()],
+                CompEntry::Invalid(_) => unreachable!(),
+            };
+            for key in keys {
+                sources.entry(key.clone()).or_insert_with(|| source.clone());
+                if let Some(advisory) = &advisory {
+                    let listed = advisories.entry(key.clone()).or_default();
+                    if !listed.contains(advisory) {
+                        listed.push(advisory.clone());
+                    }
+                }
+                if let Some(severity) = severity {
+                    let slot = severities.entry(key.clone()).or_insert(severity);
+                    *slot = (*slot).max(severity);
+                }
+                if !safe.is_empty() {
+                    let versions = safe_versions.entry(key).or_default();
+                    versions.extend(safe.iter().cloned());
+                    versions.sort();
+                    versions.dedup();
+                }
+            }
+
+            match entry {
+                CompEntry::Name(name) => {
+                    bare_names.insert(name.clone());
+                    names.insert(name);
+                }
+                CompEntry::Exact { name, version } => {
+                    names.insert(name.clone());
+                    exact.insert((name, version));
+                }
+                // The same repo, hash, or range in several lists is kept once.
+                CompEntry::Repo(url) => {
+                    if !repos.contains(&url) {
+                        repos.push(url);
+                    }
+                }
+                CompEntry::Integrity(integrity) => {
+                    if !integrities.contains(&integrity) {
+                        integrities.push(integrity);
+                    }
+                }
+                CompEntry::Pattern(pattern) => {
+                    if !patterns.iter().any(|p| p.spec() == pattern.spec()) {
+                        patterns.push(pattern);
+                    }
+                }
+                CompEntry::Range { name, range } => {
+                    names.insert(name.clone());
+                    if !ranges.iter().any(|(n, r)| *n == name && r.raw == range.raw) {
+                        ranges.push((name, range));
+                    }
+                }
+                CompEntry::Invalid(_) => unreachable!(),
+            }
+        }
+
+        // A bare name entry flags every version, overriding any floor for that name.
+        let floors = ranges
+            .iter()
+            .filter(|(name, range)| range.is_floor() && !bare_names.contains(name))
+            .map(|(name, range)| (name.clone(), range.raw.clone()))
+            .collect();
+
+        CompromisedList {
+            exact,
+            ranges,
+            names,
+            bare_names,
+            repos,
+            integrities,
+            patterns,
+            floors,
+            severities,
+            safe_versions,
+            sources,
+            advisories,
+            expired,
+        }
+    }
+}
+
+/// Annotations recognized on list entries.
+#[derive(Debug, Default)]
+pub struct EntryAnnotations {
+    pub expires: Option<NaiveDate>,
+    pub severity: Option<Severity>,
+    pub safe: Vec<String>,
+}
+
+pub fn parse_entry_annotations(
+    annotations: Vec<(&str, &str)>,
+) -> std::result::Result<EntryAnnotations, String> {
+    let mut parsed = EntryAnnotations::default();
+    for (key, value) in annotations {
+        match key {
+            "expires" => {
+                let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                    .map_err(|_| format!("Invalid expiry date '{value}'"))?;
+                parsed.expires = Some(date);
+            }
+            "severity" => {
+                let severity =
+                    Severity::parse(value).ok_or_else(|| format!("Invalid severity '{value}'"))?;
+                parsed.severity = Some(severity);
+            }
+            "safe" => {
+                for version in value.split(',') {
+                    Version::parse(version)
+                        .map_err(|_| format!("Invalid safe version '{version}'"))?;
+                    parsed.safe.push(version.to_string());
+                }
+            }
+            _ => return Err(format!("Unknown annotation '{key}'")),
+        }
+    }
+    Ok(parsed)
+}
+
+/// Split trailing whitespace-separated `key:value` tokens off a list line.
+/// Keys are lowercase ASCII words, so range text such as `>=1.0.0 <2` is never taken.
+pub fn split_entry_annotations(line: &str) -> (&str, Vec<(&str, &str)>) {
+    let mut spec = line;
+    let mut annotations = Vec::new();
+    while let Some((rest, last)) = spec.rsplit_once(char::is_whitespace) {
+        match last.split_once(':') {
+            Some((key, value))
+                if !key.is_empty() && key.chars().all(|c| c.is_ascii_lowercase()) =>
+            {
+                annotations.push((key, value));
+                spec = rest.trim_end();
+            }
+            _ => break,
+        }
+    }
+    annotations.reverse();
+    (spec, annotations)
+}
+
+/// What a list line names.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub enum CompEntry {
+    Name(String),
+    Repo(String), // normalized URL
+    Integrity(IntegrityEntry),
+
+    Exact { name: String, version: String },
+    Range { name: String, range: VersionRange },
+    Pattern(PatternEntry),
+    Invalid(String),
+}
+
+/// Determine if a line is name-only, exact, a range, a `repo:` URL, an
+/// integrity hash (`integrity:<hash>`, a bare `<hash>`, or `name@version#<hash>`),
+/// or a name pattern (a name with `*`, `?`, or `[`, optionally with a version).
+/// Logic:
+/// - Find last '@'
+/// - If no '@' => name-only
+/// - If line starts with '@':
+///     - If total '@' count >= 2: candidate for exact (@scope/pkg@version)
+/// - Else if unscoped and has one '@': candidate for exact
+/// - Validate candidate version: must not contain '/'
+/// - If the version is a range (operators, spaces, `||`, x-ranges), parse it as one;
+///   otherwise it must start with [0-9A-Za-z]
+///
+/// With a separator other than '@' (e.g. `name|version`), the line is split at
+/// the first separator and '@' is only ever part of a scoped name.
+pub fn parse_compromised_entry(line: &str, separator: char) -> CompEntry {
+    if let Some(url) = line.strip_prefix("repo:") {
+        return match normalize_repo_url(url) {
+            Some(url) => CompEntry::Repo(url),
+            None => CompEntry::Invalid("Empty repository URL".into()),
+        };
+    }
+    if let Some((package, hash)) = line.split_once('#') {
+        let hash = match parse_integrity_entry(hash) {
+            Ok(hash) => hash,
+            Err(reason) => return CompEntry::Invalid(reason),
+        };
+        return match parse_compromised_entry(package, separator) {
+            CompEntry::Exact { name, version } => CompEntry::Integrity(IntegrityEntry {
+                hash,
+                package: Some((name, version)),
+            }),
+            CompEntry::Invalid(reason) => CompEntry::Invalid(reason),
+            _ => CompEntry::Invalid("An integrity hash may only follow name@version".into()),
+        };
+    }
+    if let Some(hash) = line.strip_prefix("integrity:") {
+        return match parse_integrity_entry(hash) {
+            Ok(hash) => CompEntry::Integrity(IntegrityEntry::new(hash)),
+            Err(reason) => CompEntry::Invalid(reason),
+        };
+    }
+    if is_bare_integrity(line) {
+        let hash = parse_integrity_entry(line).unwrap_or_default();
+        return CompEntry::Integrity(IntegrityEntry::new(hash));
+    }
+    match parse_package_entry(line, separator) {
+        CompEntry::Name(name) if is_name_pattern(&name) => PatternEntry::parse(&name, None),
+        CompEntry::Exact { name, version } if is_name_pattern(&name) => {
+            PatternEntry::parse(&name, Some(VersionConstraint::Exact(version)))
+        }
+        CompEntry::Range { name, range } if is_name_pattern(&name) => {
+            PatternEntry::parse(&name, Some(VersionConstraint::Range(range)))
+        }
+        entry => entry,
+    }
+}
+
+/// The name, exact, or range entry a package spec is, before patterns are told apart.
+fn parse_package_entry(line: &str, separator: char) -> CompEntry {
+    if separator != '@' {
+        return match line.split_once(separator) {
+            None => CompEntry::Name(line.to_string()),
+            Some((name_part, ver_part)) => classify_versioned_entry(name_part, ver_part),
+        };
+    }
+    if !line.contains('@') {
+        return CompEntry::Name(line.to_string());
+    }
+    let at_count = line.matches('@').count();
+
+    if line.starts_with('@') {
+        if at_count < 2 {
+            // e.g. @scope/name (no version)
+            return CompEntry::Name(line.to_string());
+        }
+    } else {
+        if at_count == 1 {
+            // unscoped exact candidate
+        } else if at_count > 1 {
+            return CompEntry::Invalid("Too many @ characters for unscoped package".into());
+        }
+    }
+
+    let last_at = line.rfind('@').unwrap();
+    classify_versioned_entry(&line[..last_at], &line[last_at + 1..])
+}
+
+/// Validate the name and version halves of an entry and classify it as exact or range.
+fn classify_versioned_entry(name_part: &str, ver_part: &str) -> CompEntry {
+    if name_part.is_empty() {
+        return CompEntry::Invalid("Empty name part".into());
+    }
+    if ver_part.is_empty() {
+        return CompEntry::Invalid("Empty version part".into());
+    }
+    if ver_part.contains('/') {
+        return CompEntry::Invalid("Version contains '/'".into());
+    }
+    if looks_like_range(ver_part) {
+        return match VersionRange::parse(ver_part) {
+            Ok(range) => CompEntry::Range {
+                name: name_part.to_string(),
+                range,
+            },
+            Err(reason) => CompEntry::Invalid(reason),
+        };
+    }
+    if !ver_part
+        .chars()
+        .next()
+        .map(|c| c.is_ascii_alphanumeric())
+        .unwrap_or(false)
+    {
+        return CompEntry::Invalid("Version does not start with alphanumeric".into());
+    }
+
+    CompEntry::Exact {
+        name: name_part.to_string(),
+        version: ver_part.to_string(),
+    }
+}
+
+/// Collect every dependency from an `npm ls --all --json` tree.
+pub fn collect_deps(root: &Value) -> Vec<Dep> {
+    collect_deps_in_scope(root, "all")
+}
+
+/// Collect dependencies from the npm JSON tree.
+/// Returns unique list of (name, version).
+/// A dependency is direct if any of its occurrences sits directly under the root.
+/// Nodes outside `scope` (`all`, `prod`, `dev`, `optional`, or `peer`) are
+/// skipped along with their whole subtree.
+pub fn collect_deps_in_scope(root: &Value, scope: &str) -> Vec<Dep> {
+    let mut acc = Vec::new();
+    let mut seen = HashMap::new();
+
+    if let Some(deps) = root.get("dependencies") {
+        if let Some(obj) = deps.as_object() {
+            for (name, node) in obj {
+                traverse(name, node, true, scope, &mut acc, &mut seen);
+            }
+        }
+    }
+    acc.sort();
+    acc
+}
+
+/// Key of the root node's dependency map in `npm ls --json` output.
+pub const DEFAULT_ROOT_KEY: &str = "dependencies";
+
+/// Move the dependency map at `key` of an npm JSON document to its
+/// `dependencies`, where [`collect_deps`] and the other tree walks look, for
+/// envelopes that keep it elsewhere. `key` is a top-level key, or a JSON
+/// pointer such as `/data/dependencies` for a nested one.
+pub fn use_root_key(root: &mut Value, key: &str) -> Result<()> {
+    if key == DEFAULT_ROOT_KEY {
+        return Ok(());
+    }
+    let found = if key.starts_with('/') {
+        root.pointer_mut(key).map(Value::take)
+    } else {
+        root.as_object_mut().and_then(|obj| obj.remove(key))
+    };
+    match found {
+        Some(deps @ Value::Object(_)) => {
+            if let Some(obj) = root.as_object_mut() {
+                obj.insert(DEFAULT_ROOT_KEY.to_string(), deps);
+            }
+            Ok(())
+        }
+        _ => {
+            let available: Vec<&str> = root
+                .as_object()
+                .into_iter()
+                .flatten()
+                .filter(|(_, v)| v.is_object())
+                .map(|(k, _)| k.as_str())
+                .collect();
+            Err(Error::RootKey {
+                key: key.to_string(),
+                available: if available.is_empty() {
+                    "none".to_string()
+                } else {
+                    available.join(", ")
+                },
+            })
+        }
+    }
+}
+
+/// `seen` borrows its keys from the tree, so a name@version is only copied
+/// the first time it is found, however often it recurs.
+fn traverse<'a>(
+    name: &'a str,
+    node: &'a Value,
+    direct: bool,
+    scope: &str,
+    acc: &mut Vec<Dep>,
+    seen: &mut HashMap<(&'a str, &'a str), usize>,
+) {
+    if !in_scan_scope(node, scope) {
+        return;
+    }
+    if let Some(version) = node.get("version").and_then(|v| v.as_str()) {
+        let kind = DepKind::of(node);
+        if let Some(&idx) = seen.get(&(name, version)) {
+            acc[idx].direct |= direct;
+            acc[idx].kind = acc[idx].kind.merge(kind);
+        } else {
+            seen.insert((name, version), acc.len());
+            acc.push(Dep {
+                name: name.to_string(),
+                version: version.to_string(),
+                direct,
+                kind,
+            });
+        }
+    }
+    if let Some(deps) = node.get("dependencies").and_then(|d| d.as_object()) {
+        for (child_name, child_node) in deps {
+            traverse(child_name, child_node, false, scope, acc, seen);
+        }
+    }
+}
+
+/// Whether a node belongs to `--scan-scope`, from npm's node flags: `dev`,
+/// `optional`, and `peer` mark packages reachable only through that kind of
+/// dependency, and `devOptional` ones reachable only through dev or optional.
+pub fn in_scan_scope(node: &Value, scope: &str) -> bool {
+    let flag = |key: &str| node.get(key).and_then(|f| f.as_bool()).unwrap_or(false);
+    match scope {
+        "prod" => !flag("dev"),
+        "dev" => flag("dev") || flag("devOptional"),
+        "optional" => flag("optional") || flag("devOptional"),
+        "peer" => flag("peer"),
+        _ => true,
+    }
+}
+
+/// Deps whose `repository` URL (from `npm ls --long`) equals a `repo:` entry or
+/// lies below it (`github.com/evil` covers `github.com/evil/pkg`), skipping deps
+/// already matched by a stronger rule unless `match_all_rules`. A weaker match
+/// for the same dep is left for `keep_strongest`. The first URL seen for a
+/// (name, version) is used.
+pub fn find_repo_matches(
+    tree: &Value,
+    deps: &[Dep],
+    lists: &CompromisedList,
+    matched: &[MatchRecord],
+    opts: &MatchOptions,
+) -> Vec<MatchRecord> {
+    fn walk(deps: Option<&Value>, urls: &mut HashMap<(String, String), String>) {
+        for (name, node) in deps.and_then(|d| d.as_object()).into_iter().flatten() {
+            let version = node.get("version").and_then(|v| v.as_str()).unwrap_or("");
+            // `repository` is either an object with a `url` or a shorthand string.
+            let url = node
+                .get("repository")
+                .and_then(|r| r.get("url").or(Some(r)))
+                .and_then(|u| u.as_str())
+                .and_then(normalize_repo_url);
+            if let Some(url) = url {
+                urls.entry((name.clone(), version.to_string())).or_insert(url);
+            }
+            walk(node.get("dependencies"), urls);
+        }
+    }
+    let mut urls = HashMap::new();
+    walk(tree.get("dependencies"), &mut urls);
+
+    let rank = match_precedence("repo");
+    let already: HashSet<(&str, &str)> = matched
+        .iter()
+        .filter(|m| !opts.match_all_rules && match_precedence(&m.match_type) < rank)
+        .map(|m| (m.name.as_str(), m.version.as_str()))
+        .collect();
+    let mut out = Vec::new();
+    for d in deps {
+        if already.contains(&(d.name.as_str(), d.version.as_str())) {
+            continue;
+        }
+        let Some(url) = urls.get(&(d.name.clone(), d.version.clone())) else {
+            continue;
+        };
+        let hit = lists.repos.iter().find(|pattern| {
+            url == *pattern
+                || url
+                    .strip_prefix(pattern.as_str())
+                    .is_some_and(|rest| rest.starts_with('/'))
+        });
+        if let Some(pattern) = hit {
+            let mut record = MatchRecord::new("repo", d, Some(pattern.clone()));
+            lists.annotate(&mut record, opts.show_upgrade_risk);
+            out.push(record);
+        }
+    }
+    out
+}
+
+/// Reduce a repository URL to lowercase `host/path`: drops `git+`, the scheme,
+/// any `user@`, a trailing `.git` or `#ref`, and expands `github:`/`gitlab:`/
+/// `bitbucket:` and bare `owner/repo` shorthands. `None` if nothing is left.
+pub fn normalize_repo_url(url: &str) -> Option<String> {
+    let mut url = url.trim();
+    url = url.split('#').next().unwrap_or(url);
+    url = url.strip_prefix("git+").unwrap_or(url);
+    let expanded;
+    if let Some((_, rest)) = url.split_once("://") {
+        url = rest;
+    } else if let Some((prefix, rest)) = url.split_once(':') {
+        let host = match prefix {
+            "github" => Some("github.com"),
+            "gitlab" => Some("gitlab.com"),
+            "bitbucket" => Some("bitbucket.org"),
+            _ => None,
+        };
+        expanded = match host {
+            Some(host) => format!("{host}/{rest}"),
+            // scp-like `git@github.com:owner/repo`
+            None => format!("{prefix}/{rest}"),
+        };
+        url = &expanded;
+    } else if url.matches('/').count() == 1 && !url.contains('.') {
+        expanded = format!("github.com/{url}");
+        url = &expanded;
+    }
+    if let Some((user, rest)) = url.split_once('@') {
+        if !user.contains('/') {
+            url = rest;
+        }
+    }
+    let url = url.trim_end_matches('/');
+    let url = url.strip_suffix(".git").unwrap_or(url);
+    (!url.is_empty()).then(|| url.to_ascii_lowercase())
+}
+
+/// A listed tarball hash, optionally pinned to the `name@version` it was
+/// published as (a re-publish under the same version can only be told apart by it).
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct IntegrityEntry {
+    /// `<alg>-<digest>`, the digest possibly a prefix
+    pub hash: String,
+    /// (name, version) the hash applies to; any package when absent
+    pub package: Option<(String, String)>,
+}
+
+impl IntegrityEntry {
+    pub fn new(hash: String) -> Self {
+        IntegrityEntry {
+            hash,
+            package: None,
+        }
+    }
+
+    /// The entry as written, without the `integrity:` prefix.
+    pub fn spec(&self) -> String {
+        match &self.package {
+            Some((name, version)) => format!("{name}@{version}#{}", self.hash),
+            None => self.hash.clone(),
+        }
+    }
+
+    /// Whether the listed digest is complete rather than a prefix.
+    pub fn is_full(&self) -> bool {
+        self.hash
+            .split_once('-')
+            .is_some_and(|(alg, digest)| integrity_digest_len(alg) == Some(digest.len()))
+    }
+
+    /// Whether an installed package with this SRI `integrity` (one or more
+    /// `<alg>-<digest>`) is the listed artifact.
+    pub fn matches(&self, name: &str, version: &str, integrity: &str) -> bool {
+        self.package
+            .as_ref()
+            .is_none_or(|(n, v)| n == name && v == version)
+            && integrity.split_whitespace().any(|hash| hash.starts_with(&self.hash))
+    }
+}
+
+/// Whether a listed name is a glob rather than a literal name.
+fn is_name_pattern(name: &str) -> bool {
+    name.contains(['*', '?', '['])
+}
+
+/// A glob over package names: `*` matches any run of characters (`/`
+/// included), `?` one character, and `[...]` one of a set.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct NamePattern {
+    /// The pattern as written in the list
+    pub raw: String,
+    compiled: glob::Pattern,
+}
+
+impl NamePattern {
+    pub fn parse(raw: &str) -> std::result::Result<Self, String> {
+        if raw.chars().all(|c| matches!(c, '*' | '?')) {
+            return Err(format!("Pattern '{raw}' would match every package"));
+        }
+        let compiled =
+            glob::Pattern::new(raw).map_err(|e| format!("Invalid pattern '{raw}': {}", e.msg))?;
+        Ok(NamePattern {
+            raw: raw.to_string(),
+            compiled,
+        })
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        self.compiled.matches(name)
+    }
+}
+
+impl TryFrom<String> for NamePattern {
+    type Error = String;
+
+    fn try_from(raw: String) -> std::result::Result<Self, String> {
+        NamePattern::parse(&raw)
+    }
+}
+
+impl From<NamePattern> for String {
+    fn from(pattern: NamePattern) -> String {
+        pattern.raw
+    }
+}
+
+/// The version half of a pattern entry.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub enum VersionConstraint {
+    Exact(String),
+    Range(VersionRange),
+}
+
+/// A name pattern entry such as `@ctrl/*` or `eslint-config-?@1.0.0`: packages
+/// whose name matches, and whose version satisfies the constraint if one is given.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PatternEntry {
+    pub pattern: NamePattern,
+    pub version: Option<VersionConstraint>,
+}
+
+impl PatternEntry {
+    fn parse(name: &str, version: Option<VersionConstraint>) -> CompEntry {
+        match NamePattern::parse(name) {
+            Ok(pattern) => CompEntry::Pattern(PatternEntry { pattern, version }),
+            Err(reason) => CompEntry::Invalid(reason),
+        }
+    }
+
+    /// The entry as written, with `@` before any version.
+    pub fn spec(&self) -> String {
+        match &self.version {
+            None => self.pattern.raw.clone(),
+            Some(VersionConstraint::Exact(version)) => format!("{}@{version}", self.pattern.raw),
+            Some(VersionConstraint::Range(range)) => format!("{}@{}", self.pattern.raw, range.raw),
+        }
+    }
+
+    /// Whether a dep is covered. Versions that are not semver only satisfy a
+    /// range written the same way, as with range entries.
+    pub fn matches(&self, d: &Dep) -> bool {
+        self.pattern.matches(&d.name)
+            && match &self.version {
+                None => true,
+                Some(VersionConstraint::Exact(version)) => {
+                    strip_build_metadata(version) == strip_build_metadata(&d.version)
+                }
+                Some(VersionConstraint::Range(range)) => match Version::parse(&d.version) {
+                    Ok(v) => range.matches(&v),
+                    Err(_) => range.raw == d.version,
+                },
+            }
+    }
+}
+
+/// A line that is a valid `<alg>-<digest>` without the `integrity:` prefix.
+/// Digests of only lowercase letters, digits, and `-` are also valid package
+/// names, so they need the prefix unless marked as truncated with `...`.
+fn is_bare_integrity(line: &str) -> bool {
+    parse_integrity_entry(line).is_ok_and(|hash| {
+        line.ends_with("...")
+            || hash.contains(|c: char| c.is_ascii_uppercase() || "+/=".contains(c))
+    })
+}
+
+/// Shortest digest prefix an `integrity:` entry may give.
+pub const MIN_INTEGRITY_PREFIX: usize = 16;
+
+/// Validate an `integrity:` entry, `<alg>-<digest>` as npm writes it in
+/// lockfiles: a known algorithm and base64 digest, which may be cut short
+/// (optionally marked with a trailing `...`) down to `MIN_INTEGRITY_PREFIX`.
+pub fn parse_integrity_entry(hash: &str) -> std::result::Result<String, String> {
+    let hash = hash.strip_suffix("...").unwrap_or(hash);
+    let (alg, digest) = hash
+        .split_once('-')
+        .ok_or_else(|| format!("Integrity '{hash}' is not `<alg>-<digest>`"))?;
+    let full = integrity_digest_len(alg)
+        .ok_or_else(|| format!("Unknown integrity algorithm '{alg}'"))?;
+    if !digest.chars().all(|c| c.is_ascii_alphanumeric() || "+/=".contains(c)) {
+        return Err(format!("Integrity digest '{digest}' is not base64"));
+    }
+    if digest.len() < MIN_INTEGRITY_PREFIX || digest.len() > full {
+        return Err(format!(
+            "Integrity digest must be {MIN_INTEGRITY_PREFIX} to {full} characters for {alg}"
+        ));
+    }
+    Ok(hash.to_string())
+}
+
+/// Length of a full base64 digest for an SRI algorithm npm uses.
+pub fn integrity_digest_len(alg: &str) -> Option<usize> {
+    match alg {
+        "sha1" => Some(28),
+        "sha256" => Some(44),
+        "sha384" => Some(64),
+        "sha512" => Some(88),
+        _ => None,
+    }
+}
+
+/// UTS #39 confusable skeleton of a name: lookalike characters such as
+/// Cyrillic 'а' map to the same form as their Latin counterparts.
+pub fn confusable_skeleton(name: &str) -> String {
+    unicode_security::skeleton(name).collect()
+}
+
+/// `pkg` for `@scope/pkg`; `None` for unscoped names.
+pub fn scoped_basename(name: &str) -> Option<&str> {
+    name.strip_prefix('@')?.split_once('/').map(|(_, base)| base)
+}
+
+/// First range entry for the dep's name that its version satisfies.
+///
+/// A version that is not valid semver falls back to a literal comparison
+/// with the range text and is recorded as `name@version` in `unparseable`.
+pub fn find_range_match<'a>(
+    d: &Dep,
+    lists: &'a CompromisedList,
+    unparseable: &mut BTreeSet<String>,
+) -> Option<&'a (String, VersionRange)> {
+    let mut candidates = lists.ranges.iter().filter(|(rname, _)| *rname == d.name).peekable();
+    candidates.peek()?;
+    match Version::parse(&d.version) {
+        Ok(v) => candidates.find(|(_, range)| range.matches(&v)),
+        Err(_) => {
+            unparseable.insert(format!("{}@{}", d.name, d.version));
+            candidates.find(|(_, range)| range.raw == d.version)
+        }
+    }
+}
+
+/// Every range entry for the dep's name that its version satisfies, in list
+/// order; see `find_range_match`.
+pub fn find_range_matches<'a>(
+    d: &Dep,
+    lists: &'a CompromisedList,
+    unparseable: &mut BTreeSet<String>,
+) -> Vec<&'a (String, VersionRange)> {
+    let candidates: Vec<_> = lists.ranges.iter().filter(|(rname, _)| *rname == d.name).collect();
+    if candidates.is_empty() {
+        return candidates;
+    }
+    match Version::parse(&d.version) {
+        Ok(v) => candidates.into_iter().filter(|(_, range)| range.matches(&v)).collect(),
+        Err(_) => {
+            unparseable.insert(format!("{}@{}", d.name, d.version));
+            candidates.into_iter().filter(|(_, range)| range.raw == d.version).collect()
+        }
+    }
+}
+
+/// Knobs that change how deps are compared against the lists; all off by default.
+#[derive(Debug, Default)]
+pub struct MatchOptions {
+    /// An unscoped name entry also matches `@scope/<name>`
+    pub match_basename: bool,
+    /// Names whose confusable skeleton equals a listed name's match
+    pub confusable_check: bool,
+    /// Compare `+build` metadata in exact matches
+    pub strict_build_metadata: bool,
+    /// Fill `safe_version` and `upgrade_risk` from `safe:` annotations
+    pub show_upgrade_risk: bool,
+    /// `@scope`s exempt from matches on the name alone
+    pub trusted_scopes: HashSet<String>,
+    /// One record per rule a dep satisfies, instead of only the strongest
+    pub match_all_rules: bool,
+}
+
+impl MatchOptions {
+    /// Whether `name` lies in one of `trusted_scopes`, exempt from name-level rules.
+    pub fn is_trusted(&self, name: &str) -> bool {
+        name.split_once('/')
+            .is_some_and(|(scope, _)| self.trusted_scopes.contains(scope))
+    }
+}
+
+/// Nearest safe version to move `installed` to: the lowest `safe` version above
+/// it, or else the highest one below it. The risk is `major`, `minor`, or
+/// `patch` for the first component that changes, `downgrade`, `none` when the
+/// installed version is itself listed safe, `unknown` when the installed
+/// version is not semver, and `unavailable` without safe versions.
+pub fn upgrade_advice(installed: &str, safe: &[String]) -> (Option<String>, &'static str) {
+    let mut candidates: Vec<Version> = safe.iter().filter_map(|v| Version::parse(v).ok()).collect();
+    candidates.sort();
+    let Some(highest) = candidates.last() else {
+        return (None, "unavailable");
+    };
+    let Ok(current) = Version::parse(installed) else {
+        return (Some(highest.to_string()), "unknown");
+    };
+    match candidates.iter().find(|v| **v >= current) {
+        Some(v) if *v == current => (Some(v.to_string()), "none"),
+        Some(v) => {
+            let risk = if v.major != current.major {
+                "major"
+            } else if v.minor != current.minor {
+                "minor"
+            } else {
+                "patch"
+            };
+            (Some(v.to_string()), risk)
+        }
+        None => (Some(highest.to_string()), "downgrade"),
+    }
+}
+
+/// `1.0.0+build.1` -> `1.0.0`; semver gives build metadata no precedence.
+pub fn strip_build_metadata(version: &str) -> &str {
+    version.split_once('+').map_or(version, |(v, _)| v)
+}
+
+/// Dep lists at least this long are matched on all cores, in contiguous chunks.
+pub const PARALLEL_MATCH_THRESHOLD: usize = 4096;
+
+/// List rules from strongest to weakest. By default a dep is reported once,
+/// under the strongest rule it satisfies; `match_all_rules` reports every one.
+/// `integrity` and `integrity-prefix` need lockfile data and `repo` the tree's
+/// metadata, so the binary adds those and applies `keep_strongest`.
+pub const MATCH_PRECEDENCE: [&str; 9] = [
+    "integrity",
+    "exact",
+    "integrity-prefix",
+    "range",
+    "repo",
+    "pattern",
+    "name",
+    "basename",
+    "confusable",
+];
+
+/// Rank of a list rule in `MATCH_PRECEDENCE`, strongest first; `None` for
+/// other match types (advisory matches and checks such as `registry`).
+pub fn match_precedence(match_type: &str) -> Option<usize> {
+    MATCH_PRECEDENCE.iter().position(|t| *t == match_type)
+}
+
+/// Keep, for each name@version of each origin, only the record of the strongest
+/// rule; records of other match types are kept as they are. Order is otherwise
+/// preserved.
+pub fn keep_strongest(matches: Vec<MatchRecord>) -> Vec<MatchRecord> {
+    let mut best: HashMap<(&str, &str, &str), (usize, usize)> = HashMap::new();
+    for (i, m) in matches.iter().enumerate() {
+        let Some(rank) = match_precedence(&m.match_type) else {
+            continue;
+        };
+        let slot = best.entry((&m.name, &m.version, &m.origin)).or_insert((rank, i));
+        if rank < slot.0 {
+            *slot = (rank, i);
+        }
+    }
+    let keep: HashSet<usize> = best.into_values().map(|(_, i)| i).collect();
+    matches
+        .into_iter()
+        .enumerate()
+        .filter(|(i, m)| keep.contains(i) || match_precedence(&m.match_type).is_none())
+        .map(|(_, m)| m)
+        .collect()
+}
+
+/// Match deps against the exact, range, pattern, and name rules, in `MATCH_PRECEDENCE`
+/// order: by default only the first rule a dep satisfies is reported.
+/// Exact matches ignore `+build` metadata unless `strict_build_metadata`; an exact match
+/// whose listed version differs from the installed one names the entry in `entry`.
+/// A name match below a `>=` floor entry is kept but marked `below_floor`.
+/// Pattern entries are only tried for names no literal entry lists.
+/// With `match_basename`, an unscoped name entry also matches `@scope/<name>` as a last resort.
+/// With `confusable_check`, so does any name whose confusable skeleton equals a listed name's.
+/// With `match_all_rules`, a dep is reported under every rule it satisfies, each
+/// range entry separately; a name match then comes from a bare name entry, or
+/// stands in when no version rule matched.
+pub fn find_matches_with(
+    deps: &[Dep],
+    lists: &CompromisedList,
+    opts: &MatchOptions,
+) -> Vec<MatchRecord> {
+    let mut unparseable = BTreeSet::new();
+    // (name, version without build) -> listed version; the smallest, if several builds are listed
+    let mut exact_builds: HashMap<(&str, &str), &str> = HashMap::new();
+    if !opts.strict_build_metadata {
+        for (name, version) in &lists.exact {
+            let key = (name.as_str(), strip_build_metadata(version));
+            let listed = exact_builds.entry(key).or_insert(version);
+            *listed = (*listed).min(version.as_str());
+        }
+    }
+    let skeletons: HashMap<String, &String> = if opts.confusable_check {
+        lists.names.iter().map(|n| (confusable_skeleton(n), n)).collect()
+    } else {
+        HashMap::new()
+    };
+    let matches = if deps.len() < PARALLEL_MATCH_THRESHOLD {
+        match_deps(deps, lists, opts, &exact_builds, &skeletons, &mut unparseable)
+    } else {
+        // Chunks keep their order, so the result is the same as a serial run.
+        let workers = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk_len = deps.len().div_ceil(workers);
+        let (exact_builds, skeletons) = (&exact_builds, &skeletons);
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = deps
+                .chunks(chunk_len)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        let mut seen = BTreeSet::new();
+                        let found =
+                            match_deps(chunk, lists, opts, exact_builds, skeletons, &mut seen);
+                        (found, seen)
+                    })
+                })
+                .collect();
+            let mut matches = Vec::new();
+            for handle in handles {
+                let (found, chunk_unparseable) = handle.join().expect("match worker panicked");
+                matches.extend(found);
+                unparseable.extend(chunk_unparseable);
+            }
+            matches
+        })
+    };
+    if !unparseable.is_empty() {
+        warn(&format!(
+            "{} version(s) could not be parsed as semver; compared literally against ranges: {}",
+            unparseable.len(),
+            unparseable.into_iter().collect::<Vec<_>>().join(", ")
+        ));
+    }
+    matches
+}
+
+/// The exact, range, pattern, and name rules of `find_matches_with` for one run of deps,
+/// with matches annotated from the list.
+fn match_deps(
+    deps: &[Dep],
+    lists: &CompromisedList,
+    opts: &MatchOptions,
+    exact_builds: &HashMap<(&str, &str), &str>,
+    skeletons: &HashMap<String, &String>,
+    unparseable: &mut BTreeSet<String>,
+) -> Vec<MatchRecord> {
+    let mut matches = Vec::new();
+    let all = opts.match_all_rules;
+    for d in deps {
+        let found = matches.len();
+        let done = |matches: &Vec<MatchRecord>| !all && matches.len() > found;
+        let listed = lists.names.contains(&d.name);
+        if !listed {
+            // Exact, range, and name rules all need the name listed, which most deps are not.
+        } else if lists.exact.contains(&(d.name.clone(), d.version.clone())) {
+            matches.push(MatchRecord::new("exact", d, None));
+        } else if let Some(listed) =
+            exact_builds.get(&(d.name.as_str(), strip_build_metadata(&d.version)))
+        {
+            let entry = format!("{}@{listed}", d.name);
+            matches.push(MatchRecord::new("exact", d, Some(entry)));
+        }
+        if done(&matches) {
+            continue;
+        }
+        let ranges = if !listed {
+            Vec::new()
+        } else if all {
+            find_range_matches(d, lists, unparseable)
+        } else {
+            find_range_match(d, lists, unparseable).into_iter().collect()
+        };
+        let version_hit = matches.len() > found || !ranges.is_empty();
+        for (rname, range) in ranges {
+            let entry = format!("{rname}@{}", range.raw);
+            matches.push(MatchRecord::new("range", d, Some(entry)));
+        }
+        if done(&matches) || opts.is_trusted(&d.name) {
+            // First-party scope: only version-confirmed matches apply.
+            continue;
+        }
+        // Patterns are only tried for names no literal entry covers.
+        if !listed || all {
+            for pattern in lists.patterns.iter().filter(|p| p.matches(d)) {
+                matches.push(MatchRecord::new("pattern", d, Some(pattern.spec())));
+                if !all {
+                    break;
+                }
+            }
+        }
+        if done(&matches) {
+            continue;
+        }
+        if listed && (!version_hit || lists.bare_names.contains(&d.name)) {
+            let mut record = MatchRecord::new("name", d, None);
+            // Not a range match, so a parseable version is below the floor.
+            if !version_hit && Version::parse(&d.version).is_ok() {
+                record.below_floor = lists.floors.get(&d.name).cloned();
+            }
+            matches.push(record);
+        }
+        if done(&matches) {
+            continue;
+        }
+        if let Some(base) = scoped_basename(&d.name)
+            .filter(|b| opts.match_basename && lists.names.contains(*b))
+        {
+            matches.push(MatchRecord::new("basename", d, Some(base.to_string())));
+        }
+        if done(&matches) {
+            continue;
+        }
+        if opts.confusable_check {
+            let skeleton = confusable_skeleton(&d.name);
+            if let Some(listed) = skeletons.get(&skeleton).filter(|l| ***l != d.name) {
+                let mut record = MatchRecord::new("confusable", d, Some(listed.to_string()));
+                record.skeleton = Some(skeleton);
+                matches.push(record);
+            }
+        }
+    }
+    for m in &mut matches {
+        lists.annotate(m, opts.show_upgrade_risk);
+    }
+    matches
+}
+
+/// Match deps against a list with the default `MatchOptions`.
+pub fn find_matches(deps: &[Dep], list: &CompromisedList) -> Vec<MatchRecord> {
+    find_matches_with(deps, list, &MatchOptions::default())
+}
+
+/// Match the packages in `npm ls --all --json` output against a list.
+pub fn scan(npm_json: &str, list: &CompromisedList) -> Result<Vec<MatchRecord>> {
+    let tree: Value = serde_json::from_str(npm_json)?;
+    Ok(find_matches(&collect_deps(&tree), list))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn dep(name: &str, version: &str) -> Dep {
+        Dep {
+            name: name.to_string(),
+            version: version.to_string(),
+            direct: false,
+            kind: DepKind::Prod,
+        }
+    }
+
+    fn summary(matches: &[MatchRecord]) -> Vec<(String, String, String)> {
+        matches
+            .iter()
+            .map(|m| (m.match_type.clone(), m.name.clone(), m.version.clone()))
+            .collect()
+    }
+
+    fn row(match_type: &str, name: &str, version: &str) -> (String, String, String) {
+        (match_type.to_string(), name.to_string(), version.to_string())
+    }
+
+    #[test]
+    fn scoped_entries_parse_as_name_exact_and_range() {
+        let list: CompromisedList =
+            "@evil/pkg\n@ctrl/tinycolor@4.1.1\n@nx/devkit@>=20.9.0 <20.10.0\n".parse().unwrap();
+        assert!(list.names.contains("@evil/pkg"));
+        assert!(list.exact.contains(&("@ctrl/tinycolor".to_string(), "4.1.1".to_string())));
+        assert_eq!(list.ranges.len(), 1);
+        assert_eq!(list.ranges[0].0, "@nx/devkit");
+    }
+
+    #[test]
+    fn pipe_separated_scoped_entries() {
+        let parse = |line: &str| parse_compromised_entry(line, '|');
+        assert!(matches!(
+            parse("@scope/pkg|1.2.3"),
+            CompEntry::Exact { name, version } if name == "@scope/pkg" && version == "1.2.3"
+        ));
+        assert!(matches!(parse("@scope/pkg"), CompEntry::Name(name) if name == "@scope/pkg"));
+        let CompEntry::Range { name, range } = parse("@scope/pkg|>=1.0.0 <2.0.0") else {
+            panic!("expected a range entry");
+        };
+        assert_eq!(name, "@scope/pkg");
+        assert_eq!(range.raw, ">=1.0.0 <2.0.0");
+        let CompEntry::Range { name, range } = parse("@scope/pkg|1.0.0 || >=2.0.0 <2.1.0") else {
+            panic!("expected a range entry");
+        };
+        assert_eq!(name, "@scope/pkg");
+        assert!(range.matches(&Version::parse("1.0.0").unwrap()));
+        assert!(range.matches(&Version::parse("2.0.3").unwrap()));
+        assert!(!range.matches(&Version::parse("1.5.0").unwrap()));
+    }
+
+    #[test]
+    fn scoped_deps_match_their_entries() {
+        let list: CompromisedList = "@evil/pkg\n@ctrl/tinycolor@4.1.1\n".parse().unwrap();
+        let deps = [
+            dep("@ctrl/tinycolor", "4.1.1"),
+            dep("@ctrl/tinycolor", "4.1.2"),
+            dep("@evil/pkg", "0.0.1"),
+            dep("pkg", "0.0.1"),
+        ];
+        assert_eq!(
+            summary(&find_matches(&deps, &list)),
+            [
+                row("exact", "@ctrl/tinycolor", "4.1.1"),
+                row("name", "@ctrl/tinycolor", "4.1.2"),
+                row("name", "@evil/pkg", "0.0.1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn union_hyphen_and_detached_operator_ranges() {
+        let hits = |raw: &str, versions: &[&str]| -> Vec<bool> {
+            let range = VersionRange::parse(raw).unwrap();
+            versions
+                .iter()
+                .map(|v| range.matches(&Version::parse(v).unwrap()))
+                .collect()
+        };
+        assert_eq!(
+            hits(
+                "1.2.3 || >=2.0.0 <2.1.0",
+                &["1.2.3", "1.3.0", "2.0.5", "2.1.0"]
+            ),
+            [true, false, true, false]
+        );
+        assert_eq!(
+            hits("1.2.3 - 2.3.4", &["1.2.2", "1.2.3", "2.3.4", "2.3.5"]),
+            [false, true, true, false]
+        );
+        assert_eq!(
+            hits("1.2 - 2.3", &["1.1.9", "1.2.0", "2.3.9", "2.4.0"]),
+            [false, true, true, false]
+        );
+        assert_eq!(
+            hits(">= 1.2.0 < 1.3.0", &["1.1.0", "1.2.0", "1.2.9", "1.3.0"]),
+            [false, true, true, false]
+        );
+
+        let err = |raw: &str| VersionRange::parse(raw).unwrap_err();
+        assert!(err("1.0.0 || ").starts_with("Empty alternative"));
+        assert!(err("|| 1.0.0").starts_with("Empty alternative"));
+        assert!(err("1.0.0 >=").starts_with("Dangling operator"));
+    }
+
+    #[test]
+    fn strongest_rule_wins_unless_all_rules_are_asked_for() {
+        let list: CompromisedList =
+            "pkg\npkg@1.2.3\npkg@>=1.0.0 <2.0.0\npkg@^1.2.0\n".parse().unwrap();
+        let deps = [dep("pkg", "1.2.3"), dep("pkg", "1.5.0"), dep("pkg", "3.0.0")];
+        assert_eq!(
+            summary(&find_matches(&deps, &list)),
+            [
+                row("exact", "pkg", "1.2.3"),
+                row("range", "pkg", "1.5.0"),
+                row("name", "pkg", "3.0.0"),
+            ]
+        );
+
+        let opts = MatchOptions {
+            match_all_rules: true,
+            ..Default::default()
+        };
+        let all = find_matches_with(&deps, &list, &opts);
+        assert_eq!(
+            summary(&all),
+            [
+                row("exact", "pkg", "1.2.3"),
+                row("range", "pkg", "1.2.3"),
+                row("range", "pkg", "1.2.3"),
+                row("name", "pkg", "1.2.3"),
+                row("range", "pkg", "1.5.0"),
+                row("range", "pkg", "1.5.0"),
+                row("name", "pkg", "1.5.0"),
+                row("name", "pkg", "3.0.0"),
+            ]
+        );
+
+        let mut shuffled = all;
+        shuffled.reverse();
+        let mut strongest = summary(&keep_strongest(shuffled));
+        strongest.reverse();
+        assert_eq!(strongest, summary(&find_matches(&deps, &list)));
+    }
+
+    #[test]
+    fn parallel_matching_keeps_dep_order() {
+        let list: CompromisedList = "pkg-7\npkg-700@1.0.0\npkg-7000@>=1.0.0\n".parse().unwrap();
+        let deps: Vec<Dep> = (0..2 * PARALLEL_MATCH_THRESHOLD)
+            .map(|i| dep(&format!("pkg-{}", i % 7001), "1.0.0"))
+            .collect();
+        let parallel = find_matches(&deps, &list);
+        let serial: Vec<MatchRecord> = deps
+            .chunks(PARALLEL_MATCH_THRESHOLD - 1)
+            .flat_map(|chunk| find_matches(chunk, &list))
+            .collect();
+        assert_eq!(summary(&parallel), summary(&serial));
+        assert_eq!(parallel.len(), 5);
+    }
+
+    #[test]
+    fn osv_advisories_are_carried_to_matches() {
+        let osv = json!([
+            {
+                "id": "MAL-2024-1",
+                "summary": "Malicious code in evil",
+                "affected": [
+                    {"package": {"ecosystem": "npm", "name": "evil"}, "versions": ["1.0.0"]},
+                    {"package": {"ecosystem": "PyPI", "name": "evil"}}
+                ]
+            },
+            {
+                "id": "GHSA-0000-0000-0000",
+                "withdrawn": "2024-05-01T00:00:00Z",
+                "affected": [{"package": {"ecosystem": "npm", "name": "left-pad"}}]
+            }
+        ]);
+        let (entries, skipped, withdrawn) =
+            parse_osv_entries(&osv.to_string(), "osv.json").unwrap();
+        assert_eq!((entries.len(), skipped, withdrawn), (1, 1, 1));
+
+        let list = CompromisedList::from_entries(entries, NaiveDate::MIN);
+        let matches = find_matches(&[dep("evil", "1.0.0"), dep("left-pad", "1.3.0")], &list);
+        assert_eq!(summary(&matches), [row("exact", "evil", "1.0.0")]);
+        let advisory = Advisory {
+            id: "MAL-2024-1".to_string(),
+            summary: Some("Malicious code in evil".to_string()),
+        };
+        assert_eq!(matches[0].advisories, [advisory]);
+    }
+
+    #[test]
+    fn patterns_match_names_that_no_literal_entry_covers() {
+        let list: CompromisedList =
+            "@ctrl/*@1.2.3\n*-patched\neslint-config-?\nleft-patched\n".parse().unwrap();
+        assert_eq!(list.patterns.len(), 3);
+        assert!(!list.names.contains("*-patched"));
+        let deps = [
+            dep("@ctrl/tinycolor", "1.2.3"),
+            dep("@ctrl/tinycolor", "1.2.4"),
+            dep("@acme/left-patched", "2.0.0"),
+            dep("left-patched", "1.0.0"),
+            dep("eslint-config-x", "1.0.0"),
+            dep("eslint-config-xy", "1.0.0"),
+        ];
+        let matches = find_matches(&deps, &list);
+        assert_eq!(
+            summary(&matches),
+            [
+                row("pattern", "@ctrl/tinycolor", "1.2.3"),
+                row("pattern", "@acme/left-patched", "2.0.0"),
+                row("name", "left-patched", "1.0.0"),
+                row("pattern", "eslint-config-x", "1.0.0"),
+            ]
+        );
+        assert_eq!(matches[0].entry.as_deref(), Some("@ctrl/*@1.2.3"));
+    }
+
+    #[test]
+    fn invalid_list_lines_report_their_line_number() {
+        for (text, line) in [
+            ("ok@1.0.0\nbad@1@2\n", 2),
+            ("# comment\n\npkg@\n", 3),
+            ("@scope/pkg@/1\n", 1),
+            ("pkg@1.0.0 severity:apocalyptic\n", 1),
+            ("integrity:sha512-tooShort\n", 1),
+            ("integrity:md5-AAAAAAAAAAAAAAAAAAAA\n", 1),
+            ("@ctrl/*\n*\n", 2),
+        ] {
+            match text.parse::<CompromisedList>() {
+                Err(Error::InvalidEntry { line: got, .. }) => assert_eq!(got, line, "{text:?}"),
+                other => panic!("{text:?}: expected an invalid entry, got {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn integrity_entries_accept_truncated_digests() {
+        let list: CompromisedList =
+            "integrity:sha512-AbCdEfGhIjKlMnOp...\nintegrity:sha1-AAAAAAAAAAAAAAAAAAAAAAAAAAA=\n"
+                .parse()
+                .unwrap();
+        let hashes: Vec<&str> = list.integrities.iter().map(|e| e.hash.as_str()).collect();
+        assert_eq!(hashes, ["sha512-AbCdEfGhIjKlMnOp", "sha1-AAAAAAAAAAAAAAAAAAAAAAAAAAA="]);
+        assert!(list.names.is_empty());
+    }
+
+    #[test]
+    fn integrity_entries_may_be_bare_or_pinned() {
+        let list: CompromisedList =
+            "sha512-AbCdEfGhIjKlMnOp\nsha1-lowercaseonlyname\nleft-pad@1.3.0#sha512-AbCdEfGhIjKlMnOp\n"
+                .parse()
+                .unwrap();
+        let specs: Vec<String> = list.integrities.iter().map(IntegrityEntry::spec).collect();
+        assert_eq!(specs, ["sha512-AbCdEfGhIjKlMnOp", "left-pad@1.3.0#sha512-AbCdEfGhIjKlMnOp"]);
+        assert!(list.names.contains("sha1-lowercaseonlyname"));
+        assert!(!list.names.contains("left-pad"));
+        assert!(list.integrities[1].matches("left-pad", "1.3.0", "sha512-AbCdEfGhIjKlMnOpQr"));
+        assert!(!list.integrities[1].matches("left-pad", "1.3.1", "sha512-AbCdEfGhIjKlMnOpQr"));
+    }
+
+    #[test]
+    fn missing_list_file_is_an_io_error() {
+        let err = CompromisedList::from_path("/nonexistent/compromised.txt").unwrap_err();
+        assert!(matches!(err, Error::Io { .. }));
+    }
+
+    #[test]
+    fn nested_trees_are_flattened_and_deduplicated() {
+        let tree = json!({
+            "name": "app",
+            "dependencies": {
+                "a": {
+                    "version": "1.0.0",
+                    "dependencies": {
+                        "b": {
+                            "version": "2.0.0",
+                            "dependencies": { "@s/c": { "version": "3.0.0" } }
+                        }
+                    }
+                },
+                "b": { "version": "2.0.0" },
+                "d": { "version": "4.0.0", "dependencies": { "@s/c": { "version": "3.0.0" } } }
+            }
+        });
+        let deps = collect_deps(&tree);
+        let flat: Vec<(&str, &str, bool)> = deps
+            .iter()
+            .map(|d| (d.name.as_str(), d.version.as_str(), d.direct))
+            .collect();
+        assert_eq!(
+            flat,
+            [
+                ("@s/c", "3.0.0", false),
+                ("a", "1.0.0", true),
+                ("b", "2.0.0", true),
+                ("d", "4.0.0", true),
+            ]
+        );
+    }
+
+    #[test]
+    fn root_key_selects_the_dependency_map() {
+        let mut tree = json!({ "data": { "packages": { "a": { "version": "1.0.0" } } } });
+        use_root_key(&mut tree, "/data/packages").unwrap();
+        assert_eq!(collect_deps(&tree)[0].name, "a");
+
+        let mut tree = json!({ "name": "app", "deps": { "a": { "version": "1.0.0" } } });
+        let err = use_root_key(&mut tree, "packages").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "No dependency map at root key 'packages' (object-valued keys: deps)"
+        );
+    }
+
+    #[test]
+    fn scan_finds_deeply_nested_matches() {
+        let list: CompromisedList = "deep@>=1.0.0 <2.0.0\n".parse().unwrap();
+        let npm_json = r#"{"dependencies": {"top": {"version": "1.0.0", "dependencies":
+            {"mid": {"version": "1.0.0", "dependencies": {"deep": {"version": "1.2.3"}}}}}}}"#;
+        let matches = scan(npm_json, &list).unwrap();
+        assert_eq!(summary(&matches), [row("range", "deep", "1.2.3")]);
+        assert!(!matches[0].direct);
+        assert!(matches!(scan("not json", &list), Err(Error::Json(_))));
+    }
+
+    #[test]
+    fn presets_parse_to_pinned_versions() {
+        for preset in PRESETS {
+            let entries = preset.entries().unwrap();
+            assert!(!entries.is_empty(), "{} is empty", preset.name);
+            assert!(
+                entries.iter().all(|e| matches!(e.entry, CompEntry::Exact { .. })),
+                "{} should pin versions",
+                preset.name
+            );
+            assert_eq!(Preset::find(preset.name).unwrap().name, preset.name);
+        }
+    }
+
+    #[test]
+    fn match_records_round_trip_through_json() {
+        let list: CompromisedList = "x@1.0.0 severity:high safe:1.0.1\n".parse().unwrap();
+        let matches = find_matches(&[dep("x", "1.0.0")], &list);
+        let text = serde_json::to_string(&matches).unwrap();
+        let back: Vec<MatchRecord> = serde_json::from_str(&text).unwrap();
+        assert_eq!(serde_json::to_string(&back).unwrap(), text);
+    }
+}
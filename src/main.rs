@@ -1,308 +1,7100 @@
 use anyhow::{anyhow, Context, Result};
+use chrono::{Datelike, NaiveDate};
 use clap::{ArgAction, Parser, ValueHint};
-use serde_json::Value;
-use std::collections::{BTreeSet, HashSet};
+use npm_compromised_scan::{
+    collect_deps, collect_deps_in_scope, find_matches_with, find_repo_matches, in_scan_scope,
+    keep_strongest, parse_compromised_entry, parse_compromised_file, parse_entry_annotations,
+    parse_list_line, parse_list_text, parse_osv_entries, scoped_basename, set_warning_handler,
+    split_entry_annotations, use_root_key, Advisory, CompEntry, CompromisedList, Dep, DepKind,
+    EntryAnnotations, IntegrityEntry, ListEntry, MatchContext, MatchOptions, MatchRecord,
+    ParseOptions, Preset, Severity, VersionRange, DEFAULT_ROOT_KEY, DEFAULT_SEVERITY, PRESETS,
+};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::Write as _;
 use std::fs;
-use std::io::{self, Read};
+use std::io::{self, IsTerminal, Read, Write as _};
 use std::path::PathBuf;
 use std::process::Command;
 
-#[derive(Parser, Debug)]
+mod output;
+
+use output::{diag, Palette, PhaseTimer};
+
+#[derive(Parser, Debug, Clone)]
 #[command(
     name = "npm-compromised-scan",
     version,
     about = "Compare npm dependency tree (npm ls --all --json) to a list of compromised packages."
 )]
 struct Cli {
-    /// Path to compromised list file (default: compromised.txt)
-    #[arg(short = 'l', long = "list", value_hint = ValueHint::FilePath, default_value = "compromised.txt")]
-    list_file: PathBuf,
+    /// Path to compromised list file (default: compromised.txt, unless --advisory, --preset, or --detect-integrity-collisions is given), or an http(s) or s3:// URL to download it from; repeat to merge several lists; matches always fail the scan
+    #[arg(short = 'l', long = "list", visible_alias = "deny-list", value_hint = ValueHint::FilePath, action = ArgAction::Append)]
+    list_files: Vec<PathBuf>,
+
+    /// Built-in curated list to scan against, merged with any --list; repeatable. `--preset list` shows the presets
+    #[arg(long = "preset", value_name = "NAME", action = ArgAction::Append, value_parser = parse_preset_name)]
+    presets: Vec<String>,
+
+    /// OSV advisory JSON file (one vulnerability or an array of them) to read entries from, alongside any --list; repeatable; matches fail the scan
+    #[arg(long = "advisory", value_name = "PATH", value_hint = ValueHint::FilePath, action = ArgAction::Append)]
+    advisories: Vec<PathBuf>,
+
+    /// Cache a --list URL in this directory, reusing it for --cache-ttl and when a download fails
+    #[arg(long = "cache-dir", value_hint = ValueHint::DirPath)]
+    cache_dir: Option<PathBuf>,
+
+    /// Seconds a cached --list download stays fresh
+    #[arg(long = "cache-ttl", value_name = "SECONDS", default_value_t = 3600, requires = "cache_dir")]
+    cache_ttl: u64,
+
+    /// Always download a --list URL, ignoring (but refreshing) the --cache-dir copy
+    #[arg(long = "no-cache", action = ArgAction::SetTrue)]
+    no_cache: bool,
+
+    /// Advisory list: matches are reported as `advisory-<type>` but do not fail the scan
+    #[arg(long = "advisory-list", value_hint = ValueHint::FilePath)]
+    advisory_list: Option<PathBuf>,
+
+    /// File of known-good `name@version -> <integrity>` lines; installed copies whose --package-lock integrity differs are flagged as `signature-mismatch`
+    #[arg(long = "known-good", value_name = "PATH", value_hint = ValueHint::FilePath, value_parser = parse_known_good_file, requires = "package_lock")]
+    known_good: Option<HashMap<String, String>>,
+
+    /// Registry URL that --package-lock `resolved` tarballs may come from; repeat to allow several. Packages resolved elsewhere are flagged as `registry`
+    #[arg(long = "allowed-registry", value_name = "URL", action = ArgAction::Append)]
+    allowed_registries: Vec<String>,
+
+    /// Flag --package-lock packages whose integrity is shared by a package of another name (a republished tarball) as `integrity-collision`; needs no list
+    #[arg(long = "detect-integrity-collisions", action = ArgAction::SetTrue, requires = "package_lock")]
+    detect_integrity_collisions: bool,
+
+    /// Waive matches for packages in this file (bare names or `name@version`, list syntax, optional `expires:` and trailing `reason:`); waived matches are reported as suppressed
+    #[arg(long = "ignore-file", visible_alias = "allow-file", value_hint = ValueHint::FilePath)]
+    ignore_file: Option<PathBuf>,
+
+    /// Warn about --ignore-file entries that expire within this many days
+    #[arg(long = "ignore-expiry-warning-days", value_name = "DAYS", default_value_t = 14, requires = "ignore_file")]
+    ignore_expiry_warning_days: i64,
+
+    /// Warn about --ignore-file entries that suppressed nothing
+    #[arg(long = "warn-unused-ignores", action = ArgAction::SetTrue, requires = "ignore_file")]
+    warn_unused_ignores: bool,
+
+    /// Baseline of accepted matches (from --write-baseline, or a --format json report): matches in it are reported as existing and only new ones fail the scan
+    #[arg(long = "baseline", value_name = "PATH", value_hint = ValueHint::FilePath)]
+    baseline: Option<PathBuf>,
+
+    /// Write the current matches (existing ones included) to this file as a baseline for --baseline
+    #[arg(long = "write-baseline", value_name = "PATH", value_hint = ValueHint::FilePath)]
+    write_baseline: Option<PathBuf>,
+
+    /// Also fail the scan on advisory-list matches
+    #[arg(long = "fail-on-advisory", action = ArgAction::SetTrue, requires = "advisory_list")]
+    fail_on_advisory: bool,
+
+    /// Apply a patch of `+entry` / `-entry` lines on top of --list before scanning
+    #[arg(long = "list-patch", value_hint = ValueHint::FilePath)]
+    list_patch: Option<PathBuf>,
+
+    /// Load the compromised list from a binary index built with --build-list-index instead of --list
+    #[arg(long = "list-index", value_hint = ValueHint::FilePath, conflicts_with_all = ["list_patch", "advisories", "presets"])]
+    list_index: Option<PathBuf>,
+
+    /// Compile --list (with --list-patch applied) into a binary index file and exit
+    #[arg(long = "build-list-index", value_hint = ValueHint::FilePath, conflicts_with = "list_index")]
+    build_list_index: Option<PathBuf>,
+
+    /// Provide an existing npm ls JSON file path, or '-' to read from stdin; repeat to merge several files. If omitted, runs `npm ls --all --json`.
+    #[arg(long = "npm-json", value_hint = ValueHint::FilePath, action = ArgAction::Append)]
+    npm_json: Vec<String>,
+
+    /// Load dependencies from a prebuilt binary index (see --build-index) instead of running npm
+    #[arg(long = "index", value_hint = ValueHint::FilePath, conflicts_with_all = ["npm_json", "require_cache"])]
+    index: Option<PathBuf>,
+
+    /// Compare two compromised list files and print added, removed, and changed entries, then exit
+    #[arg(long = "list-diff", num_args = 2, value_names = ["OLD", "NEW"], value_hint = ValueHint::FilePath)]
+    list_diff: Option<Vec<PathBuf>>,
+
+    /// Merge list files or http(s) URLs into one sorted, deduplicated canonical list, then exit
+    #[arg(long = "merge-lists", num_args = 1.., value_name = "SOURCE")]
+    merge_lists: Option<Vec<String>>,
+
+    /// With --merge-lists, precede each entry with a comment naming the sources it came from
+    #[arg(long = "provenance", action = ArgAction::SetTrue, requires = "merge_lists")]
+    provenance: bool,
+
+    /// Merge all SARIF files matching a glob into one SARIF 2.1.0 document and exit
+    #[arg(long = "merge-sarif", value_name = "GLOB")]
+    merge_sarif: Option<String>,
+
+    /// Compare the `# version:` header of --list with the ETag (or Last-Modified) of the list at URL, then exit
+    #[arg(long = "check-list-version", value_name = "URL", value_hint = ValueHint::Url)]
+    check_list_version: Option<String>,
+
+    /// Write the collected dependencies to a binary index file and exit
+    #[arg(long = "build-index", value_hint = ValueHint::FilePath, conflicts_with = "index")]
+    build_index: Option<PathBuf>,
+
+    /// Scan modules from a JSON dump of `require.cache` paths instead of the npm tree
+    #[arg(long = "require-cache", value_hint = ValueHint::FilePath, conflicts_with = "npm_json")]
+    require_cache: Option<PathBuf>,
+
+    /// Scan a generic JSON array of `{"name", "version", "direct"?}` objects from any resolver
+    #[arg(long = "deps-json", value_hint = ValueHint::FilePath, conflicts_with_all = ["npm_json", "index", "require_cache"])]
+    deps_json: Option<PathBuf>,
+
+    /// Scan the bundled dependencies inside an `npm pack` tarball (.tgz)
+    #[arg(long = "tarball", value_hint = ValueHint::FilePath, conflicts_with_all = ["npm_json", "index", "require_cache", "deps_json"])]
+    tarball: Option<PathBuf>,
+
+    /// Scan a package-lock.json (lockfile v1, v2, or v3) directly, without node_modules or npm; repeat to merge several lockfiles
+    #[arg(long = "package-lock", visible_alias = "lockfile", value_hint = ValueHint::FilePath, action = ArgAction::Append, conflicts_with_all = ["npm_json", "index", "require_cache", "deps_json", "tarball", "npm_query"])]
+    package_lock: Vec<PathBuf>,
 
-    /// Provide an existing npm ls JSON file path, or '-' to read from stdin. If omitted, runs `npm ls --all --json`.
-    #[arg(long = "npm-json", value_hint = ValueHint::FilePath)]
-    npm_json: Option<String>,
+    /// Parse several --npm-json or --package-lock inputs concurrently, on up to --jobs workers
+    #[arg(long = "parallel-parse", action = ArgAction::SetTrue)]
+    parallel_parse: bool,
 
-    /// Output format: text or json
-    #[arg(short = 'f', long = "format", default_value = "text", value_parser = ["text", "json"])]
+    /// Worker threads for --parallel-parse (default: the number of CPUs)
+    #[arg(long = "jobs", value_name = "N", value_parser = clap::value_parser!(u16).range(1..), requires = "parallel_parse")]
+    jobs: Option<u16>,
+
+    /// Scan a yarn.lock (classic v1 or Berry, detected from the contents) directly
+    #[arg(long = "yarn-lock", value_hint = ValueHint::FilePath, conflicts_with_all = ["npm_json", "index", "require_cache", "deps_json", "tarball", "npm_query", "package_lock"])]
+    yarn_lock: Option<PathBuf>,
+
+    /// Scan a pnpm-lock.yaml (lockfile 5.x, 6.x, or 9) directly
+    #[arg(long = "pnpm-lock", value_hint = ValueHint::FilePath, conflicts_with_all = ["npm_json", "index", "require_cache", "deps_json", "tarball", "npm_query", "package_lock", "yarn_lock"])]
+    pnpm_lock: Option<PathBuf>,
+
+    /// Walk node_modules on disk (default: ./node_modules), reading each installed package.json, and report where it disagrees with the npm ls tree
+    #[arg(long = "scan-node-modules", value_name = "DIR", num_args = 0..=1, default_missing_value = "node_modules", value_hint = ValueHint::DirPath, conflicts_with_all = ["index", "require_cache", "deps_json", "tarball", "package_lock", "yarn_lock", "pnpm_lock", "npm_query", "global"])]
+    scan_node_modules: Option<PathBuf>,
+
+    /// Scan each project directory (repeatable) in parallel and report merged and per-directory results
+    #[arg(long = "scan-dir", value_hint = ValueHint::DirPath, conflicts_with_all = ["npm_json", "index", "require_cache", "deps_json", "tarball", "package_lock", "yarn_lock", "pnpm_lock", "global", "no_run_npm", "known_good", "allowed_registries", "detect_integrity_collisions"], conflicts_with_all = SINGLE_SCAN_ONLY)]
+    scan_dir: Vec<PathBuf>,
+
+    /// Find every npm project (package.json) below a directory, skipping node_modules, and scan each one
+    #[arg(long = "recursive", value_name = "DIR", value_hint = ValueHint::DirPath, conflicts_with_all = ["npm_json", "index", "require_cache", "deps_json", "tarball", "package_lock", "yarn_lock", "pnpm_lock", "scan_dir", "global", "no_run_npm"], conflicts_with_all = SINGLE_SCAN_ONLY)]
+    recursive: Option<PathBuf>,
+
+    /// With --recursive, stop at the first project that cannot be scanned instead of reporting it and continuing
+    #[arg(long = "strict", action = ArgAction::SetTrue, requires = "recursive")]
+    strict: bool,
+
+    /// With --scan-dir, kill a directory's npm run after this many seconds and report it as timed out
+    #[arg(long = "per-dir-timeout", value_name = "SECS", requires = "scan_dir")]
+    per_dir_timeout: Option<u64>,
+
+    /// With --scan-dir, append one JSON line per directory to this file as soon as it finishes (see README)
+    #[arg(long = "stream-output", value_name = "PATH", value_hint = ValueHint::FilePath, requires = "scan_dir")]
+    stream_output: Option<PathBuf>,
+
+    /// Directory of earlier --format json reports (e.g. --report files): mark each match with the `scanned_at` of the earliest report that has it, or `now`
+    #[arg(long = "with-first-seen", value_name = "DIR", value_hint = ValueHint::DirPath)]
+    with_first_seen: Option<PathBuf>,
+
+    /// Match against every dated list file (YYYY-MM-DD in the name) in a directory and report when each match was first flagged
+    #[arg(long = "list-history", value_hint = ValueHint::DirPath)]
+    list_history: Option<PathBuf>,
+
+    /// Output format: text, json, ndjson (one match per line), ndjson-with-meta (a `meta` line, then `match` lines), sarif (SARIF 2.1.0 for code scanning), github (Actions workflow annotations), cyclonedx-vex, junit, badge (SVG), dot (Graphviz graph of paths to matches), or bin (compact binary, see --decode-bin)
+    #[arg(short = 'f', long = "format", default_value = "text", value_parser = ["text", "json", "ndjson", "ndjson-with-meta", "sarif", "github", "cyclonedx-vex", "junit", "badge", "dot", "bin"])]
     format: String,
 
+    /// Print a `--format bin` result file (or '-' for stdin) as JSON and exit
+    #[arg(long = "decode-bin", value_name = "FILE", value_hint = ValueHint::FilePath)]
+    decode_bin: Option<PathBuf>,
+
+    /// Render the report with a TinyTemplate file instead of --format (see README for variables)
+    #[arg(long = "template", value_hint = ValueHint::FilePath)]
+    template: Option<PathBuf>,
+
+    /// Write the given CycloneDX SBOM with the matches attached to its components, instead of a report
+    #[arg(long = "annotate-sbom", value_hint = ValueHint::FilePath, conflicts_with_all = ["template", "redact_versions"])]
+    annotate_sbom: Option<PathBuf>,
+
+    /// `name` of the testsuite in --format junit
+    #[arg(long = "junit-suite-name", default_value = "npm-compromised-scan")]
+    junit_suite_name: String,
+
+    /// `classname` of every testcase in --format junit
+    #[arg(long = "junit-classname", default_value = "npm.dependencies")]
+    junit_classname: String,
+
+    /// Put every match in one failing --format junit testsuite, instead of an `exact` suite of failures and a `name` suite of skipped testcases
+    #[arg(long = "junit-single-suite", action = ArgAction::SetTrue)]
+    junit_single_suite: bool,
+
+    /// File that --format sarif results point at, relative to the repository root
+    #[arg(long = "artifact-path", value_name = "PATH", default_value = "package.json")]
+    artifact_path: String,
+
+    /// File that --format github annotations point at (default: the single --package-lock, --yarn-lock, or --pnpm-lock), relative to the repository root
+    #[arg(long = "annotate-file", value_name = "PATH", value_hint = ValueHint::FilePath)]
+    annotate_file: Option<PathBuf>,
+
+    /// Write the report to a file instead of stdout
+    #[arg(short = 'o', long = "output", value_hint = ValueHint::FilePath)]
+    output: Option<PathBuf>,
+
+    /// Also write the `--format json` report to this file, whatever --format prints
+    #[arg(long = "report", value_name = "PATH", value_hint = ValueHint::FilePath)]
+    report: Option<PathBuf>,
+
     /// Exit code to use when any matches are found
     #[arg(long = "fail-exit-code", default_value_t = 42)]
     fail_exit_code: i32,
 
+    /// Which matches fail the scan: exact (version-confirmed: exact, range, signature-mismatch), name (the rest), any, or none; the others only warn
+    #[arg(long = "fail-on", default_value = "any", value_parser = ["exact", "name", "any", "none"])]
+    fail_on: String,
+
+    /// Exit code when the only gating matches are ones --fail-on leaves as warnings (0 if unset)
+    #[arg(long = "warn-exit-code", value_name = "CODE")]
+    warn_exit_code: Option<i32>,
+
+    /// Only exit with the fail code for matches that are direct dependencies; transitive matches are reported only
+    #[arg(long = "fail-on-direct-only", action = ArgAction::SetTrue)]
+    fail_on_direct_only: bool,
+
+    /// Attach each match's ancestors and immediate children to JSON output
+    #[arg(long = "include-context", action = ArgAction::SetTrue)]
+    include_context: bool,
+
+    /// Warn about matches whose license is missing or unknown (requests `npm ls --long` for license metadata)
+    #[arg(long = "require-license", action = ArgAction::SetTrue)]
+    require_license: bool,
+
+    /// Keep at most N dependency paths per match (all by default)
+    #[arg(long = "max-paths", value_name = "N")]
+    max_paths: Option<usize>,
+
+    /// Show only the first N matches (sorted by name, version, and match type) in the output; counts and the exit code still cover every match
+    #[arg(long = "sample", value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    sample: Option<u64>,
+
+    /// Show the nearest `safe:` version for each match and whether reaching it is a major, minor, or patch bump
+    #[arg(long = "show-upgrade-risk", action = ArgAction::SetTrue)]
+    show_upgrade_risk: bool,
+
+    /// Print statistics about the loaded npm tree to stderr (as JSON with --format json)
+    #[arg(long = "tree-stats", action = ArgAction::SetTrue)]
+    tree_stats: bool,
+
+    /// Report only the most severe, most specific match for each package name
+    #[arg(long = "collapse-per-package", action = ArgAction::SetTrue)]
+    collapse_per_package: bool,
+
+    /// Also match unscoped name entries against the basename of scoped packages (`foo` matches `@scope/foo`)
+    #[arg(long = "match-basename", action = ArgAction::SetTrue)]
+    match_basename: bool,
+
+    /// Compare `+build` metadata in exact matches, so `foo@1.0.0+evil` matches only that build (ignored by default)
+    #[arg(long = "strict-build-metadata", action = ArgAction::SetTrue)]
+    strict_build_metadata: bool,
+
+    /// Report a package once per list rule it matches, not only under the strongest rule
+    #[arg(long = "match-all-rules", action = ArgAction::SetTrue)]
+    match_all_rules: bool,
+
+    /// Print to stderr why the exit code was chosen (also when exiting 0 with --verbose)
+    #[arg(long = "explain-exit", action = ArgAction::SetTrue)]
+    explain_exit: bool,
+
+    /// Only exit with the fail code when more than N distinct package names match
+    #[arg(long = "fail-on-distinct", value_name = "N")]
+    fail_on_distinct: Option<usize>,
+
+    /// Flag installed names whose Unicode confusable skeleton equals a listed name's (homoglyph lookalikes)
+    #[arg(long = "confusable-check", action = ArgAction::SetTrue)]
+    confusable_check: bool,
+
+    /// File of first-party scopes (`@acme`, one per line) exempt from name, pattern, basename, and confusable matches
+    #[arg(long = "trusted-scopes-file", value_name = "PATH", value_hint = ValueHint::FilePath, value_parser = parse_trusted_scopes_file)]
+    trusted_scopes: Option<HashSet<String>>,
+
+    /// Ask the registry (`npm view`) whether each matched version can still be installed
+    #[arg(long = "verify-reachable", action = ArgAction::SetTrue)]
+    verify_reachable: bool,
+
+    /// Do not fail on matches whose version is no longer on the registry (requires --verify-reachable)
+    #[arg(long = "skip-unreachable", action = ArgAction::SetTrue, requires = "verify_reachable")]
+    skip_unreachable: bool,
+
+    /// Drop matches installed fewer than N times in the npm tree or --package-lock from the report and the exit code
+    #[arg(long = "min-occurrences", value_name = "N", conflicts_with_all = ["index", "require_cache", "deps_json", "tarball", "yarn_lock", "pnpm_lock", "scan_node_modules", "npm_query"])]
+    min_occurrences: Option<usize>,
+
+    /// Keep exact, integrity, and signature-mismatch matches regardless of --min-occurrences
+    #[arg(long = "occurrences-exact-exempt", action = ArgAction::SetTrue, requires = "min_occurrences")]
+    occurrences_exact_exempt: bool,
+
+    /// Report packages installed at two or more distinct versions
+    #[arg(long = "report-duplicates", action = ArgAction::SetTrue)]
+    report_duplicates: bool,
+
+    /// Exit with the fail code if any duplicate packages are found (implies --report-duplicates)
+    #[arg(long = "fail-on-duplicates", action = ArgAction::SetTrue)]
+    fail_on_duplicates: bool,
+
+    /// Exit with the code mapped to the highest `severity:` among failing matches (see --severity-exit-map)
+    #[arg(long = "fail-code-from-severity", action = ArgAction::SetTrue, conflicts_with = "exit_bitmask")]
+    fail_code_from_severity: bool,
+
+    /// Exit codes per severity for --fail-code-from-severity; severities left out use --fail-exit-code
+    #[arg(long = "severity-exit-map", value_name = "MAP", default_value = "critical=40,high=30,medium=20,low=10", value_parser = parse_severity_exit_map)]
+    severity_exit_map: BTreeMap<Severity, i32>,
+
+    /// Shell command that receives the JSON result on stdin; its exit code becomes the scan's (see README)
+    #[arg(long = "exit-hook", value_name = "CMD")]
+    exit_hook: Option<String>,
+
+    /// Encode which match categories fired as bits of the exit code (see README) instead of --fail-exit-code
+    #[arg(long = "exit-bitmask", action = ArgAction::SetTrue)]
+    exit_bitmask: bool,
+
+    /// Read the flat `npm query '*' --json` array (from --npm-json, or by running it) instead of the nested npm ls tree
+    #[arg(long = "npm-query", action = ArgAction::SetTrue)]
+    npm_query: bool,
+
+    /// Top-level key of the npm JSON that holds the root dependency map, or a JSON pointer such as /data/dependencies
+    #[arg(long = "root-key", default_value = DEFAULT_ROOT_KEY, conflicts_with = "npm_query")]
+    root_key: String,
+
+    /// Only follow dependencies of this type when walking the tree (uses npm's dev/optional/peer node flags)
+    #[arg(long = "scan-scope", default_value = "all", value_parser = ["all", "prod", "dev", "optional", "peer"])]
+    scan_scope: String,
+
+    /// Dependency types whose matches do not fail the scan (reported as `omitted_matches`); repeat for several. Passed on to `npm ls`, which then leaves them out
+    #[arg(long = "omit", value_name = "TYPE", value_parser = ["dev", "optional", "peer"], action = ArgAction::Append)]
+    omit: Vec<String>,
+
+    /// Only scan direct dependencies of the root project
+    #[arg(long = "top-level-only", action = ArgAction::SetTrue)]
+    top_level_only: bool,
+
+    /// Skip resolving the tree when package.json declares no listed name (requires --top-level-only)
+    #[arg(long = "prefilter-manifest", action = ArgAction::SetTrue, requires = "top_level_only")]
+    prefilter_manifest: bool,
+
+    /// Manifest read by --prefilter-manifest
+    #[arg(long = "package-json", value_hint = ValueHint::FilePath, default_value = "package.json")]
+    package_json: PathBuf,
+
+    /// Scan globally installed packages (`npm ls -g --all --json`) instead of the current project
+    #[arg(long = "global", action = ArgAction::SetTrue)]
+    global: bool,
+
+    /// Also scan globally installed packages (`npm ls -g --all --json`) and merge them into the project's report
+    #[arg(long = "include-global", action = ArgAction::SetTrue, conflicts_with_all = ["global", "scan_dir", "recursive", "npm_query"])]
+    include_global: bool,
+
+    /// Also scan the packages npx has installed in its cache (`_npx` in the npm cache) and merge them into the report
+    #[arg(long = "npx-cache", action = ArgAction::SetTrue, conflicts_with_all = ["scan_dir", "recursive"])]
+    npx_cache: bool,
+
+    /// npm cache directory holding the npx cache (default: $npm_config_cache, else ~/.npm, or %LocalAppData%\npm-cache on Windows)
+    #[arg(long = "npm-cache-dir", value_name = "DIR", value_hint = ValueHint::DirPath, requires = "npx_cache")]
+    npm_cache_dir: Option<PathBuf>,
+
+    /// Skip the structural check that --npm-json input looks like npm output
+    #[arg(long = "skip-validation", action = ArgAction::SetTrue)]
+    skip_validation: bool,
+
+    /// Error instead of reporting clean when npm failed and fewer than --min-deps packages were read
+    #[arg(long = "fail-on-incomplete-tree", action = ArgAction::SetTrue)]
+    fail_on_incomplete_tree: bool,
+
+    /// Exit with code 43 instead of scanning when the npm ls tree has missing or versionless nodes or reports problems
+    #[arg(long = "strict-tree", action = ArgAction::SetTrue)]
+    strict_tree: bool,
+
+    /// Fail the run with exit code 44 if any warning was printed (npm exiting non-zero, unparseable versions, unscanned tree nodes, ...), listing them at the end
+    #[arg(long = "warnings-as-errors", action = ArgAction::SetTrue)]
+    warnings_as_errors: bool,
+
+    /// Dependency count below which a failed npm run is considered incomplete
+    #[arg(long = "min-deps", value_name = "N", default_value_t = 1, requires = "fail_on_incomplete_tree")]
+    min_deps: usize,
+
     /// Suppress running npm (error if no JSON source is provided)
     #[arg(long = "no-run-npm", action = ArgAction::SetTrue)]
     no_run_npm: bool,
-}
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
-struct Dep {
-    name: String,
-    version: String,
-}
+    /// Show the npm command and ask before running it; without a terminal, fail unless --yes is given
+    #[arg(long = "confirm-npm", action = ArgAction::SetTrue, conflicts_with = "no_run_npm")]
+    confirm_npm: bool,
+
+    /// Answer yes to --confirm-npm, for non-interactive runs
+    #[arg(short = 'y', long = "yes", action = ArgAction::SetTrue, requires = "confirm_npm")]
+    yes: bool,
+
+    /// Re-run `npm ls` up to N times while it reports no dependencies but ./node_modules exists
+    #[arg(long = "retry-on-empty", value_name = "N", default_value_t = 0, conflicts_with_all = ["npm_json", "no_run_npm"])]
+    retry_on_empty: u32,
+
+    /// Error if the compromised list contains no entries (default: warn only)
+    #[arg(long = "require-nonempty-list", action = ArgAction::SetTrue)]
+    require_nonempty_list: bool,
+
+    /// Also send each match to the local syslog daemon
+    #[arg(long = "syslog", action = ArgAction::SetTrue)]
+    syslog: bool,
+
+    /// Syslog facility for --syslog (e.g. user, daemon, local0)
+    #[arg(long = "syslog-facility", default_value = "user", value_parser = parse_syslog_facility)]
+    syslog_facility: syslog::Facility,
+
+    /// Character separating name and version in list entries (e.g. '|' for `name|version`)
+    #[arg(long = "list-separator", default_value_t = '@')]
+    list_separator: char,
+
+    /// Evaluate `expires:` dates in the list relative to this date (YYYY-MM-DD) instead of today
+    #[arg(long = "as-of", value_name = "DATE")]
+    as_of: Option<NaiveDate>,
+
+    /// Print diagnostics (e.g. expired list entries, the dependency source, list entry counts, and phase timings) to stderr
+    #[arg(short = 'v', long = "verbose", action = ArgAction::SetTrue)]
+    verbose: bool,
+
+    /// Print only matches and errors: no warnings, notes, clean message, or summary line; the exit code carries the result
+    #[arg(short = 'q', long = "quiet", action = ArgAction::SetTrue, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Color text output: auto (when stdout is a terminal and NO_COLOR is unset), always, or never
+    #[arg(long = "color", default_value = "auto", value_parser = ["auto", "always", "never"])]
+    color: String,
+
+    /// Print a stable fingerprint of the match set (type, name, version) for change detection
+    #[arg(long = "result-hash", action = ArgAction::SetTrue)]
+    result_hash: bool,
+
+    /// In JSON output, echo only list entries for package names present in the tree
+    #[arg(long = "echo-relevant-only", action = ArgAction::SetTrue)]
+    echo_relevant_only: bool,
 
-#[derive(Debug)]
-struct Lists {
-    exact: HashSet<(String, String)>, // (name, version)
-    names: HashSet<String>,           // name only
+    /// Replace version strings in output with a placeholder (x.x.x) and omit the compromised_exact echo
+    #[arg(long = "redact-versions", action = ArgAction::SetTrue)]
+    redact_versions: bool,
 }
 
+/// Prefix of the `match_type` of matches from `--advisory-list`.
+const ADVISORY_PREFIX: &str = "advisory-";
+
+/// List read when no --list, --advisory, or --preset is given.
+const DEFAULT_LIST: &str = "compromised.txt";
+
+/// Placeholder printed instead of real versions when `--redact-versions` is set.
+const REDACTED_VERSION: &str = "x.x.x";
+
+/// Version recorded for a dependency whose package.json could not be read.
+const UNKNOWN_VERSION: &str = "unknown";
+
+/// Exit code of `--strict-tree` on a degraded npm tree.
+const INCOMPLETE_TREE_EXIT_CODE: i32 = 43;
+
+/// Flags of the single-project report that --scan-dir and --recursive do not apply.
+const SINGLE_SCAN_ONLY: [&str; 33] = [
+    "write_baseline",
+    "with_first_seen",
+    "template",
+    "annotate_sbom",
+    "junit_suite_name",
+    "junit_classname",
+    "junit_single_suite",
+    "artifact_path",
+    "annotate_file",
+    "report",
+    "warn_exit_code",
+    "warn_unused_ignores",
+    "include_context",
+    "require_license",
+    "max_paths",
+    "sample",
+    "tree_stats",
+    "collapse_per_package",
+    "explain_exit",
+    "fail_on_distinct",
+    "verify_reachable",
+    "report_duplicates",
+    "fail_on_duplicates",
+    "fail_code_from_severity",
+    "severity_exit_map",
+    "exit_hook",
+    "exit_bitmask",
+    "prefilter_manifest",
+    "strict_tree",
+    "syslog",
+    "result_hash",
+    "echo_relevant_only",
+    "retry_on_empty",
+];
+
 #[derive(Debug, serde::Serialize)]
-struct MatchRecord {
-    match_type: String, // "exact" or "name"
+struct DuplicateRecord {
     name: String,
-    version: String,
+    versions: Vec<String>,
 }
 
 fn main() -> Result<()> {
-    let cli = Cli::parse();
-
-    let lists = parse_compromised_file(&cli.list_file)
-        .context(format!("Failed to parse compromised list: {:?}", cli.list_file))?;
+    run()?;
+    output::fail_on_warnings();
+    Ok(())
+}
 
-    let npm_json_value = load_npm_tree_json(&cli)?;
-    let deps = collect_deps(&npm_json_value)?;
+fn run() -> Result<()> {
+    let mut cli = Cli::parse();
+    output::set_quiet(cli.quiet);
+    output::set_warnings_as_errors(cli.warnings_as_errors);
+    set_warning_handler(|message| diag!("Warning: {}", message));
+    if cli.presets.iter().any(|p| p == "list") {
+        print_presets()?;
+        return Ok(());
+    }
+    // Collision detection is list-free, so it runs without the default list.
+    let no_list = cli.list_files.is_empty() && cli.advisories.is_empty() && cli.presets.is_empty();
+    if no_list && !cli.detect_integrity_collisions {
+        cli.list_files.push(PathBuf::from(DEFAULT_LIST));
+    }
 
-    let (matches, any) = find_matches(&deps, &lists);
-    match cli.format.as_str() {
-        "text" => {
-            if any {
-                for m in &matches {
-                    match m.match_type.as_str() {
-                        "exact" => println!("[EXACT MATCH] {}@{}", m.name, m.version),
-                        "name" => println!("[NAME MATCH ] {}@{}", m.name, m.version),
-                        _ => {}
-                    }
-                }
-            } else {
-                println!("No compromised dependencies found.");
-            }
-        }
-        "json" => {
-            #[derive(serde::Serialize)]
-            struct Output<'a> {
-                matches: &'a [MatchRecord],
-                match_count: usize,
-                compromised_names: Vec<String>,
-                compromised_exact: Vec<String>,
-            }
-            let comp_names: BTreeSet<_> = lists.names.iter().cloned().collect();
-            let comp_exact: BTreeSet<_> = lists
-                .exact
-                .iter()
-                .map(|(n, v)| format!("{n}@{v}"))
-                .collect();
-            let out = Output {
-                matches: &matches,
-                match_count: matches.len(),
-                compromised_names: comp_names.into_iter().collect(),
-                compromised_exact: comp_exact.into_iter().collect(),
-            };
-            println!("{}", serde_json::to_string_pretty(&out)?);
+    if let Some(pattern) = &cli.merge_sarif {
+        let merged = merge_sarif_files(pattern)?;
+        let rendered = format!("{}\n", serde_json::to_string_pretty(&merged)?);
+        match &cli.output {
+            Some(path) => fs::write(path, rendered)
+                .context(format!("Failed to write output file: {:?}", path))?,
+            None => print!("{rendered}"),
         }
-        _ => unreachable!(),
+        return Ok(());
     }
 
-    if any {
-        std::process::exit(cli.fail_exit_code);
+    if let Some(path) = &cli.decode_bin {
+        let data = if path.as_os_str() == "-" {
+            let mut buf = Vec::new();
+            io::stdin().read_to_end(&mut buf)?;
+            buf
+        } else {
+            fs::read(path).context(format!("Failed to read binary result: {:?}", path))?
+        };
+        let (format_version, result) = decode_bin_result(&data)?;
+        #[derive(serde::Serialize)]
+        struct Decoded {
+            format_version: u32,
+            #[serde(flatten)]
+            result: BinResult,
+        }
+        let decoded = Decoded {
+            format_version,
+            result,
+        };
+        println!("{}", serde_json::to_string_pretty(&decoded)?);
+        return Ok(());
     }
-    Ok(())
-}
 
-/// Parse the compromised list file.
-///
-/// Rules:
-/// - Ignore blank lines and lines starting with '#'
-/// - Distinguish name-only vs exact (name@version or @scope/name@version)
-fn parse_compromised_file(path: &PathBuf) -> Result<Lists> {
-    let content = fs::read_to_string(path)
-        .context(format!("Unable to read compromised list file: {:?}", path))?;
-    let mut exact = HashSet::new();
-    let mut names = HashSet::new();
-
-    for (lineno, raw_line) in content.lines().enumerate() {
-        let line = raw_line.trim();
-        if line.is_empty() || line.starts_with('#') {
-            continue;
+    if let Some(sources) = &cli.merge_lists {
+        let merged = merge_list_sources(sources, &cli)?;
+        match &cli.output {
+            Some(path) => fs::write(path, merged)
+                .context(format!("Failed to write output file: {:?}", path))?,
+            None => print!("{merged}"),
         }
+        return Ok(());
+    }
 
-        match parse_compromised_entry(line) {
-            CompEntry::Name(name) => {
-                names.insert(name);
-            }
-            CompEntry::Exact { name, version } => {
-                names.insert(name.clone());
-                exact.insert((name, version));
-            }
-            CompEntry::Invalid(reason) => {
-                return Err(anyhow!(
-                    "Invalid entry at line {}: '{}' ({})",
-                    lineno + 1,
-                    line,
-                    reason
-                ));
-            }
-        }
+    if let Some(pair) = &cli.list_diff {
+        return run_list_diff(&pair[0], &pair[1], &cli);
     }
 
-    Ok(Lists { exact, names })
-}
+    if let Some(url) = &cli.check_list_version {
+        return run_check_list_version(url, &cli);
+    }
 
-enum CompEntry {
-    Name(String),
-    Exact { name: String, version: String },
-    Invalid(String),
-}
+    if let Some(out) = &cli.build_list_index {
+        let (entries, _) = load_list_entries(&cli)?;
+        write_list_index(out, &entries)?;
+        eprintln!("Wrote list index of {} entries to {:?}", entries.len(), out);
+        return Ok(());
+    }
 
-/// Determine if a line is name-only or exact.
-/// Logic:
-/// - Find last '@'
-/// - If no '@' => name-only
-/// - If line starts with '@':
-///     - If total '@' count >= 2: candidate for exact (@scope/pkg@version)
-/// - Else if unscoped and has one '@': candidate for exact
-/// - Validate candidate version: must not contain '/', and starts with [0-9A-Za-z]
-fn parse_compromised_entry(line: &str) -> CompEntry {
-    if !line.contains('@') {
-        return CompEntry::Name(line.to_string());
+    if let Some(out) = &cli.build_index {
+        let (deps, _) = load_deps(&cli, wants_long_output(&cli, None))?;
+        write_dep_index(out, &deps)?;
+        eprintln!("Wrote index of {} dependencies to {:?}", deps.len(), out);
+        return Ok(());
     }
-    let at_count = line.matches('@').count();
 
-    if line.starts_with('@') {
-        if at_count < 2 {
-            // e.g. @scope/name (no version)
-            return CompEntry::Name(line.to_string());
+    if let Some(dir) = &cli.list_history {
+        let (deps, _) = load_deps(&cli, wants_long_output(&cli, None))?;
+        return run_list_history(dir, &deps, &cli);
+    }
+
+    let mut timer = PhaseTimer::start(cli.verbose);
+    let (lists, list_sources) = load_compromised_list(&cli)?;
+    timer.finish("load");
+    if cli.verbose {
+        eprintln!("{}", output::list_counts(&lists));
+        for e in &lists.expired {
+            eprintln!(
+                "Skipping expired entry '{}' (expired {}); consider pruning it from the list.",
+                e.entry, e.expired_on
+            );
         }
-    } else {
-        if at_count == 1 {
-            // unscoped exact candidate
-        } else if at_count > 1 {
-            return CompEntry::Invalid("Too many @ characters for unscoped package".into());
+    }
+    let list_given =
+        cli.list_index.is_some() || !cli.list_files.is_empty() || !cli.advisories.is_empty();
+    if lists.is_empty() && list_given {
+        if cli.require_nonempty_list {
+            return Err(anyhow!(
+                "Compromised list {} contains no entries",
+                list_label(&cli)
+            ));
         }
+        diag!(
+            "Warning: compromised list {} contains no entries; every scan will report clean.",
+            list_label(&cli)
+        );
     }
 
-    let last_at = line.rfind('@').unwrap();
-    let name_part = &line[..last_at];
-    let ver_part = &line[last_at + 1..];
-
-    if name_part.is_empty() {
-        return CompEntry::Invalid("Empty name part".into());
+    if !cli.scan_dir.is_empty() {
+        return run_dir_scan(&cli.scan_dir, &lists, &cli);
     }
-    if ver_part.is_empty() {
-        return CompEntry::Invalid("Empty version part".into());
+    if let Some(root) = &cli.recursive {
+        return run_recursive_scan(root, &lists, &cli);
     }
-    if ver_part.contains('/') {
-        return CompEntry::Invalid("Version contains '/'".into());
+
+    let manifest_clean = cli.prefilter_manifest
+        && !manifest_declares_listed_name(&cli.package_json, &lists, cli.match_basename)?;
+    let (mut deps, tree) = if manifest_clean {
+        if cli.verbose {
+            eprintln!(
+                "No compromised name declared in {:?}; skipping tree resolution.",
+                cli.package_json
+            );
+        }
+        (Vec::new(), None)
+    } else {
+        load_deps(&cli, wants_long_output(&cli, Some(&lists)))?
+    };
+    timer.finish("collect");
+    let mut sources = vec![describe_source(&cli, manifest_clean, deps.len())];
+    let extra_scans = extra_scans(&cli, wants_long_output(&cli, Some(&lists)))?;
+    sources.extend(extra_scans.iter().map(|(_, _, source)| source.clone()));
+    if cli.verbose {
+        for src in &sources {
+            eprintln!(
+                "Source {} parsed as {} (parser {}): {} dependencies",
+                src.input, src.format, src.parser_version, src.dep_count
+            );
+        }
     }
-    if !ver_part
-        .chars()
-        .next()
-        .map(|c| c.is_ascii_alphanumeric())
-        .unwrap_or(false)
-    {
-        return CompEntry::Invalid("Version does not start with alphanumeric".into());
+    if cli.tree_stats {
+        match &tree {
+            Some(tree) => {
+                let stats = tree_stats(tree);
+                if cli.format == "json" {
+                    eprintln!("{}", serde_json::to_string_pretty(&stats)?);
+                } else {
+                    eprintln!("{}", stats.summary());
+                }
+            }
+            None => {
+                diag!("Note: --tree-stats needs the npm ls tree; no statistics for this input.")
+            }
+        }
     }
-
-    CompEntry::Exact {
-        name: name_part.to_string(),
-        version: ver_part.to_string(),
+    let node_issues = tree.as_ref().map(|t| tree_node_issues(t, &cli.scan_scope));
+    let problems: Option<Vec<Value>> = tree.as_ref().and_then(|t| {
+        let problems = t.get("problems")?.as_array()?;
+        (!problems.is_empty()).then(|| problems.clone())
+    });
+    let skipped = node_issues.as_ref().map_or(0, |i| i.skipped.len());
+    if skipped > 0 {
+        // Listed in the report rather than printed as a warning.
+        output::record_warning(format!(
+            "Warning: {} node(s) in the npm tree could not be scanned",
+            skipped
+        ));
     }
-}
-
-/// Load npm dependency tree JSON (Value).
-fn load_npm_tree_json(cli: &Cli) -> Result<Value> {
-    if let Some(src) = &cli.npm_json {
-        if src == "-" {
-            let mut buf = String::new();
-            io::stdin()
-                .read_to_string(&mut buf)
-                .context("Failed to read stdin")?;
-            let v: Value =
-                serde_json::from_str(&buf).context("Failed to parse JSON from stdin (--npm-json -)")?;
-            return Ok(v);
-        } else {
-            let data = fs::read_to_string(src)
-                .context(format!("Failed to read npm JSON file: {}", src))?;
-            let v: Value =
-                serde_json::from_str(&data).context("Failed to parse provided npm JSON file")?;
-            return Ok(v);
+    if cli.strict_tree {
+        let problem_count = problems.as_ref().map_or(0, Vec::len);
+        if skipped > 0 || problem_count > 0 {
+            eprintln!(
+                "Error: the npm tree is incomplete ({} node(s) could not be scanned, {} problem(s) reported by npm); not scanning it (--strict-tree)",
+                skipped, problem_count
+            );
+            for node in node_issues.iter().flat_map(|i| &i.skipped) {
+                eprintln!("  {}", node.describe());
+            }
+            for problem in problems.iter().flatten() {
+                eprintln!("  {}", problem.as_str().unwrap_or_default());
+            }
+            output::exit(INCOMPLETE_TREE_EXIT_CODE);
         }
     }
+    if cli.top_level_only {
+        deps.retain(|d| d.direct);
+    }
 
-    if cli.no_run_npm {
+    if cli.format == "dot" && tree.is_none() && !manifest_clean {
         return Err(anyhow!(
-            "no-run-npm specified but no --npm-json source provided"
+            "--format dot needs the npm ls tree; it is not available with --index, --require-cache, --deps-json, --tarball, --package-lock, --yarn-lock, --pnpm-lock, or --npm-query"
         ));
     }
 
-    let output = Command::new("npm")
-        .args(["ls", "--all", "--json"])
-        .output()
-        .context("Failed to execute `npm ls --all --json`")?;
-
-    if !output.status.success() {
-        eprintln!(
-            "Warning: npm ls exited with non-zero status ({:?}). Still attempting to parse output.",
-            output.status.code()
-        );
+    let mut matches = find_matches_with(&deps, &lists, &match_options(&cli));
+    if let Some(issues) = &node_issues {
+        matches.extend(find_unversioned_name_matches(&cli, &issues.skipped, &lists));
     }
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let v: Value =
-        serde_json::from_str(&stdout).context("Failed to parse JSON from `npm ls` output")?;
-    Ok(v)
-}
-
-/// Collect dependencies from the npm JSON tree.
-/// Returns unique list of (name, version).
-fn collect_deps(root: &Value) -> Result<Vec<Dep>> {
-    let mut acc = Vec::new();
-    let mut seen = HashSet::new();
-
-    if let Some(deps) = root.get("dependencies") {
-        if let Some(obj) = deps.as_object() {
-            for (name, node) in obj {
-                traverse(name, node, &mut acc, &mut seen);
+    for (origin, mut extra, _) in extra_scans {
+        if cli.top_level_only {
+            extra.retain(|d| d.direct);
+        }
+        let mut found = find_matches_with(&extra, &lists, &match_options(&cli));
+        for m in &mut found {
+            m.origin = origin.to_string();
+        }
+        matches.extend(found);
+        deps.extend(extra);
+    }
+    if !lists.repos.is_empty() {
+        match &tree {
+            Some(tree) => {
+                let repo_matches =
+                    find_repo_matches(tree, &deps, &lists, &matches, &match_options(&cli));
+                matches.extend(repo_matches);
             }
+            None if !manifest_clean => diag!(
+                "Warning: repo: entries need the npm ls tree and were not checked for this input"
+            ),
+            None => {}
         }
     }
-    acc.sort();
-    Ok(acc)
-}
-
-fn traverse(name: &str, node: &Value, acc: &mut Vec<Dep>, seen: &mut HashSet<(String, String)>) {
-    if let Some(version) = node.get("version").and_then(|v| v.as_str()) {
-        let key = (name.to_string(), version.to_string());
-        if seen.insert(key.clone()) {
-            acc.push(Dep {
-                name: key.0.clone(),
-                version: key.1.clone(),
-            });
+    if let Some(advisory) = load_advisory_list(&cli)? {
+        let advisories = find_advisory_matches(&deps, &advisory, &matches, &match_options(&cli));
+        matches.extend(advisories);
+    }
+    // Integrity and resolved URLs are only known from a lockfile; other inputs skip these checks.
+    let lockfile_matches =
+        find_lockfile_matches(&cli, &lists, &cli.package_lock, &match_options(&cli))?;
+    matches.extend(lockfile_matches);
+    if !cli.match_all_rules {
+        matches = keep_strongest(matches);
+    }
+    timer.finish("match");
+    let mut omitted = None;
+    if !cli.omit.is_empty() {
+        let (left_out, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut matches)
+            .into_iter()
+            .partition(|m| m.dependency_kind.is_omitted(&cli.omit));
+        matches = kept;
+        omitted = Some(left_out);
+    }
+    let mut suppressed = None;
+    if let Some(path) = &cli.ignore_file {
+        let ignores = parse_ignore_file(path, cli.list_separator)
+            .context(format!("Failed to parse ignore file: {:?}", path))?;
+        let as_of = parse_options(&cli).as_of;
+        warn_expiring_ignores(&cli, &ignores, as_of);
+        let (waived, unused, expired) = apply_ignores(&mut matches, &ignores, as_of);
+        for e in expired {
+            diag!(
+                "Warning: ignore entry '{}' expired on {}; its matches are reported again.",
+                e.entry.spec,
+                e.entry.expires.unwrap_or_default()
+            );
+        }
+        if cli.warn_unused_ignores {
+            for entry in unused {
+                diag!("Warning: ignore entry '{}' suppressed no match.", entry);
+            }
+        }
+        suppressed = Some(waived);
+    }
+    let mut existing = None;
+    let mut stale_baseline = None;
+    if let Some(path) = &cli.baseline {
+        let baseline =
+            read_baseline(path).context(format!("Failed to read baseline: {:?}", path))?;
+        let found: BTreeSet<BaselineEntry> = matches
+            .iter()
+            .chain(suppressed.iter().flatten().map(|s| &s.record))
+            .chain(omitted.iter().flatten())
+            .map(BaselineEntry::of)
+            .collect();
+        stale_baseline = Some(baseline.difference(&found).cloned().collect::<Vec<_>>());
+        let (known, new): (Vec<_>, Vec<_>) = std::mem::take(&mut matches)
+            .into_iter()
+            .partition(|m| baseline.contains(&BaselineEntry::of(m)));
+        matches = new;
+        existing = Some(known);
+    }
+    if let Some(path) = &cli.write_baseline {
+        let entries = matches
+            .iter()
+            .chain(existing.iter().flatten())
+            .map(BaselineEntry::of);
+        let baseline = BaselineFile {
+            baseline_format_version: BASELINE_FORMAT_VERSION,
+            matches: entries.collect(),
+        };
+        let rendered = format!("{}\n", serde_json::to_string_pretty(&baseline)?);
+        fs::write(path, rendered).context(format!("Failed to write baseline: {:?}", path))?;
+    }
+    if let Some(min) = cli.min_occurrences.filter(|_| !manifest_clean) {
+        let before = matches.len();
+        filter_min_occurrences(&cli, tree.as_ref(), &cli.package_lock, &mut matches)?;
+        if cli.verbose && matches.len() < before {
+            eprintln!(
+                "Suppressed {} match(es) installed fewer than {} time(s) (--min-occurrences).",
+                before - matches.len(),
+                min
+            );
         }
     }
-    if let Some(deps) = node.get("dependencies").and_then(|d| d.as_object()) {
-        for (child_name, child_node) in deps {
-            traverse(child_name, child_node, acc, seen);
+    if let Some(dir) = &cli.with_first_seen {
+        let seen =
+            read_first_seen(dir).context(format!("Failed to read report history: {:?}", dir))?;
+        for m in matches.iter_mut().chain(existing.iter_mut().flatten()) {
+            let first = seen
+                .get(&BaselineEntry::of(m))
+                .map_or("now", String::as_str);
+            m.first_seen = Some(first.to_string());
+        }
+    }
+    if cli.verify_reachable {
+        verify_reachable(&mut matches);
+    }
+    if cli.collapse_per_package {
+        let before = matches.len();
+        matches = collapse_per_package(&cli, matches);
+        if cli.verbose && matches.len() < before {
+            eprintln!(
+                "Collapsed {} match(es) into {} package(s) (--collapse-per-package).",
+                before,
+                matches.len()
+            );
+        }
+    }
+    if cli.global {
+        for m in matches.iter_mut().filter(|m| m.origin == "project") {
+            m.origin = "global".to_string();
+        }
+    }
+    if let Some(tree) = &tree {
+        // Matches from --include-global and --npx-cache are not in this tree.
+        let tree_origin = if cli.global { "global" } else { "project" };
+        let (mut in_tree, others): (Vec<_>, Vec<_>) = std::mem::take(&mut matches)
+            .into_iter()
+            .partition(|m| m.origin == tree_origin);
+        attach_match_paths(tree, &mut in_tree, &cli.scan_scope, cli.max_paths);
+        matches = in_tree;
+        matches.extend(others);
+    }
+    attach_licenses(&cli, tree.as_ref(), &mut matches)?;
+    if cli.require_license {
+        for m in matches
+            .iter()
+            .filter(|m| is_unknown_license(m.license.as_deref()))
+        {
+            diag!(
+                "Warning: {}@{} has no known license (--require-license).",
+                m.name,
+                m.version
+            );
+        }
+    }
+    if cli.include_context {
+        if let Some(tree) = &tree {
+            attach_match_context(tree, &mut matches);
+        }
+    }
+    // Taken before redaction, since the graph is built by matching real versions.
+    let match_keys: HashSet<(String, String)> = matches
+        .iter()
+        .map(|m| (m.name.clone(), m.version.clone()))
+        .collect();
+    if cli.redact_versions {
+        matches.iter_mut().for_each(redact_match);
+        for s in suppressed.iter_mut().flatten() {
+            redact_match(&mut s.record);
+            s.ignored_by = redact_spec(&s.ignored_by);
+        }
+        omitted.iter_mut().chain(existing.iter_mut()).flatten().for_each(redact_match);
+        for e in stale_baseline.iter_mut().flatten() {
+            e.version = REDACTED_VERSION.to_string();
+        }
+    }
+    // After redaction, so the hash cannot be used to recover the versions.
+    let result_hash = cli.result_hash.then(|| match_set_hash(&matches));
+    let mut duplicates = if cli.report_duplicates || cli.fail_on_duplicates {
+        Some(find_duplicates(&deps))
+    } else {
+        None
+    };
+    if cli.redact_versions {
+        for d in duplicates.iter_mut().flatten() {
+            d.versions.fill(REDACTED_VERSION.to_string());
         }
     }
-}
 
-fn find_matches(deps: &[Dep], lists: &Lists) -> (Vec<MatchRecord>, bool) {
-    let mut matches = Vec::new();
-    for d in deps {
-        if lists.exact.contains(&(d.name.clone(), d.version.clone())) {
-            matches.push(MatchRecord {
-                match_type: "exact".to_string(),
-                name: d.name.clone(),
-                version: d.version.clone(),
-            });
-        } else if lists.names.contains(&d.name) {
-            matches.push(MatchRecord {
-                match_type: "name".to_string(),
-                name: d.name.clone(),
-                version: d.version.clone(),
+    // Sorted so the same matches always give the same sample, whatever the tree order.
+    let sample = cli
+        .sample
+        .map(|n| n as usize)
+        .filter(|&n| n < matches.len())
+        .map(|n| {
+            let mut sample = matches.clone();
+            sample.sort_by(|a, b| {
+                (&a.name, &a.version, &a.match_type).cmp(&(&b.name, &b.version, &b.match_type))
             });
-        }
+            sample.truncate(n);
+            sample
+        });
+    let shown = sample.as_deref().unwrap_or(&matches);
+    let sampled_out = matches.len() - shown.len();
+    let report = JsonReport {
+        deps: &deps,
+        lists: &lists,
+        matches: &matches,
+        sample: sample.as_deref(),
+        suppressed: suppressed.as_deref(),
+        omitted: omitted.as_deref(),
+        existing: existing.as_deref(),
+        stale_baseline: stale_baseline.as_deref(),
+        skipped_nodes: node_issues.as_ref().map(|i| i.skipped.as_slice()),
+        flagged_nodes: node_issues.as_ref().map(|i| i.flagged.as_slice()),
+        problems: problems.as_deref(),
+        duplicates: duplicates.as_deref(),
+        result_hash: result_hash.as_deref(),
+        sources: &sources,
+        list_sources: &list_sources,
+    };
+    let mut rendered = String::new();
+    let mut binary = None;
+    let format = if cli.annotate_sbom.is_some() {
+        "sbom-annotation"
+    } else if cli.template.is_some() {
+        "template"
+    } else {
+        cli.format.as_str()
+    };
+    // These formats have nowhere to say that matches were left out.
+    let unannotated = [
+        "ndjson",
+        "sarif",
+        "github",
+        "cyclonedx-vex",
+        "junit",
+        "template",
+    ];
+    if sampled_out > 0 && unannotated.contains(&format) {
+        diag!(
+            "Note: showing {} of {} matches (--sample).",
+            shown.len(),
+            matches.len()
+        );
+    }
+    match format {
+        "text" => {
+            let palette = Palette::new(
+                &cli.color,
+                cli.output.is_none() && io::stdout().is_terminal(),
+            );
+            for m in shown {
+                writeln!(rendered, "{}", palette.match_line(m, &format_text_match(m)))?;
+                // A path of one is the direct install, already implied by the match line.
+                for path in m.paths.iter().filter(|p| p.len() > 1) {
+                    writeln!(rendered, "    via {}", path.join(" > "))?;
+                }
+                if m.omitted_paths > 0 {
+                    writeln!(
+                        rendered,
+                        "    ... {} more path(s) (--max-paths)",
+                        m.omitted_paths
+                    )?;
+                }
+            }
+            if sampled_out > 0 {
+                writeln!(
+                    rendered,
+                    "... and {} more match(es) (--sample)",
+                    sampled_out
+                )?;
+            }
+            if !cli.quiet {
+                write_text_status(&mut rendered, &cli, palette, &report)?;
+            }
+            if let Some(dups) = &duplicates {
+                if dups.is_empty() {
+                    if !cli.quiet {
+                        writeln!(rendered, "No duplicate packages found.")?;
+                    }
+                } else {
+                    writeln!(rendered, "Duplicate packages ({}):", dups.len())?;
+                    for d in dups {
+                        writeln!(
+                            rendered,
+                            "[DUPLICATE  ] {}: {}",
+                            d.name,
+                            d.versions.join(", ")
+                        )?;
+                    }
+                }
+            }
+            if let Some(hash) = &result_hash {
+                writeln!(rendered, "Result hash: {hash}")?;
+            }
+            if !cli.quiet {
+                writeln!(rendered, "{}", output::summary_line(deps.len(), &matches))?;
+            }
+        }
+        "json" => {
+            writeln!(rendered, "{}", render_json_report(&cli, &report)?)?;
+        }
+        "ndjson" => {
+            for m in shown {
+                writeln!(rendered, "{}", serde_json::to_string(m)?)?;
+            }
+        }
+        "ndjson-with-meta" => {
+            write!(rendered, "{}", render_ndjson_with_meta(&cli, &report)?)?;
+        }
+        "sarif" => {
+            let sarif = render_sarif(shown, &cli.artifact_path);
+            writeln!(rendered, "{}", serde_json::to_string_pretty(&sarif)?)?;
+        }
+        "cyclonedx-vex" => {
+            let vex = render_cyclonedx_vex(shown);
+            writeln!(rendered, "{}", serde_json::to_string_pretty(&vex)?)?;
+        }
+        "github" => {
+            let file = annotation_file(&cli);
+            write!(rendered, "{}", render_github(shown, deps.len(), file))?;
+            if let Some(path) = std::env::var_os("GITHUB_STEP_SUMMARY").filter(|p| !p.is_empty()) {
+                let summary = render_step_summary(&matches, shown, deps.len());
+                fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .and_then(|mut f| f.write_all(summary.as_bytes()))
+                    .context(format!("Failed to write step summary: {:?}", path))?;
+            }
+        }
+        "junit" => {
+            let junit = render_junit(
+                shown,
+                &cli.junit_suite_name,
+                &cli.junit_classname,
+                cli.junit_single_suite,
+            );
+            write!(rendered, "{junit}")?;
+        }
+        "badge" => {
+            writeln!(rendered, "{}", render_badge(matches.len()))?;
+        }
+        "template" => {
+            let path = cli.template.as_ref().unwrap();
+            let text = render_template(path, shown)
+                .context(format!("Failed to render template: {:?}", path))?;
+            write!(rendered, "{text}")?;
+        }
+        "sbom-annotation" => {
+            let path = cli.annotate_sbom.as_ref().unwrap();
+            let sbom = annotate_sbom(path, &matches)
+                .context(format!("Failed to annotate SBOM: {:?}", path))?;
+            writeln!(rendered, "{sbom}")?;
+        }
+        "dot" => {
+            let graph = render_dot(tree.as_ref(), &match_keys, cli.redact_versions);
+            write!(rendered, "{graph}")?;
+        }
+        "bin" => {
+            binary = Some(encode_bin_result(
+                &matches,
+                duplicates.as_deref(),
+                result_hash.as_deref(),
+            )?);
+        }
+        _ => unreachable!(),
+    }
+    let bytes = binary.unwrap_or_else(|| rendered.into_bytes());
+    match &cli.output {
+        Some(path) => {
+            fs::write(path, &bytes).context(format!("Failed to write output file: {:?}", path))?
+        }
+        None => io::stdout().write_all(&bytes)?,
+    }
+    // The --report file and --exit-hook always get every match.
+    let report = JsonReport {
+        sample: None,
+        ..report
+    };
+    if let Some(path) = &cli.report {
+        fs::write(path, render_json_report(&cli, &report)? + "\n")
+            .context(format!("Failed to write report: {:?}", path))?;
+    }
+
+    if cli.syslog {
+        send_to_syslog(&matches, cli.syslog_facility);
+    }
+
+    if cli.fail_on_direct_only {
+        let transitive = matches.iter().filter(|m| !m.direct).count();
+        if transitive > 0 {
+            diag!(
+                "Note: {} transitive match(es) reported without failing (--fail-on-direct-only).",
+                transitive
+            );
+        }
+    }
+    let gating: Vec<&MatchRecord> = matches.iter().filter(|m| is_gating(&cli, m)).collect();
+    let fail = match cli.fail_on_distinct {
+        Some(threshold) => distinct_package_count(gating.iter().copied()) > threshold,
+        None => !gating.is_empty(),
+    };
+    let fail_duplicates =
+        cli.fail_on_duplicates && duplicates.as_ref().is_some_and(|d| !d.is_empty());
+    let mut code = if cli.exit_bitmask {
+        let mut code = 0;
+        for m in &gating {
+            code |= exit_bit(&m.match_type);
+        }
+        if fail_duplicates {
+            code |= exit_bit("duplicate");
+        }
+        code
+    } else if cli.fail_code_from_severity && fail {
+        let worst = highest_severity(&gating);
+        cli.severity_exit_map
+            .get(&worst)
+            .copied()
+            .unwrap_or(cli.fail_exit_code)
+    } else if fail || fail_duplicates {
+        cli.fail_exit_code
+    } else {
+        0
+    };
+    let warn_code = cli
+        .warn_exit_code
+        .filter(|_| code == 0 && matches.iter().any(|m| is_warning_only(&cli, m)));
+    if let Some(warn_code) = warn_code {
+        code = warn_code;
+    }
+    let mut explanation = explain_exit(&cli, code, &matches, &gating, fail_duplicates);
+    if warn_code.is_some() {
+        explanation.push_str("; exit code from --warn-exit-code");
+    }
+    if let Some(hook) = &cli.exit_hook {
+        match run_exit_hook(hook, &render_json_report(&cli, &report)?) {
+            Ok(hook_code) => {
+                explanation = format!(
+                    "exiting {}: decided by --exit-hook (built-in policy: {})",
+                    hook_code, explanation
+                );
+                code = hook_code;
+            }
+            Err(e) => diag!(
+                "Warning: --exit-hook failed ({:#}); using the built-in exit code {}.",
+                e,
+                code
+            ),
+        }
+    }
+    if cli.explain_exit && (code != 0 || cli.verbose) {
+        eprintln!("{}", explanation);
+    }
+    if code != 0 {
+        output::exit(code);
+    }
+    Ok(())
+}
+
+/// The lines of the text report around the matches: the clean message and the
+/// suppressed, omitted, baseline, npm tree, and distinct-package notes. Left out
+/// by --quiet.
+fn write_text_status(
+    out: &mut String,
+    cli: &Cli,
+    palette: Palette,
+    report: &JsonReport,
+) -> Result<()> {
+    let JsonReport {
+        matches,
+        suppressed,
+        omitted,
+        existing,
+        stale_baseline,
+        skipped_nodes,
+        flagged_nodes,
+        problems,
+        ..
+    } = *report;
+    if matches.is_empty() {
+        let clean = if cli.baseline.is_some() {
+            "No new compromised dependencies found."
+        } else {
+            "No compromised dependencies found."
+        };
+        writeln!(out, "{}", palette.green(clean))?;
+    }
+    if let Some(waived) = suppressed.filter(|s| !s.is_empty()) {
+        writeln!(out, "{} match(es) suppressed by ignore list.", waived.len())?;
+    }
+    if let Some(left_out) = omitted.filter(|o| !o.is_empty()) {
+        writeln!(
+            out,
+            "{} match(es) in omitted dependency types (--omit {}) not failing the scan.",
+            left_out.len(),
+            cli.omit.join(", --omit ")
+        )?;
+    }
+    if let Some(known) = existing.filter(|e| !e.is_empty()) {
+        writeln!(
+            out,
+            "{} existing match(es) in the baseline, not failing the scan:",
+            known.len()
+        )?;
+        for m in known {
+            writeln!(out, "  {}", format_text_match(m))?;
+        }
+    }
+    if let Some(stale) = stale_baseline.filter(|s| !s.is_empty()) {
+        writeln!(
+            out,
+            "{} baseline entry(ies) no longer match and can be pruned:",
+            stale.len()
+        )?;
+        for e in stale {
+            writeln!(out, "  {}@{} ({})", e.name, e.version, e.match_type)?;
+        }
+    }
+    let global = matches.iter().filter(|m| m.origin == "global").count();
+    if global > 0 {
+        writeln!(
+            out,
+            "{} match(es) in globally installed packages: update or remove them with `npm install -g` / `npm uninstall -g`.",
+            global
+        )?;
+    }
+    let npx = matches.iter().filter(|m| m.origin == "npx-cache").count();
+    if npx > 0 {
+        writeln!(
+            out,
+            "{} match(es) in the npx cache: delete its `_npx` directory so npx fetches fresh copies.",
+            npx
+        )?;
+    }
+    let describe = |nodes: &[TreeNodeIssue]| {
+        nodes
+            .iter()
+            .map(TreeNodeIssue::describe)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    if let Some(skipped) = skipped_nodes.filter(|s| !s.is_empty()) {
+        writeln!(
+            out,
+            "{} node(s) in the npm tree could not be scanned: {}",
+            skipped.len(),
+            describe(skipped)
+        )?;
+    }
+    if let Some(flagged) = flagged_nodes.filter(|f| !f.is_empty()) {
+        writeln!(
+            out,
+            "{} node(s) flagged by npm were scanned: {}",
+            flagged.len(),
+            describe(flagged)
+        )?;
+    }
+    if let Some(problems) = problems {
+        writeln!(out, "npm reported {} problem(s):", problems.len())?;
+        for problem in problems {
+            writeln!(out, "  {}", problem.as_str().unwrap_or_default())?;
+        }
+    }
+    if cli.fail_on_distinct.is_some() {
+        writeln!(
+            out,
+            "{} distinct compromised package(s) across {} match(es).",
+            distinct_package_count(matches.iter()),
+            matches.len()
+        )?;
+    }
+    Ok(())
+}
+
+/// What goes into the `--format json` report.
+struct JsonReport<'a> {
+    deps: &'a [Dep],
+    lists: &'a CompromisedList,
+    matches: &'a [MatchRecord],
+    sample: Option<&'a [MatchRecord]>,         // with --sample, when it drops matches
+    suppressed: Option<&'a [SuppressedMatch]>, // with --ignore-file
+    omitted: Option<&'a [MatchRecord]>,        // with --omit
+    existing: Option<&'a [MatchRecord]>,       // with --baseline
+    stale_baseline: Option<&'a [BaselineEntry]>,
+    skipped_nodes: Option<&'a [TreeNodeIssue]>, // npm tree nodes not scanned
+    flagged_nodes: Option<&'a [TreeNodeIssue]>, // invalid and extraneous nodes
+    problems: Option<&'a [Value]>,              // npm ls's top-level `problems`
+    duplicates: Option<&'a [DuplicateRecord]>,
+    result_hash: Option<&'a str>,
+    sources: &'a [SourceRecord],
+    list_sources: &'a [ListSourceRecord],
+}
+
+/// The `--format json` report, also fed to `--exit-hook`.
+fn render_json_report(cli: &Cli, report: &JsonReport) -> Result<String> {
+    Ok(serde_json::to_string_pretty(&json_report(cli, report)?)?)
+}
+
+/// `--format ndjson-with-meta`: a `{"type":"meta",...}` line carrying the
+/// `--format json` fields other than the matches, plus the tool version and
+/// a timestamp, then one `{"type":"match",...}` (or `"suppressed"`, `"omitted"`,
+/// `"existing"`) line each.
+fn render_ndjson_with_meta(cli: &Cli, report: &JsonReport) -> Result<String> {
+    let Value::Object(fields) = json_report(cli, report)? else {
+        unreachable!()
+    };
+    let mut meta = serde_json::Map::new();
+    meta.insert("type".into(), json!("meta"));
+    meta.insert("tool".into(), json!(env!("CARGO_PKG_NAME")));
+    meta.insert("version".into(), json!(env!("CARGO_PKG_VERSION")));
+    meta.insert(
+        "generated_at".into(),
+        json!(chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true)),
+    );
+    let mut records = Vec::new();
+    for (key, value) in fields {
+        match (key.as_str(), value) {
+            ("matches", Value::Array(items)) => {
+                records.extend(items.into_iter().map(|m| ("match", m)));
+            }
+            ("suppressed", Value::Array(items)) => {
+                meta.insert("suppressed_count".into(), json!(items.len()));
+                records.extend(items.into_iter().map(|m| ("suppressed", m)));
+            }
+            ("omitted_matches", Value::Array(items)) => {
+                meta.insert("omitted_count".into(), json!(items.len()));
+                records.extend(items.into_iter().map(|m| ("omitted", m)));
+            }
+            ("existing_matches", Value::Array(items)) => {
+                meta.insert("existing_count".into(), json!(items.len()));
+                records.extend(items.into_iter().map(|m| ("existing", m)));
+            }
+            (_, value) => {
+                meta.insert(key, value);
+            }
+        }
+    }
+    let mut out = serde_json::to_string(&meta)?;
+    out.push('\n');
+    for (kind, record) in records {
+        let Value::Object(fields) = record else {
+            unreachable!()
+        };
+        let mut line = serde_json::Map::new();
+        line.insert("type".into(), json!(kind));
+        line.extend(fields);
+        writeln!(out, "{}", serde_json::to_string(&line)?)?;
+    }
+    Ok(out)
+}
+
+/// The `--format json` document.
+fn json_report(cli: &Cli, report: &JsonReport) -> Result<Value> {
+    let JsonReport {
+        deps,
+        lists,
+        matches,
+        sample,
+        suppressed,
+        omitted,
+        existing,
+        stale_baseline,
+        skipped_nodes,
+        flagged_nodes,
+        problems,
+        duplicates,
+        result_hash,
+        sources,
+        list_sources,
+    } = *report;
+    #[derive(serde::Serialize)]
+    struct Output<'a> {
+        matches: &'a [MatchRecord],
+        #[serde(skip_serializing_if = "Option::is_none")]
+        suppressed: Option<&'a [SuppressedMatch]>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        omitted_matches: Option<&'a [MatchRecord]>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        existing_matches: Option<&'a [MatchRecord]>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        stale_baseline_entries: Option<&'a [BaselineEntry]>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        skipped_nodes: Option<&'a [TreeNodeIssue]>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        flagged_nodes: Option<&'a [TreeNodeIssue]>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        problems: Option<&'a [Value]>,
+        match_count: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sampled_out: Option<usize>,
+        match_type_counts: BTreeMap<&'a str, usize>,
+        distinct_package_count: usize,
+        compromised_names: Vec<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        compromised_exact: Option<Vec<String>>,
+        compromised_ranges: Vec<String>,
+        compromised_patterns: Vec<String>,
+        unmatched_entries: Vec<String>,
+        dependency_count: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        duplicates: Option<&'a [DuplicateRecord]>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        result_hash: Option<&'a str>,
+        sources: &'a [SourceRecord],
+        list_sources: &'a [ListSourceRecord],
+        tool_version: &'static str,
+        scanned_at: String,
+    }
+    // Basename and confusable matches name their listed entry in `entry`.
+    let relevant: HashSet<&str> = deps
+        .iter()
+        .map(|d| d.name.as_str())
+        .chain(
+            matches
+                .iter()
+                .filter(|m| matches!(m.match_type.as_str(), "basename" | "confusable"))
+                .filter_map(|m| m.entry.as_deref()),
+        )
+        .collect();
+    let echoed = |name: &str| !cli.echo_relevant_only || relevant.contains(name);
+    let comp_names: BTreeSet<_> = lists.names.iter().filter(|n| echoed(n)).cloned().collect();
+    let comp_exact: BTreeSet<_> = lists
+        .exact
+        .iter()
+        .filter(|(n, _)| echoed(n))
+        .map(|(n, v)| format!("{n}@{v}"))
+        .collect();
+    // Under --redact-versions, matches carry redacted entries, so echoed
+    // entries are redacted before they are compared with them, too.
+    let redact = |spec: String| {
+        if cli.redact_versions {
+            redact_spec(&spec)
+        } else {
+            spec
+        }
+    };
+    let comp_ranges: BTreeSet<_> = lists
+        .ranges
+        .iter()
+        .filter(|(n, _)| echoed(n))
+        .map(|(n, r)| redact(format!("{n}@{}", r.raw)))
+        .collect();
+    let comp_patterns: BTreeSet<_> = lists
+        .patterns
+        .iter()
+        .filter(|p| !cli.echo_relevant_only || deps.iter().any(|d| p.pattern.matches(&d.name)))
+        .map(|p| redact(p.spec()))
+        .collect();
+    // Entries no match (waived ones included) was reported against; a name
+    // counts as found when any of its entries matched.
+    let hit: HashSet<String> = matches
+        .iter()
+        .chain(suppressed.into_iter().flatten().map(|s| &s.record))
+        .chain(omitted.into_iter().flatten())
+        .chain(existing.into_iter().flatten())
+        .flat_map(|m| [CompromisedList::entry_key(m), m.name.clone()])
+        .collect();
+    // Names of exact and range entries are covered by those entries' keys.
+    let versioned: HashSet<&str> = lists
+        .exact
+        .iter()
+        .map(|(n, _)| n.as_str())
+        .chain(lists.ranges.iter().map(|(n, _)| n.as_str()))
+        .collect();
+    let unmatched: BTreeSet<String> = comp_names
+        .iter()
+        .filter(|n| !versioned.contains(n.as_str()))
+        .chain(comp_exact.iter().filter(|_| !cli.redact_versions))
+        .chain(&comp_ranges)
+        .chain(&comp_patterns)
+        .cloned()
+        .chain(lists.repos.iter().map(|url| format!("repo:{url}")))
+        .chain(lists.integrities.iter().map(|e| {
+            let spec = e.spec();
+            let spec = if cli.redact_versions {
+                redact_integrity_spec(&spec)
+            } else {
+                spec
+            };
+            format!("integrity:{spec}")
+        }))
+        .filter(|key| !hit.contains(key))
+        .collect();
+    let out = Output {
+        matches: sample.unwrap_or(matches),
+        suppressed,
+        omitted_matches: omitted,
+        existing_matches: existing,
+        stale_baseline_entries: stale_baseline,
+        skipped_nodes,
+        flagged_nodes,
+        problems,
+        match_count: matches.len(),
+        sampled_out: sample.map(|s| matches.len() - s.len()),
+        match_type_counts: matches.iter().fold(BTreeMap::new(), |mut counts, m| {
+            *counts.entry(m.match_type.as_str()).or_default() += 1;
+            counts
+        }),
+        distinct_package_count: distinct_package_count(matches.iter()),
+        compromised_names: comp_names.into_iter().collect(),
+        compromised_exact: if cli.redact_versions {
+            None
+        } else {
+            Some(comp_exact.into_iter().collect())
+        },
+        compromised_ranges: comp_ranges.into_iter().collect(),
+        compromised_patterns: comp_patterns.into_iter().collect(),
+        unmatched_entries: unmatched.into_iter().collect(),
+        dependency_count: deps.len(),
+        duplicates,
+        result_hash,
+        sources,
+        list_sources,
+        tool_version: env!("CARGO_PKG_VERSION"),
+        scanned_at: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+    };
+    Ok(serde_json::to_value(&out)?)
+}
+
+/// Version of the `--write-baseline` format, recorded in the file.
+const BASELINE_FORMAT_VERSION: u32 = 1;
+
+/// A match accepted in a `--baseline`, also how --with-first-seen recognizes a
+/// match in earlier reports. The match type is part of the key, so a package
+/// that moves to another compromised version counts as new.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
+struct BaselineEntry {
+    name: String,
+    version: String,
+    match_type: String,
+}
+
+impl BaselineEntry {
+    fn of(m: &MatchRecord) -> Self {
+        BaselineEntry {
+            name: m.name.clone(),
+            version: m.version.clone(),
+            match_type: m.match_type.clone(),
+        }
+    }
+}
+
+/// The `--write-baseline` file.
+#[derive(Debug, serde::Serialize)]
+struct BaselineFile {
+    baseline_format_version: u32,
+    matches: BTreeSet<BaselineEntry>,
+}
+
+/// Entries of a `--baseline`: the `matches` of a `--write-baseline` file or of
+/// a `--format json` report (plus its `existing_matches`, when that report was
+/// itself run against a baseline), keeping only name, version, and match type.
+fn read_baseline(path: &std::path::Path) -> Result<BTreeSet<BaselineEntry>> {
+    let data = fs::read_to_string(path)?;
+    let v: Value = serde_json::from_str(&data).context("Baseline is not valid JSON")?;
+    let matches = v
+        .get("matches")
+        .and_then(Value::as_array)
+        .ok_or_else(|| anyhow!("Baseline has no `matches` array"))?;
+    let existing = v.get("existing_matches").and_then(Value::as_array);
+    matches
+        .iter()
+        .chain(existing.into_iter().flatten())
+        .enumerate()
+        .map(|(i, m)| {
+            serde_json::from_value(m.clone())
+                .context(format!("Baseline match {} needs name, version, and match_type", i + 1))
+        })
+        .collect()
+}
+
+/// `--with-first-seen`: for each match in the `*.json` reports in `dir`
+/// (`matches`, `existing_matches`, and `omitted_matches`), the earliest
+/// `scanned_at` of a report holding it. Files that are not reports are skipped.
+fn read_first_seen(dir: &std::path::Path) -> Result<HashMap<BaselineEntry, String>> {
+    let mut first_seen: HashMap<BaselineEntry, (chrono::DateTime<chrono::FixedOffset>, String)> =
+        HashMap::new();
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<io::Result<_>>()?;
+    files.retain(|p| p.extension().is_some_and(|ext| ext == "json"));
+    files.sort();
+    for path in files {
+        let dated = fs::read_to_string(&path).ok().and_then(|text| {
+            let report: Value = serde_json::from_str(&text).ok()?;
+            let stamp = report.get("scanned_at")?.as_str()?.to_string();
+            let when = chrono::DateTime::parse_from_rfc3339(&stamp).ok()?;
+            Some((report, stamp, when))
+        });
+        let Some((report, stamp, when)) = dated else {
+            diag!("Warning: skipping {:?} (not a --format json report)", path);
+            continue;
+        };
+        let records = ["matches", "existing_matches", "omitted_matches"]
+            .iter()
+            .filter_map(|key| report.get(key)?.as_array())
+            .flatten();
+        for record in records {
+            let Ok(key) = serde_json::from_value::<BaselineEntry>(record.clone()) else {
+                continue;
+            };
+            let stamp = stamp.clone();
+            first_seen
+                .entry(key)
+                .and_modify(|seen| {
+                    if when < seen.0 {
+                        *seen = (when, stamp.clone());
+                    }
+                })
+                .or_insert((when, stamp));
+        }
+    }
+    Ok(first_seen.into_iter().map(|(key, (_, stamp))| (key, stamp)).collect())
+}
+
+/// A match waived by `--ignore-file`.
+#[derive(Debug, serde::Serialize)]
+struct SuppressedMatch {
+    #[serde(flatten)]
+    record: MatchRecord,
+    ignored_by: String, // the ignore entry that waived it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires: Option<NaiveDate>,
+}
+
+/// One `--ignore-file` line.
+struct IgnoreEntry {
+    entry: ListEntry,
+    reason: Option<String>, // free text after `reason:`, which must come last
+}
+
+/// Entries of `--ignore-file`: list syntax, limited to bare names and exact
+/// versions, with an optional trailing `reason:` that may contain spaces.
+fn parse_ignore_file(path: &PathBuf, separator: char) -> Result<Vec<IgnoreEntry>> {
+    let content = fs::read_to_string(path)?;
+    let source = path.display().to_string();
+    let mut entries = Vec::new();
+    for (lineno, raw_line) in content.lines().enumerate() {
+        let line = raw_line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (spec, reason) = match line.split_once("reason:") {
+            Some((spec, reason)) if spec.is_empty() || spec.ends_with(char::is_whitespace) => {
+                (spec.trim_end(), Some(reason.trim().to_string()))
+            }
+            _ => (line, None),
+        };
+        let entry = parse_list_line(spec, separator, &source).map_err(|reason| {
+            anyhow!("Invalid entry at line {}: '{}' ({})", lineno + 1, line, reason)
+        })?;
+        if !matches!(entry.entry, CompEntry::Name(_) | CompEntry::Exact { .. }) {
+            return Err(anyhow!(
+                "'{}': ignore entries must be a name or name@version",
+                entry.spec
+            ));
+        }
+        entries.push(IgnoreEntry { entry, reason });
+    }
+    Ok(entries)
+}
+
+/// Warn about ignore entries that expire within --ignore-expiry-warning-days of `as_of`.
+fn warn_expiring_ignores(cli: &Cli, ignores: &[IgnoreEntry], as_of: NaiveDate) {
+    for e in ignores {
+        let Some(date) = e.entry.expires.filter(|d| *d >= as_of) else {
+            continue;
+        };
+        let days = (date - as_of).num_days();
+        if days <= cli.ignore_expiry_warning_days {
+            diag!(
+                "Warning: ignore entry '{}' expires on {} (in {} day(s)); re-review it.",
+                e.entry.spec,
+                date,
+                days
+            );
+        }
+    }
+}
+
+/// Move matches covered by an ignore entry out of `matches`: a name entry
+/// waives every version, an exact entry only that version. Entries whose
+/// `expires:` date is before `as_of` waive nothing. Returns the waived
+/// matches, the ignore entries that waived nothing, and the expired entries
+/// that would have waived a match.
+fn apply_ignores<'a>(
+    matches: &mut Vec<MatchRecord>,
+    ignores: &'a [IgnoreEntry],
+    as_of: NaiveDate,
+) -> (Vec<SuppressedMatch>, Vec<&'a str>, Vec<&'a IgnoreEntry>) {
+    let mut used = vec![false; ignores.len()];
+    let mut expired_hits = vec![false; ignores.len()];
+    let mut waived = Vec::new();
+    for m in std::mem::take(matches) {
+        let mut ignored_by = None;
+        for (i, e) in ignores.iter().enumerate() {
+            let hit = match &e.entry.entry {
+                CompEntry::Name(name) => *name == m.name,
+                CompEntry::Exact { name, version } => *name == m.name && *version == m.version,
+                _ => false,
+            };
+            if !hit {
+                continue;
+            }
+            if e.entry.expires.is_some_and(|d| d < as_of) {
+                expired_hits[i] = true;
+            } else {
+                used[i] = true;
+                ignored_by.get_or_insert(e);
+            }
+        }
+        match ignored_by {
+            Some(e) => waived.push(SuppressedMatch {
+                record: m,
+                ignored_by: e.entry.spec.clone(),
+                reason: e.reason.clone(),
+                expires: e.entry.expires,
+            }),
+            None => matches.push(m),
+        }
+    }
+    let unused = ignores
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !used[*i] && !expired_hits[*i])
+        .map(|(_, e)| e.entry.spec.as_str())
+        .collect();
+    let expired = ignores
+        .iter()
+        .zip(expired_hits)
+        .filter(|(_, hit)| *hit)
+        .map(|(e, _)| e)
+        .collect();
+    (waived, unused, expired)
+}
+
+/// Run `--exit-hook` through the shell with `report` on stdin and return its
+/// exit code. The hook's stdout goes to stderr so the report stays clean.
+/// Failing to spawn it, or it dying from a signal, is an error.
+fn run_exit_hook(hook: &str, report: &str) -> Result<i32> {
+    use std::process::Stdio;
+    let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+    let mut child = Command::new(shell)
+        .args([flag, hook])
+        .stdin(Stdio::piped())
+        .stdout(io::stderr())
+        .spawn()
+        .context("could not start it")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A hook that ignores its input may exit before reading it all.
+        if let Err(e) = stdin.write_all(report.as_bytes()) {
+            if e.kind() != io::ErrorKind::BrokenPipe {
+                return Err(e.into());
+            }
+        }
+    }
+    match child.wait()?.code() {
+        Some(code @ 0..=255) => Ok(code),
+        Some(code) => Err(anyhow!("exit code {} is outside 0-255", code)),
+        None => Err(anyhow!("terminated by a signal")),
+    }
+}
+
+/// Highest severity among gating matches, counting unannotated entries as `DEFAULT_SEVERITY`.
+fn highest_severity(gating: &[&MatchRecord]) -> Severity {
+    gating
+        .iter()
+        .map(|m| m.severity.unwrap_or(DEFAULT_SEVERITY))
+        .max()
+        .unwrap_or(DEFAULT_SEVERITY)
+}
+
+/// One line for `--explain-exit`, e.g.
+/// `exiting 42: 2 gating match(es) (2 exact) of 3 reported; 1 transitive excluded (--fail-on-direct-only); policy=any`.
+fn explain_exit(
+    cli: &Cli,
+    code: i32,
+    matches: &[MatchRecord],
+    gating: &[&MatchRecord],
+    fail_duplicates: bool,
+) -> String {
+    let mut by_type: BTreeMap<&str, usize> = BTreeMap::new();
+    for m in gating {
+        *by_type.entry(m.match_type.as_str()).or_default() += 1;
+    }
+    let mut line = format!("exiting {}: {} gating match(es)", code, gating.len());
+    if !by_type.is_empty() {
+        let types: Vec<String> = by_type.iter().map(|(t, n)| format!("{n} {t}")).collect();
+        write!(line, " ({})", types.join(", ")).ok();
+    }
+    write!(line, " of {} reported", matches.len()).ok();
+
+    let excluded = [
+        (
+            matches.iter().filter(|m| m.below_floor.is_some()).count(),
+            "below a floor entry",
+        ),
+        (
+            matches
+                .iter()
+                .filter(|m| !cli.fail_on_advisory && m.match_type.starts_with(ADVISORY_PREFIX))
+                .count(),
+            "advisory",
+        ),
+        (
+            matches
+                .iter()
+                .filter(|m| cli.skip_unreachable && m.reachable == Some(false))
+                .count(),
+            "unpublished (--skip-unreachable)",
+        ),
+        (
+            matches
+                .iter()
+                .filter(|m| cli.fail_on_direct_only && !m.direct)
+                .count(),
+            "transitive (--fail-on-direct-only)",
+        ),
+    ];
+    for (count, reason) in excluded.iter().filter(|(count, _)| *count > 0) {
+        write!(line, "; {count} excluded as {reason}").ok();
+    }
+    let warned = matches.iter().filter(|m| is_warning_only(cli, m)).count();
+    if warned > 0 {
+        write!(line, "; {warned} warning-only (--fail-on {})", cli.fail_on).ok();
+    }
+
+    if cli.exit_bitmask {
+        line.push_str("; policy=bitmask");
+    } else if cli.fail_code_from_severity && !gating.is_empty() {
+        write!(
+            line,
+            "; policy=severity (highest {})",
+            highest_severity(gating).as_str()
+        )
+        .ok();
+    } else {
+        match cli.fail_on_distinct {
+            Some(threshold) => write!(
+                line,
+                "; policy=distinct > {} ({} distinct)",
+                threshold,
+                distinct_package_count(gating.iter().copied())
+            ),
+            None => write!(line, "; policy=any"),
+        }
+        .ok();
+    }
+    if fail_duplicates {
+        line.push_str("; duplicate packages found (--fail-on-duplicates)");
+    }
+    line
+}
+
+/// Parse `critical=40,high=30,...` for --severity-exit-map.
+fn parse_severity_exit_map(s: &str) -> std::result::Result<BTreeMap<Severity, i32>, String> {
+    s.split(',')
+        .map(|pair| {
+            let (sev, code) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected severity=code, got '{pair}'"))?;
+            let sev = Severity::parse(sev.trim())
+                .ok_or_else(|| format!("unknown severity '{}'", sev.trim()))?;
+            let code = code
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid exit code '{}'", code.trim()))?;
+            Ok((sev, code))
+        })
+        .collect()
+}
+
+/// Read --trusted-scopes-file: one scope per line, with or without its `@`;
+/// blank lines and `#` comments are skipped.
+fn parse_trusted_scopes_file(path: &str) -> std::result::Result<HashSet<String>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("cannot read {path}: {e}"))?;
+    let mut scopes = HashSet::new();
+    for (lineno, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let scope = line.strip_prefix('@').unwrap_or(line);
+        if scope.is_empty() || scope.contains(['/', '@']) || scope.contains(char::is_whitespace) {
+            return Err(format!("line {}: '{}' is not a scope", lineno + 1, line));
+        }
+        scopes.insert(format!("@{scope}"));
+    }
+    Ok(scopes)
+}
+
+/// `name@version -> <integrity>` lines, keyed by `name@version`. The integrity is
+/// a Subresource Integrity string as npm writes it, one or more `<alg>-<digest>`.
+fn parse_known_good_file(path: &str) -> std::result::Result<HashMap<String, String>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("cannot read {path}: {e}"))?;
+    let mut known_good = HashMap::new();
+    for (lineno, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |reason: &str| format!("line {}: '{}' ({})", lineno + 1, line, reason);
+        let (spec, integrity) = line
+            .split_once("->")
+            .ok_or_else(|| invalid("expected `name@version -> <integrity>`"))?;
+        let CompEntry::Exact { name, version } = parse_compromised_entry(spec.trim(), '@') else {
+            return Err(invalid("not a name@version"));
+        };
+        let integrity = integrity.trim();
+        if integrity.is_empty() || !integrity.split_whitespace().all(|h| h.contains('-')) {
+            return Err(invalid("integrity is not `<alg>-<digest>`"));
+        }
+        known_good.insert(format!("{name}@{version}"), integrity.to_string());
+    }
+    Ok(known_good)
+}
+
+/// Whether two integrity strings agree: they share at least one algorithm,
+/// and every shared algorithm has the same digest.
+fn integrity_agrees(expected: &str, actual: &str) -> bool {
+    let actual: Vec<(&str, &str)> =
+        actual.split_whitespace().filter_map(|h| h.split_once('-')).collect();
+    let mut shared = false;
+    for (alg, digest) in expected.split_whitespace().filter_map(|h| h.split_once('-')) {
+        for (_, actual_digest) in actual.iter().filter(|(a, _)| *a == alg) {
+            if *actual_digest != digest {
+                return false;
+            }
+            shared = true;
+        }
+    }
+    shared
+}
+
+/// Installed lockfile packages listed in `known_good` whose integrity differs
+/// from (or is missing instead of) the known-good value, one per name@version.
+fn find_signature_mismatches(
+    locked: &[LockedPackage],
+    known_good: &HashMap<String, String>,
+) -> Vec<MatchRecord> {
+    let mut found: BTreeMap<String, MatchRecord> = BTreeMap::new();
+    for p in locked {
+        let key = format!("{}@{}", p.name, p.version);
+        let Some(expected) = known_good.get(&key) else {
+            continue;
+        };
+        if p.integrity.as_deref().is_some_and(|actual| integrity_agrees(expected, actual)) {
+            continue;
+        }
+        let dep = Dep {
+            name: p.name.clone(),
+            version: p.version.clone(),
+            direct: p.direct,
+            kind: p.kind,
+        };
+        let record = found.entry(key).or_insert_with(|| {
+            let mut m = MatchRecord::new("signature-mismatch", &dep, None);
+            m.integrity = p.integrity.clone();
+            m.expected_integrity = Some(expected.clone());
+            m
+        });
+        record.direct |= p.direct;
+    }
+    found.into_values().collect()
+}
+
+/// Lockfile packages whose integrity shares a listed hash (for that
+/// name@version, if the entry is pinned): `integrity` when the full digest is
+/// listed, `integrity-prefix` when only its start is. One match per
+/// name@version; other rules matching it are reconciled by `keep_strongest`.
+fn find_integrity_matches(
+    locked: &[LockedPackage],
+    lists: &CompromisedList,
+    opts: &MatchOptions,
+) -> Vec<MatchRecord> {
+    let mut found: BTreeMap<(String, String), MatchRecord> = BTreeMap::new();
+    for p in locked {
+        let Some(actual) = p.integrity.as_deref() else {
+            continue;
+        };
+        let hit = lists.integrities.iter().find(|listed| listed.matches(&p.name, &p.version, actual));
+        let Some(listed) = hit else {
+            continue;
+        };
+        let dep = Dep {
+            name: p.name.clone(),
+            version: p.version.clone(),
+            direct: p.direct,
+            kind: p.kind,
+        };
+        let record = found.entry((p.name.clone(), p.version.clone())).or_insert_with(|| {
+            let match_type = if listed.is_full() { "integrity" } else { "integrity-prefix" };
+            let mut m = MatchRecord::new(match_type, &dep, Some(listed.spec()));
+            m.integrity = Some(actual.to_string());
+            m.resolved = p.resolved.clone();
+            lists.annotate(&mut m, opts.show_upgrade_risk);
+            m
+        });
+        record.direct |= p.direct;
+    }
+    found.into_values().collect()
+}
+
+/// Lockfile packages whose `resolved` URL lies under none of the allowed
+/// registries, one per name@version. Local `file:` packages are not checked.
+fn find_registry_matches(locked: &[LockedPackage], allowed: &[String]) -> Vec<MatchRecord> {
+    fn normalize(url: &str) -> String {
+        let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+        url.trim_end_matches('/').to_ascii_lowercase()
+    }
+    let allowed: Vec<String> = allowed.iter().map(|url| normalize(url)).collect();
+    let mut found: BTreeMap<(String, String), MatchRecord> = BTreeMap::new();
+    for p in locked {
+        let Some(resolved) = p.resolved.as_deref().filter(|r| !r.starts_with("file:")) else {
+            continue;
+        };
+        let url = normalize(resolved);
+        let permitted = allowed.iter().any(|registry| {
+            url.strip_prefix(registry.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        });
+        if permitted {
+            continue;
+        }
+        let dep = Dep {
+            name: p.name.clone(),
+            version: p.version.clone(),
+            direct: p.direct,
+            kind: p.kind,
+        };
+        let record = found.entry((p.name.clone(), p.version.clone())).or_insert_with(|| {
+            let mut m = MatchRecord::new("registry", &dep, None);
+            m.resolved = Some(resolved.to_string());
+            m
+        });
+        record.direct |= p.direct;
+    }
+    found.into_values().collect()
+}
+
+/// Lockfile packages whose integrity is also recorded for a package of a
+/// different name, one per name@version, naming the others in `entry`.
+/// Legitimate packages never share a tarball, so this needs no list.
+fn find_integrity_collisions(locked: &[LockedPackage]) -> Vec<MatchRecord> {
+    let mut by_integrity: BTreeMap<&str, BTreeMap<(&str, &str), &LockedPackage>> = BTreeMap::new();
+    for p in locked {
+        if let Some(integrity) = p.integrity.as_deref() {
+            let sharing = by_integrity.entry(integrity).or_default();
+            let first = sharing.entry((&p.name, &p.version)).or_insert(p);
+            if p.direct && !first.direct {
+                *first = p;
+            }
+        }
+    }
+    let mut found = Vec::new();
+    for (integrity, sharing) in by_integrity {
+        let names: BTreeSet<&str> = sharing.keys().map(|(name, _)| *name).collect();
+        if names.len() < 2 {
+            continue;
+        }
+        for (&(name, version), p) in &sharing {
+            let others: Vec<String> = sharing
+                .keys()
+                .filter(|(other, _)| *other != name)
+                .map(|(other, v)| format!("{other}@{v}"))
+                .collect();
+            let dep = Dep {
+                name: name.to_string(),
+                version: version.to_string(),
+                direct: p.direct,
+                kind: p.kind,
+            };
+            let mut m = MatchRecord::new("integrity-collision", &dep, Some(others.join(", ")));
+            m.integrity = Some(integrity.to_string());
+            m.resolved = p.resolved.clone();
+            found.push(m);
+        }
+    }
+    found
+}
+
+fn parse_syslog_facility(s: &str) -> std::result::Result<syslog::Facility, String> {
+    s.parse().map_err(|_| format!("unknown syslog facility '{s}'"))
+}
+
+/// Log one message per match; exact and range matches at `err`, others at `warning`.
+/// Syslog being unreachable is reported on stderr and never fails the scan.
+fn send_to_syslog(matches: &[MatchRecord], facility: syslog::Facility) {
+    let formatter = syslog::Formatter3164 {
+        facility,
+        process: env!("CARGO_PKG_NAME").to_string(),
+        ..Default::default()
+    };
+    let mut logger = match syslog::unix(formatter) {
+        Ok(logger) => logger,
+        Err(e) => {
+            diag!("Warning: could not connect to syslog: {e}");
+            return;
+        }
+    };
+    for m in matches {
+        let msg = format!(
+            "compromised dependency {}@{} ({} match)",
+            m.name, m.version, m.match_type
+        );
+        let sent = match m.match_type.as_str() {
+            "exact" | "range" | "signature-mismatch" | "integrity" | "integrity-prefix" => {
+                logger.err(msg)
+            }
+            _ => logger.warning(msg),
+        };
+        if let Err(e) = sent {
+            diag!("Warning: failed to send match to syslog: {e}");
+            return;
+        }
+    }
+}
+
+/// FNV-1a (64-bit) over the sorted `type\tname\tversion` lines of the matches,
+/// as 16 hex digits. Stable across runs and platforms; not a cryptographic hash.
+fn match_set_hash(matches: &[MatchRecord]) -> String {
+    let lines: BTreeSet<String> = matches
+        .iter()
+        .map(|m| format!("{}\t{}\t{}\n", m.match_type, m.name, m.version))
+        .collect();
+    format!("{:016x}", fnv1a64(lines.into_iter().collect::<String>().as_bytes()))
+}
+
+/// Minimum spacing between registry lookups for `--verify-reachable`.
+const REGISTRY_LOOKUP_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+
+/// Set `reachable` on each match from `npm view <name>@<version>`, looking up
+/// each (name, version) once. Lookups that fail for reasons other than a 404
+/// leave `reachable` unset and print a warning.
+fn verify_reachable(matches: &mut [MatchRecord]) {
+    let mut cache: HashMap<(String, String), Option<bool>> = HashMap::new();
+    let mut last_lookup: Option<std::time::Instant> = None;
+    for m in matches.iter_mut() {
+        let key = (m.name.clone(), m.version.clone());
+        if let Some(&known) = cache.get(&key) {
+            m.reachable = known;
+            continue;
+        }
+        if let Some(elapsed) = last_lookup.map(|t| t.elapsed()) {
+            std::thread::sleep(REGISTRY_LOOKUP_INTERVAL.saturating_sub(elapsed));
+        }
+        last_lookup = Some(std::time::Instant::now());
+        let reachable = match registry_has_version(&m.name, &m.version) {
+            Ok(found) => Some(found),
+            Err(e) => {
+                diag!("Warning: could not verify {}@{}: {e}", m.name, m.version);
+                None
+            }
+        };
+        m.reachable = reachable;
+        cache.insert(key, reachable);
+    }
+}
+
+/// True if the registry still serves `name@version`.
+fn registry_has_version(name: &str, version: &str) -> Result<bool> {
+    let spec = format!("{name}@{version}");
+    let output = Command::new("npm")
+        .args(["view", &spec, "version", "--json"])
+        .output()
+        .context("Failed to execute `npm view`")?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if output.status.success() {
+        // An unknown version of a known package prints nothing.
+        return Ok(!stdout.trim().is_empty());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if stderr.contains("E404") || stdout.contains("E404") {
+        return Ok(false);
+    }
+    Err(anyhow!(
+        "`npm view {}` exited with {:?}",
+        spec,
+        output.status.code()
+    ))
+}
+
+/// Number of distinct package names among the matches, regardless of version.
+fn distinct_package_count<'a>(matches: impl Iterator<Item = &'a MatchRecord>) -> usize {
+    matches.map(|m| m.name.as_str()).collect::<HashSet<_>>().len()
+}
+
+/// Bit assignments for `--exit-bitmask`. Exit codes are 0-255, so at most 8 categories;
+/// every integrity finding shares bit 2, both kinds of source finding bit 7, and
+/// pattern matches bit 0 with name matches.
+const EXIT_BITS: &[(&str, u8)] = &[
+    ("name", 0),
+    ("pattern", 0),
+    ("exact", 1),
+    ("signature-mismatch", 2),
+    ("integrity", 2),
+    ("integrity-prefix", 2),
+    ("range", 3),
+    ("duplicate", 4),
+    ("basename", 5),
+    ("confusable", 6),
+    ("repo", 7),
+    ("registry", 7),
+    ("integrity-collision", 2),
+];
+
+/// Whether a match counts towards the exit code under the gating options.
+fn is_gating(cli: &Cli, m: &MatchRecord) -> bool {
+    passes_gating_filters(cli, m) && fail_on_covers(cli, m)
+}
+
+/// A match that passes every gating filter but --fail-on, so it only warns.
+fn is_warning_only(cli: &Cli, m: &MatchRecord) -> bool {
+    passes_gating_filters(cli, m) && !fail_on_covers(cli, m)
+}
+
+fn passes_gating_filters(cli: &Cli, m: &MatchRecord) -> bool {
+    m.below_floor.is_none()
+        && (cli.fail_on_advisory || !m.match_type.starts_with(ADVISORY_PREFIX))
+        && (!cli.skip_unreachable || m.reachable != Some(false))
+        && (!cli.fail_on_direct_only || m.direct)
+}
+
+/// Whether --fail-on lets this kind of match fail the scan.
+fn fail_on_covers(cli: &Cli, m: &MatchRecord) -> bool {
+    match cli.fail_on.as_str() {
+        "exact" => is_version_confirmed(&m.match_type),
+        "name" => !is_version_confirmed(&m.match_type),
+        "none" => false,
+        _ => true,
+    }
+}
+
+/// Match types that confirm the installed version is affected, as opposed to
+/// name-only findings (name, pattern, basename, confusable, repo).
+fn is_version_confirmed(match_type: &str) -> bool {
+    let category = match_type.strip_prefix(ADVISORY_PREFIX).unwrap_or(match_type);
+    matches!(
+        category,
+        "exact" | "range" | "signature-mismatch" | "integrity" | "integrity-prefix"
+    )
+}
+
+/// Match types from most to least specific; advisory matches rank below all of them.
+const MATCH_CONFIDENCE: [&str; 12] = [
+    "signature-mismatch",
+    "integrity",
+    "exact",
+    "integrity-prefix",
+    "range",
+    "repo",
+    "registry",
+    "integrity-collision",
+    "pattern",
+    "name",
+    "basename",
+    "confusable",
+];
+
+/// Keep one match per package name: a gating one if any, then the highest
+/// severity, then the most specific match type. Order is otherwise preserved.
+fn collapse_per_package(cli: &Cli, matches: Vec<MatchRecord>) -> Vec<MatchRecord> {
+    let rank = |m: &MatchRecord| {
+        let advisory = m.match_type.starts_with(ADVISORY_PREFIX);
+        let category = m.match_type.trim_start_matches(ADVISORY_PREFIX);
+        let specificity = MATCH_CONFIDENCE
+            .iter()
+            .position(|t| *t == category)
+            .map_or(0, |i| MATCH_CONFIDENCE.len() - i);
+        (
+            is_gating(cli, m),
+            m.severity.unwrap_or(DEFAULT_SEVERITY),
+            !advisory,
+            specificity,
+        )
+    };
+    let mut best: HashMap<&str, usize> = HashMap::new();
+    for (i, m) in matches.iter().enumerate() {
+        match best.get(m.name.as_str()) {
+            Some(&j) if rank(&matches[j]) >= rank(m) => {}
+            _ => {
+                best.insert(&m.name, i);
+            }
+        }
+    }
+    let keep: HashSet<usize> = best.into_values().collect();
+    matches
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| keep.contains(i))
+        .map(|(_, m)| m)
+        .collect()
+}
+
+fn exit_bit(category: &str) -> i32 {
+    let category = category.strip_prefix(ADVISORY_PREFIX).unwrap_or(category);
+    EXIT_BITS
+        .iter()
+        .find(|(c, _)| *c == category)
+        .map(|(_, bit)| 1 << bit)
+        .unwrap_or(0)
+}
+
+/// Collect deps from the selected input, keeping the npm tree when there is one.
+/// `long` requests `npm ls --long`, whose per-node metadata some options need.
+fn load_deps(cli: &Cli, long: bool) -> Result<(Vec<Dep>, Option<Value>)> {
+    if let Some(path) = &cli.index {
+        let deps = read_dep_index(path)
+            .context(format!("Failed to load dependency index: {:?}", path))?;
+        return Ok((deps, None));
+    }
+    if let Some(path) = &cli.require_cache {
+        let deps = collect_require_cache_deps(path)
+            .context(format!("Failed to load require.cache dump: {:?}", path))?;
+        return Ok((deps, None));
+    }
+    if let Some(path) = &cli.tarball {
+        let deps = collect_tarball_deps(path, cli.verbose)
+            .context(format!("Failed to read tarball: {:?}", path))?;
+        return Ok((deps, None));
+    }
+    if let Some(path) = &cli.deps_json {
+        let deps = collect_generic_deps(path)
+            .context(format!("Failed to load dependency list: {:?}", path))?;
+        return Ok((deps, None));
+    }
+    if !cli.package_lock.is_empty() {
+        let per_file = parse_inputs(cli, sorted_inputs(&cli.package_lock), |path| {
+            collect_package_lock_deps(path, &cli.scan_scope)
+                .context(format!("Failed to load package lock: {:?}", path))
+        })?;
+        return Ok((merge_dep_sets(per_file), None));
+    }
+    if let Some(path) = &cli.yarn_lock {
+        if cli.scan_scope != "all" {
+            diag!(
+                "Warning: yarn.lock does not record dependency types; --scan-scope {} is ignored.",
+                cli.scan_scope
+            );
+        }
+        let deps = collect_yarn_lock_deps(path)
+            .context(format!("Failed to load yarn lock: {:?}", path))?;
+        return Ok((deps, None));
+    }
+    if let Some(path) = &cli.pnpm_lock {
+        if cli.scan_scope != "all" {
+            diag!(
+                "Warning: pnpm-lock.yaml is scanned whole; --scan-scope {} is ignored.",
+                cli.scan_scope
+            );
+        }
+        let deps = collect_pnpm_lock_deps(path)
+            .context(format!("Failed to load pnpm lock: {:?}", path))?;
+        return Ok((deps, None));
+    }
+    if let Some(dir) = &cli.scan_node_modules {
+        if cli.scan_scope != "all" {
+            diag!(
+                "Warning: node_modules does not record dependency types; --scan-scope {} is ignored.",
+                cli.scan_scope
+            );
+        }
+        let installed = collect_node_modules_packages(dir)
+            .context(format!("Failed to scan node_modules: {:?}", dir))?;
+        // The tree is only a cross-check here; without one the disk scan stands alone.
+        let tree = if !cli.npm_json.is_empty() || !cli.no_run_npm {
+            match load_npm_tree_json(cli, long) {
+                Ok((tree, _)) => Some(tree),
+                Err(e) => {
+                    diag!(
+                        "Warning: no npm ls tree to compare node_modules against ({:#})",
+                        e
+                    );
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        if let Some(tree) = &tree {
+            report_node_modules_discrepancies(&installed, &collect_deps(tree));
+        }
+        return Ok((dedup_installed_packages(installed), tree));
+    }
+    if cli.npm_json.len() > 1 {
+        if cli.npm_json.iter().any(|src| src == "-") {
+            return Err(anyhow!("--npm-json - cannot be combined with other --npm-json files"));
+        }
+        // Trees from several files are not merged, only their deps.
+        let per_file = parse_inputs(cli, sorted_inputs(&cli.npm_json), |src| {
+            let (value, ok) = read_npm_json_file(cli, src)?;
+            deps_from_npm_json(cli, value, ok).map(|(deps, _)| deps)
+        })?;
+        return Ok((merge_dep_sets(per_file), None));
+    }
+    let (mut npm_json_value, mut npm_ok) = load_npm_tree_json(cli, long)?;
+    if cli.retry_on_empty > 0 && !cli.global && std::path::Path::new("node_modules").is_dir() {
+        // A flaky npm can print an empty tree for a populated node_modules.
+        let empty = |v: &Value| match v.as_array() {
+            Some(items) => items.is_empty(),
+            None => collect_deps(v).is_empty(),
+        };
+        let mut retries = 0;
+        while empty(&npm_json_value) && retries < cli.retry_on_empty {
+            retries += 1;
+            diag!(
+                "npm found no dependencies although node_modules exists; retrying ({}/{}).",
+                retries, cli.retry_on_empty
+            );
+            (npm_json_value, npm_ok) = run_npm_json(cli, None, long, None)?;
+        }
+        if retries > 0 {
+            let count = match npm_json_value.as_array() {
+                Some(items) => items.len(),
+                None => collect_deps(&npm_json_value).len(),
+            };
+            if count == 0 {
+                diag!(
+                    "npm still reported no dependencies after {} retry(ies); treating the project as having none.",
+                    retries
+                );
+            } else {
+                diag!("npm reported {} dependencies after {} retry(ies).", count, retries);
+            }
+        }
+    }
+    deps_from_npm_json(cli, npm_json_value, npm_ok)
+}
+
+/// Inputs sorted by path, so merged results do not depend on argument order.
+fn sorted_inputs<T: Ord>(inputs: &[T]) -> Vec<&T> {
+    let mut sorted: Vec<&T> = inputs.iter().collect();
+    sorted.sort();
+    sorted
+}
+
+/// Run `parse` on every input, in order or, with --parallel-parse, on up to
+/// --jobs worker threads. Results keep the order of `inputs`; the first
+/// failure, in that order, is returned.
+fn parse_inputs<I: Sync, T: Send>(
+    cli: &Cli,
+    inputs: Vec<&I>,
+    parse: impl Fn(&I) -> Result<T> + Sync,
+) -> Result<Vec<T>> {
+    if !cli.parallel_parse || inputs.len() < 2 {
+        return inputs.into_iter().map(parse).collect();
+    }
+    let jobs = cli
+        .jobs
+        .map(usize::from)
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()))
+        .min(inputs.len());
+    let next = std::sync::atomic::AtomicUsize::new(0);
+    let results: Vec<std::sync::Mutex<Option<Result<T>>>> =
+        inputs.iter().map(|_| std::sync::Mutex::new(None)).collect();
+    std::thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                let Some(input) = inputs.get(i) else {
+                    break;
+                };
+                let result = parse(input);
+                *results[i].lock().unwrap_or_else(|e| e.into_inner()) = Some(result);
+            });
+        }
+    });
+    results
+        .into_iter()
+        .map(|slot| {
+            slot.into_inner()
+                .unwrap_or_else(|e| e.into_inner())
+                .expect("every input is parsed")
+        })
+        .collect()
+}
+
+/// Union of several dep sets: one dep per name and version, direct if it is
+/// direct in any input, its kind merged across inputs, sorted.
+fn merge_dep_sets(sets: Vec<Vec<Dep>>) -> Vec<Dep> {
+    let mut merged: BTreeMap<(String, String), (bool, DepKind)> = BTreeMap::new();
+    for d in sets.into_iter().flatten() {
+        let (direct, kind) = merged.entry((d.name, d.version)).or_insert((false, d.kind));
+        *direct |= d.direct;
+        *kind = kind.merge(d.kind);
+    }
+    merged
+        .into_iter()
+        .map(|((name, version), (direct, kind))| Dep {
+            name,
+            version,
+            direct,
+            kind,
+        })
+        .collect()
+}
+
+/// Which input the dependencies came from and how it was parsed.
+#[derive(Debug, Clone, serde::Serialize)]
+struct SourceRecord {
+    input: String,
+    format: &'static str,
+    parser_version: &'static str,
+    dep_count: usize,
+}
+
+/// Describe the input `load_deps` read for these flags (or the manifest, when
+/// `--prefilter-manifest` skipped the tree).
+fn describe_source(cli: &Cli, manifest_clean: bool, dep_count: usize) -> SourceRecord {
+    let path = |p: &PathBuf| p.display().to_string();
+    let (input, format) = if manifest_clean {
+        (path(&cli.package_json), "manifest-prefilter")
+    } else if let Some(p) = &cli.index {
+        (path(p), "index")
+    } else if let Some(p) = &cli.require_cache {
+        (path(p), "require-cache")
+    } else if let Some(p) = &cli.tarball {
+        (path(p), "tarball")
+    } else if let Some(p) = &cli.deps_json {
+        (path(p), "deps-json")
+    } else if !cli.package_lock.is_empty() {
+        let inputs: Vec<String> = sorted_inputs(&cli.package_lock).into_iter().map(path).collect();
+        (inputs.join(", "), "package-lock")
+    } else if let Some(p) = &cli.yarn_lock {
+        (path(p), "yarn-lock")
+    } else if let Some(p) = &cli.pnpm_lock {
+        (path(p), "pnpm-lock")
+    } else if let Some(p) = &cli.scan_node_modules {
+        (path(p), "node-modules")
+    } else {
+        let input = match cli.npm_json.as_slice() {
+            [src] if src == "-" => "stdin".to_string(),
+            [_, ..] => {
+                let inputs: Vec<&str> =
+                    sorted_inputs(&cli.npm_json).into_iter().map(String::as_str).collect();
+                inputs.join(", ")
+            }
+            [] => {
+                let args = if cli.npm_query { "query '*' --json" } else { "ls --all --json" };
+                let global = if cli.global { " -g" } else { "" };
+                let (cmd, rest) = args.split_once(' ').unwrap();
+                format!("`npm {cmd}{global} {rest}`")
+            }
+        };
+        (input, if cli.npm_query { "npm-query" } else { "npm-ls" })
+    };
+    SourceRecord {
+        input,
+        format,
+        parser_version: env!("CARGO_PKG_VERSION"),
+        dep_count,
+    }
+}
+
+/// The scans merged into the report besides the project's own (`--include-global`,
+/// `--npx-cache`), each with the `origin` its matches are tagged with.
+fn extra_scans(cli: &Cli, long: bool) -> Result<Vec<(&'static str, Vec<Dep>, SourceRecord)>> {
+    let mut scans = Vec::new();
+    let record = |input: String, format, dep_count| SourceRecord {
+        input,
+        format,
+        parser_version: env!("CARGO_PKG_VERSION"),
+        dep_count,
+    };
+    if cli.include_global {
+        let global = Cli {
+            global: true,
+            npm_json: Vec::new(),
+            root_key: DEFAULT_ROOT_KEY.to_string(),
+            ..cli.clone()
+        };
+        let (value, ok) = run_npm_json(&global, None, long, None)?;
+        let (deps, _) = deps_from_npm_json(&global, value, ok)?;
+        let source = record("`npm ls -g --all --json`".to_string(), "npm-ls", deps.len());
+        scans.push(("global", deps, source));
+    }
+    if cli.npx_cache {
+        let dir = npx_cache_dir(cli)?;
+        if dir.is_dir() {
+            let deps = collect_npx_cache_deps(&dir)
+                .context(format!("Failed to scan the npx cache: {:?}", dir))?;
+            let source = record(dir.display().to_string(), "npx-cache", deps.len());
+            scans.push(("npx-cache", deps, source));
+        } else {
+            diag!("Note: no npx cache at {:?}; nothing to scan there.", dir);
+        }
+    }
+    Ok(scans)
+}
+
+/// `_npx` in the npm cache: --npm-cache-dir, else `npm_config_cache`, else
+/// npm's default for the platform.
+fn npx_cache_dir(cli: &Cli) -> Result<PathBuf> {
+    let env_dir = |var: &str| {
+        std::env::var_os(var)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+    };
+    let cache = match (&cli.npm_cache_dir, env_dir("npm_config_cache")) {
+        (Some(dir), _) => dir.clone(),
+        (None, Some(dir)) => dir,
+        (None, None) if cfg!(windows) => env_dir("LOCALAPPDATA")
+            .context("LOCALAPPDATA is not set; pass --npm-cache-dir")?
+            .join("npm-cache"),
+        (None, None) => env_dir("HOME")
+            .context("HOME is not set; pass --npm-cache-dir")?
+            .join(".npm"),
+    };
+    Ok(cache.join("_npx"))
+}
+
+/// Packages in each `<npx cache>/<hash>/node_modules`, where npx installs what
+/// it runs; the packages the `<hash>/package.json` beside it asks for are direct.
+fn collect_npx_cache_deps(dir: &std::path::Path) -> Result<Vec<Dep>> {
+    let mut installs: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path().join("node_modules"))
+        .filter(|p| p.is_dir())
+        .collect();
+    installs.sort();
+    let mut installed = Vec::new();
+    for node_modules in installs {
+        match collect_node_modules_packages(&node_modules) {
+            Ok(found) => installed.extend(found),
+            Err(e) => diag!("Warning: skipping {}: {}", node_modules.display(), e),
+        }
+    }
+    Ok(dedup_installed_packages(installed))
+}
+
+/// Collect deps from npm output, enforcing `--fail-on-incomplete-tree`.
+fn deps_from_npm_json(
+    cli: &Cli,
+    npm_json_value: Value,
+    npm_ok: bool,
+) -> Result<(Vec<Dep>, Option<Value>)> {
+    let deps = if cli.npm_query {
+        collect_npm_query_deps(&npm_json_value, &cli.scan_scope)?
+    } else {
+        collect_deps_in_scope(&npm_json_value, &cli.scan_scope)
+    };
+    if cli.scan_scope != "all" && !has_dependency_type_flags(&npm_json_value) {
+        diag!(
+            "Warning: --scan-scope {} found no dev/optional/peer flags in the npm output; \
+             provide `npm ls --all --long --json` output",
+            cli.scan_scope
+        );
+    }
+    if cli.fail_on_incomplete_tree && !npm_ok && deps.len() < cli.min_deps {
+        return Err(anyhow!(
+            "npm reported a failure and only {} dependencies were read (--min-deps {}); \
+             refusing to report a possibly incomplete tree as clean",
+            deps.len(),
+            cli.min_deps
+        ));
+    }
+    let tree = (!cli.npm_query).then_some(npm_json_value);
+    Ok((deps, tree))
+}
+
+/// Parse `--advisory-list`, if given.
+fn load_advisory_list(cli: &Cli) -> Result<Option<CompromisedList>> {
+    let Some(path) = &cli.advisory_list else {
+        return Ok(None);
+    };
+    let advisory = parse_compromised_file(path, &parse_options(cli))
+        .context(format!("Failed to parse advisory list: {:?}", path))?;
+    Ok(Some(advisory))
+}
+
+/// Advisory list matches, typed `advisory-<type>`, for the deps that `matches`
+/// from the deny list do not already cover.
+fn find_advisory_matches(
+    deps: &[Dep],
+    advisory: &CompromisedList,
+    matches: &[MatchRecord],
+    opts: &MatchOptions,
+) -> Vec<MatchRecord> {
+    let denied: HashSet<(&str, &str)> = matches
+        .iter()
+        .map(|m| (m.name.as_str(), m.version.as_str()))
+        .collect();
+    let mut found = find_matches_with(deps, advisory, opts);
+    found.retain(|m| !denied.contains(&(m.name.as_str(), m.version.as_str())));
+    for m in &mut found {
+        m.match_type = format!("{ADVISORY_PREFIX}{}", m.match_type);
+    }
+    found
+}
+
+/// A match merged across `--scan-dir` directories.
+#[derive(Debug, serde::Serialize)]
+struct MergedMatchRecord {
+    match_type: String,
+    name: String,
+    version: String,
+    directories: Vec<String>, // sorted
+}
+
+/// The --ignore-file and --baseline of a --scan-dir or --recursive scan,
+/// read once and applied to the matches of each directory or project.
+struct ProjectFilters {
+    ignores: Vec<IgnoreEntry>,
+    baseline: Option<BTreeSet<BaselineEntry>>,
+    as_of: NaiveDate,
+}
+
+impl ProjectFilters {
+    fn load(cli: &Cli) -> Result<Self> {
+        let as_of = parse_options(cli).as_of;
+        let ignores = match &cli.ignore_file {
+            Some(path) => parse_ignore_file(path, cli.list_separator)
+                .context(format!("Failed to parse ignore file: {:?}", path))?,
+            None => Vec::new(),
+        };
+        warn_expiring_ignores(cli, &ignores, as_of);
+        let baseline = match &cli.baseline {
+            Some(path) => {
+                Some(read_baseline(path).context(format!("Failed to read baseline: {:?}", path))?)
+            }
+            None => None,
+        };
+        Ok(Self {
+            ignores,
+            baseline,
+            as_of,
+        })
+    }
+
+    /// Drop the matches of `dir` that a single-project scan leaves out of its
+    /// report (--omit, --ignore-file, --baseline, --min-occurrences), then
+    /// apply --redact-versions. Installs are counted in `tree`, or else in `lockfiles`.
+    fn apply(
+        &self,
+        cli: &Cli,
+        dir: &std::path::Path,
+        tree: Option<&Value>,
+        lockfiles: &[PathBuf],
+        matches: &mut Vec<MatchRecord>,
+    ) -> Result<()> {
+        matches.retain(|m| !m.dependency_kind.is_omitted(&cli.omit));
+        let (_, _, expired) = apply_ignores(matches, &self.ignores, self.as_of);
+        for e in expired {
+            diag!(
+                "Warning: ignore entry '{}' expired on {}; its matches in {} are reported again.",
+                e.entry.spec,
+                e.entry.expires.unwrap_or_default(),
+                dir.display()
+            );
+        }
+        if let Some(baseline) = &self.baseline {
+            matches.retain(|m| !baseline.contains(&BaselineEntry::of(m)));
+        }
+        filter_min_occurrences(cli, tree, lockfiles, matches)?;
+        if cli.redact_versions {
+            matches.iter_mut().for_each(redact_match);
+        }
+        Ok(())
+    }
+}
+
+/// Matches for one --scan-dir directory, or `None` if its npm run timed out.
+fn scan_directory(
+    cli: &Cli,
+    dir: &PathBuf,
+    lists: &CompromisedList,
+    advisory: Option<&CompromisedList>,
+    filters: &ProjectFilters,
+    opts: &MatchOptions,
+    timeout: Option<std::time::Duration>,
+) -> Result<Option<Vec<MatchRecord>>> {
+    let long = wants_long_output(cli, Some(lists));
+    let (value, ok) = match run_npm_json(cli, Some(dir), long, timeout) {
+        Err(e) if e.is::<NpmTimeout>() => return Ok(None),
+        result => result.context(format!("Failed to scan directory: {:?}", dir))?,
+    };
+    let (mut deps, tree) = deps_from_npm_json(cli, value, ok)
+        .context(format!("Failed to scan directory: {:?}", dir))?;
+    if cli.top_level_only {
+        deps.retain(|d| d.direct);
+    }
+    let mut matches = find_matches_with(&deps, lists, opts);
+    if let Some(tree) = &tree {
+        matches.extend(find_repo_matches(tree, &deps, lists, &matches, opts));
+    }
+    if let Some(advisory) = advisory {
+        matches.extend(find_advisory_matches(&deps, advisory, &matches, opts));
+    }
+    if !opts.match_all_rules {
+        matches = keep_strongest(matches);
+    }
+    filters
+        .apply(cli, dir, tree.as_ref(), &[], &mut matches)
+        .context(format!("Failed to scan directory: {:?}", dir))?;
+    Ok(Some(matches))
+}
+
+/// Append one --stream-output line for a finished directory. Each line is
+/// written with a single call under the lock, so parallel scans never interleave.
+fn append_stream_record(
+    stream: &std::sync::Mutex<fs::File>,
+    dir: &std::path::Path,
+    result: &Result<Option<Vec<MatchRecord>>>,
+) -> Result<()> {
+    let (status, matches, error) = match result {
+        Ok(Some(matches)) => ("ok", &matches[..], None),
+        Ok(None) => ("timed_out", &[][..], None),
+        Err(e) => ("error", &[][..], Some(format!("{e:#}"))),
+    };
+    #[derive(serde::Serialize)]
+    struct StreamRecord<'a> {
+        directory: String,
+        status: &'a str,
+        finished_at: String,
+        match_count: usize,
+        matches: &'a [MatchRecord],
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    }
+    let record = StreamRecord {
+        directory: dir.display().to_string(),
+        status,
+        finished_at: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        match_count: matches.len(),
+        matches,
+        error,
+    };
+    let mut line = serde_json::to_string(&record)?;
+    line.push('\n');
+    let mut file = stream.lock().unwrap_or_else(|e| e.into_inner());
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Scan every directory on its own thread, then merge. Records are keyed by
+/// (type, name, version, directory) in ordered maps, so the output does not
+/// depend on which thread finishes first. A directory whose npm run exceeds
+/// `--per-dir-timeout` is reported as timed out (`None`) instead of failing the batch.
+fn run_dir_scan(dirs: &[PathBuf], lists: &CompromisedList, cli: &Cli) -> Result<()> {
+    if !matches!(cli.format.as_str(), "text" | "json") {
+        return Err(anyhow!(
+            "--scan-dir supports --format text or json, not {}",
+            cli.format
+        ));
+    }
+    let opts = match_options(cli);
+    let advisory = load_advisory_list(cli)?;
+    let filters = ProjectFilters::load(cli)?;
+    let timeout = cli.per_dir_timeout.map(std::time::Duration::from_secs);
+    let stream = match &cli.stream_output {
+        Some(path) => Some(std::sync::Mutex::new(
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .context(format!("Failed to open stream output: {:?}", path))?,
+        )),
+        None => None,
+    };
+    type DirResult = Result<(String, Option<Vec<MatchRecord>>)>;
+    let results: Vec<DirResult> = std::thread::scope(|scope| {
+        let handles: Vec<_> = dirs
+            .iter()
+            .map(|dir| {
+                let opts = &opts;
+                let advisory = advisory.as_ref();
+                let filters = &filters;
+                let stream = stream.as_ref();
+                scope.spawn(move || {
+                    let matches = scan_directory(cli, dir, lists, advisory, filters, opts, timeout);
+                    if let Some(stream) = stream {
+                        append_stream_record(stream, dir, &matches)
+                            .context("Failed to write stream output")?;
+                    }
+                    Ok((dir.display().to_string(), matches?))
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("directory scan thread panicked"))
+            .collect()
+    });
+
+    let mut by_directory: BTreeMap<String, BTreeMap<(String, String, String), MatchRecord>> =
+        BTreeMap::new();
+    let mut timed_out = BTreeSet::new();
+    for result in results {
+        let (dir, matches) = result?;
+        let Some(matches) = matches else {
+            timed_out.insert(dir);
+            continue;
+        };
+        let records = by_directory.entry(dir).or_default();
+        for m in matches {
+            let key = (m.match_type.clone(), m.name.clone(), m.version.clone());
+            records.entry(key).or_insert(m);
+        }
+    }
+    let mut merged: BTreeMap<(String, String, String), BTreeSet<String>> = BTreeMap::new();
+    for (dir, records) in &by_directory {
+        for key in records.keys() {
+            merged.entry(key.clone()).or_default().insert(dir.clone());
+        }
+    }
+    let merged: Vec<MergedMatchRecord> = merged
+        .into_iter()
+        .map(|((match_type, name, version), dirs)| MergedMatchRecord {
+            match_type,
+            name,
+            version,
+            directories: dirs.into_iter().collect(),
+        })
+        .collect();
+    let by_directory: BTreeMap<String, Vec<MatchRecord>> = by_directory
+        .into_iter()
+        .map(|(dir, records)| (dir, records.into_values().collect()))
+        .collect();
+
+    let mut rendered = String::new();
+    match cli.format.as_str() {
+        "json" => {
+            #[derive(serde::Serialize)]
+            struct DirScanOutput<'a> {
+                matches: &'a [MergedMatchRecord],
+                match_count: usize,
+                directories_scanned: usize,
+                by_directory: &'a BTreeMap<String, Vec<MatchRecord>>,
+                #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+                timed_out: &'a BTreeSet<String>,
+            }
+            let out = DirScanOutput {
+                matches: &merged,
+                match_count: merged.len(),
+                directories_scanned: by_directory.len(),
+                by_directory: &by_directory,
+                timed_out: &timed_out,
+            };
+            writeln!(rendered, "{}", serde_json::to_string_pretty(&out)?)?;
+        }
+        _ => {
+            for (dir, records) in &by_directory {
+                writeln!(rendered, "{dir}:")?;
+                if records.is_empty() {
+                    writeln!(rendered, "  No compromised dependencies found.")?;
+                }
+                for m in records {
+                    writeln!(rendered, "  {}", format_text_match(m))?;
+                }
+            }
+            for dir in &timed_out {
+                writeln!(rendered, "{dir}:")?;
+                writeln!(
+                    rendered,
+                    "  Timed out after {}s; not scanned.",
+                    cli.per_dir_timeout.unwrap_or_default()
+                )?;
+            }
+            writeln!(
+                rendered,
+                "{} distinct match(es) across {} director(ies).",
+                merged.len(),
+                by_directory.len()
+            )?;
+        }
+    }
+    match &cli.output {
+        Some(path) => fs::write(path, &rendered)
+            .context(format!("Failed to write output file: {:?}", path))?,
+        None => print!("{rendered}"),
+    }
+    let fail = by_directory.values().flatten().any(|m| is_gating(cli, m));
+    if fail {
+        output::exit(cli.fail_exit_code);
+    }
+    if !timed_out.is_empty() {
+        // An unscanned directory must not pass as clean.
+        return Err(anyhow!(
+            "{} director(ies) timed out and were not scanned",
+            timed_out.len()
+        ));
+    }
+    Ok(())
+}
+
+/// Directories at or below `root` that contain a package.json, sorted.
+/// `node_modules` and symlinked directories are not entered.
+fn find_projects(root: &std::path::Path) -> Result<Vec<PathBuf>> {
+    let mut projects = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        if dir.join("package.json").is_file() {
+            projects.push(dir.clone());
+        }
+        for entry in fs::read_dir(&dir).context(format!("Failed to read directory: {:?}", dir))? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() && entry.file_name() != "node_modules" {
+                pending.push(entry.path());
+            }
+        }
+    }
+    projects.sort();
+    Ok(projects)
+}
+
+/// One project of a --recursive scan.
+#[derive(Debug, serde::Serialize)]
+struct ProjectResult {
+    project: String,       // path relative to the --recursive directory
+    source: &'static str,  // "package-lock" or "npm-ls"
+    match_count: usize,
+    matches: Vec<MatchRecord>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Integrity, registry, integrity-collision, and --known-good signature
+/// matches, which need the integrity and resolved URLs only `lockfiles` record.
+/// The lockfiles are not read when none of these checks is asked for.
+fn find_lockfile_matches(
+    cli: &Cli,
+    lists: &CompromisedList,
+    lockfiles: &[PathBuf],
+    opts: &MatchOptions,
+) -> Result<Vec<MatchRecord>> {
+    let checks_lockfile = cli.known_good.is_some()
+        || !lists.integrities.is_empty()
+        || !cli.allowed_registries.is_empty()
+        || cli.detect_integrity_collisions;
+    let mut matches = Vec::new();
+    if !checks_lockfile || lockfiles.is_empty() {
+        return Ok(matches);
+    }
+    let mut locked = Vec::new();
+    for path in sorted_inputs(lockfiles) {
+        locked.extend(
+            read_package_lock(path, &cli.scan_scope)
+                .context(format!("Failed to load package lock: {:?}", path))?,
+        );
+    }
+    if !lists.integrities.is_empty() {
+        matches.extend(find_integrity_matches(&locked, lists, opts));
+    }
+    if !cli.allowed_registries.is_empty() {
+        matches.extend(find_registry_matches(&locked, &cli.allowed_registries));
+    }
+    if cli.detect_integrity_collisions {
+        matches.extend(find_integrity_collisions(&locked));
+    }
+    if let Some(known_good) = &cli.known_good {
+        matches.extend(find_signature_mismatches(&locked, known_good));
+    }
+    Ok(matches)
+}
+
+/// Matches for a --recursive project from its package-lock.json, with the
+/// checks and filters `--package-lock` gets in a single scan.
+fn scan_lockfile(
+    cli: &Cli,
+    lockfile: &PathBuf,
+    lists: &CompromisedList,
+    advisory: Option<&CompromisedList>,
+    filters: &ProjectFilters,
+    opts: &MatchOptions,
+) -> Result<Vec<MatchRecord>> {
+    let mut deps = collect_package_lock_deps(lockfile, &cli.scan_scope)
+        .context(format!("Failed to parse lockfile: {:?}", lockfile))?;
+    if cli.top_level_only {
+        deps.retain(|d| d.direct);
+    }
+    let mut matches = find_matches_with(&deps, lists, opts);
+    if let Some(advisory) = advisory {
+        matches.extend(find_advisory_matches(&deps, advisory, &matches, opts));
+    }
+    let lockfiles = std::slice::from_ref(lockfile);
+    matches.extend(find_lockfile_matches(cli, lists, lockfiles, opts)?);
+    if !opts.match_all_rules {
+        matches = keep_strongest(matches);
+    }
+    let dir = lockfile.parent().unwrap_or(lockfile);
+    filters.apply(cli, dir, None, lockfiles, &mut matches)?;
+    Ok(matches)
+}
+
+/// Matches for one project: from its package-lock.json when it has one,
+/// otherwise from `npm ls` run in the project directory.
+fn scan_project(
+    cli: &Cli,
+    dir: &PathBuf,
+    lists: &CompromisedList,
+    advisory: Option<&CompromisedList>,
+    filters: &ProjectFilters,
+    opts: &MatchOptions,
+) -> (&'static str, Result<Vec<MatchRecord>>) {
+    let lockfile = dir.join("package-lock.json");
+    if lockfile.is_file() {
+        let result = scan_lockfile(cli, &lockfile, lists, advisory, filters, opts);
+        return ("package-lock", result);
+    }
+    let result = scan_directory(cli, dir, lists, advisory, filters, opts, None)
+        .map(Option::unwrap_or_default);
+    ("npm-ls", result)
+}
+
+fn run_recursive_scan(root: &PathBuf, lists: &CompromisedList, cli: &Cli) -> Result<()> {
+    if !matches!(cli.format.as_str(), "text" | "json") {
+        return Err(anyhow!(
+            "--recursive supports --format text or json, not {}",
+            cli.format
+        ));
+    }
+    let projects = find_projects(root)?;
+    if projects.is_empty() {
+        return Err(anyhow!("No package.json found under {:?}", root));
+    }
+    let opts = match_options(cli);
+    let advisory = load_advisory_list(cli)?;
+    let filters = ProjectFilters::load(cli)?;
+    let mut results = Vec::new();
+    for dir in &projects {
+        let project = match dir.strip_prefix(root) {
+            Ok(rel) if rel.as_os_str().is_empty() => ".".to_string(),
+            Ok(rel) => rel.display().to_string(),
+            Err(_) => dir.display().to_string(),
+        };
+        let (source, result) = scan_project(cli, dir, lists, advisory.as_ref(), &filters, &opts);
+        let (matches, error) = match result {
+            Ok(matches) => (matches, None),
+            Err(e) if cli.strict => {
+                return Err(e.context(format!("Project {project} failed (--strict)")));
+            }
+            Err(e) => {
+                diag!("Warning: could not scan project {project}: {e:#}");
+                (Vec::new(), Some(format!("{e:#}")))
+            }
+        };
+        results.push(ProjectResult {
+            project,
+            source,
+            match_count: matches.len(),
+            matches,
+            error,
+        });
+    }
+
+    let match_count: usize = results.iter().map(|p| p.match_count).sum();
+    let failed = results.iter().filter(|p| p.error.is_some()).count();
+    let mut rendered = String::new();
+    match cli.format.as_str() {
+        "json" => {
+            #[derive(serde::Serialize)]
+            struct RecursiveOutput<'a> {
+                projects: &'a [ProjectResult],
+                match_count: usize,
+                projects_scanned: usize,
+                projects_failed: usize,
+            }
+            let out = RecursiveOutput {
+                projects: &results,
+                match_count,
+                projects_scanned: results.len() - failed,
+                projects_failed: failed,
+            };
+            writeln!(rendered, "{}", serde_json::to_string_pretty(&out)?)?;
+        }
+        _ => {
+            for p in &results {
+                writeln!(rendered, "{} ({}):", p.project, p.source)?;
+                if let Some(error) = &p.error {
+                    writeln!(rendered, "  Not scanned: {error}")?;
+                } else if p.matches.is_empty() {
+                    writeln!(rendered, "  No compromised dependencies found.")?;
+                }
+                for m in &p.matches {
+                    writeln!(rendered, "  {}", format_text_match(m))?;
+                }
+            }
+            writeln!(
+                rendered,
+                "{} match(es) across {} project(s).",
+                match_count,
+                results.len()
+            )?;
+        }
+    }
+    match &cli.output {
+        Some(path) => fs::write(path, &rendered)
+            .context(format!("Failed to write output file: {:?}", path))?,
+        None => print!("{rendered}"),
+    }
+
+    let fail = results
+        .iter()
+        .flat_map(|p| &p.matches)
+        .any(|m| is_gating(cli, m));
+    if fail {
+        output::exit(cli.fail_exit_code);
+    }
+    if failed > 0 {
+        // An unscanned project must not pass as clean.
+        return Err(anyhow!("{} project(s) could not be scanned", failed));
+    }
+    Ok(())
+}
+
+#[derive(Debug, serde::Serialize)]
+struct ListDiffRecord {
+    change: &'static str, // "added", "removed", or "changed"
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    old: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    new: Option<String>,
+}
+
+/// Effective rules per package name, as text: "any version" for name-only,
+/// otherwise the sorted exact versions and ranges. An exact or range entry
+/// implies the name entry, so `foo` alongside `foo@1.0.0` is the same as `foo@1.0.0`.
+fn effective_rules(lists: &CompromisedList) -> BTreeMap<String, String> {
+    let mut versions: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for (name, version) in &lists.exact {
+        versions.entry(name).or_default().insert(version);
+    }
+    for (name, range) in &lists.ranges {
+        versions.entry(name).or_default().insert(&range.raw);
+    }
+    lists
+        .names
+        .iter()
+        .map(|name| {
+            let rule = match versions.get(name.as_str()) {
+                Some(v) => v.iter().copied().collect::<Vec<_>>().join(" || "),
+                None => "any version".to_string(),
+            };
+            (name.clone(), rule)
+        })
+        .collect()
+}
+
+/// Semantic diff between two list files, keyed by package name.
+fn run_list_diff(old_path: &PathBuf, new_path: &PathBuf, cli: &Cli) -> Result<()> {
+    let opts = parse_options(cli);
+    let old = parse_compromised_file(old_path, &opts)
+        .context(format!("Failed to parse compromised list: {:?}", old_path))?;
+    let new = parse_compromised_file(new_path, &opts)
+        .context(format!("Failed to parse compromised list: {:?}", new_path))?;
+    let old_rules = effective_rules(&old);
+    let new_rules = effective_rules(&new);
+
+    let names: BTreeSet<&String> = old_rules.keys().chain(new_rules.keys()).collect();
+    let mut diff = Vec::new();
+    for name in names {
+        let (o, n) = (old_rules.get(name), new_rules.get(name));
+        let change = match (o, n) {
+            (None, Some(_)) => "added",
+            (Some(_), None) => "removed",
+            (Some(o), Some(n)) if o != n => "changed",
+            _ => continue,
+        };
+        diff.push(ListDiffRecord {
+            change,
+            name: name.clone(),
+            old: o.cloned(),
+            new: n.cloned(),
+        });
+    }
+
+    match cli.format.as_str() {
+        "json" => println!("{}", serde_json::to_string_pretty(&diff)?),
+        _ => {
+            if diff.is_empty() {
+                println!("No effective changes between the lists.");
+            }
+            for d in &diff {
+                match d.change {
+                    "added" => println!("+ {} ({})", d.name, d.new.as_deref().unwrap_or_default()),
+                    "removed" => {
+                        println!("- {} ({})", d.name, d.old.as_deref().unwrap_or_default())
+                    }
+                    _ => println!(
+                        "~ {}: {} -> {}",
+                        d.name,
+                        d.old.as_deref().unwrap_or_default(),
+                        d.new.as_deref().unwrap_or_default()
+                    ),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, serde::Serialize)]
+struct ListVersionReport {
+    current: Option<String>,
+    available: Option<String>,
+    newer_available: bool,
+}
+
+/// Report whether the list at `url` is newer than the local `--list`, using a
+/// HEAD request so the list itself is not downloaded.
+fn run_check_list_version(url: &str, cli: &Cli) -> Result<()> {
+    let [list_file] = cli.list_files.as_slice() else {
+        return Err(anyhow!("--check-list-version compares a single --list"));
+    };
+    let local = fs::read_to_string(list_file)
+        .context(format!("Unable to read compromised list file: {:?}", list_file))?;
+    let current = list_version_header(&local);
+    let available = fetch_list_source_version(url)?;
+    let report = ListVersionReport {
+        newer_available: available.is_some() && available != current,
+        current,
+        available,
+    };
+
+    match cli.format.as_str() {
+        "json" => println!("{}", serde_json::to_string_pretty(&report)?),
+        _ => {
+            let or_unknown = |v: &Option<String>| v.clone().unwrap_or_else(|| "unknown".into());
+            println!("Current list version:   {}", or_unknown(&report.current));
+            println!("Available list version: {}", or_unknown(&report.available));
+            if report.available.is_none() {
+                println!("The list source exposes no ETag or Last-Modified header.");
+            } else if report.newer_available {
+                println!("A newer list is available; refresh {:?}.", list_file);
+            } else {
+                println!("The local list is up to date.");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Value of a `# version: <v>` comment line in a list file, if any.
+fn list_version_header(content: &str) -> Option<String> {
+    content.lines().find_map(|line| {
+        let comment = line.trim().strip_prefix('#')?.trim();
+        let version = comment.strip_prefix("version:")?.trim();
+        (!version.is_empty()).then(|| version.to_string())
+    })
+}
+
+/// ETag (preferred) or Last-Modified of `url`, via `curl --head`.
+fn fetch_list_source_version(url: &str) -> Result<Option<String>> {
+    let output = Command::new("curl")
+        .args(["--silent", "--show-error", "--fail", "--location", "--head", url])
+        .output()
+        .context("Failed to execute `curl`")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "HEAD request to {} failed: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    // With --location every hop's headers are printed; only the last response counts.
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    let start = lines.iter().rposition(|l| l.starts_with("HTTP/")).unwrap_or(0);
+    let header = |wanted: &str| {
+        lines[start..].iter().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.trim()
+                .eq_ignore_ascii_case(wanted)
+                .then(|| value.trim().to_string())
+        })
+    };
+    Ok(header("etag").or_else(|| header("last-modified")))
+}
+
+/// Combine list sources (files, or http(s) URLs fetched with curl) into one
+/// canonical list: entries rewritten as `name`, `name@version`, `name@range`,
+/// or `repo:url`, deduplicated, and sorted. When sources disagree on an
+/// entry's `expires:` date, the entry lives as long as its longest-lived copy;
+/// when they disagree on `severity:`, the highest wins, and `safe:` versions are combined.
+fn merge_list_sources(sources: &[String], cli: &Cli) -> Result<String> {
+    struct MergedEntry<'a> {
+        expires: Option<NaiveDate>, // None: never
+        severity: Option<Severity>,
+        safe: BTreeSet<String>,
+        sources: BTreeSet<&'a str>,
+    }
+    // by canonical entry
+    let mut merged: BTreeMap<String, MergedEntry> = BTreeMap::new();
+    for source in sources {
+        let content = if is_remote_list(source) {
+            fetch_url_text(source)?
+        } else {
+            fs::read_to_string(source)
+                .context(format!("Unable to read compromised list file: {:?}", source))?
+        };
+        for (lineno, raw_line) in content.lines().enumerate() {
+            let line = raw_line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason: String| {
+                anyhow!(
+                    "Invalid entry in {} at line {}: '{}' ({})",
+                    source,
+                    lineno + 1,
+                    line,
+                    reason
+                )
+            };
+            let (spec, annotations) = split_entry_annotations(line);
+            let EntryAnnotations {
+                expires,
+                severity,
+                safe,
+            } = parse_entry_annotations(annotations).map_err(invalid)?;
+            let canonical = match parse_compromised_entry(spec, cli.list_separator) {
+                CompEntry::Name(name) => name,
+                CompEntry::Exact { name, version } => format!("{name}@{version}"),
+                CompEntry::Range { name, range } => {
+                    let raw: Vec<&str> = range.raw.split_whitespace().collect();
+                    format!("{name}@{}", raw.join(" "))
+                }
+                CompEntry::Repo(url) => format!("repo:{url}"),
+                CompEntry::Integrity(integrity) => format!("integrity:{}", integrity.spec()),
+                CompEntry::Pattern(pattern) => pattern.spec(),
+                CompEntry::Invalid(reason) => return Err(invalid(reason)),
+            };
+            match merged.get_mut(&canonical) {
+                Some(existing) => {
+                    existing.expires = match (existing.expires, expires) {
+                        (Some(a), Some(b)) => Some(a.max(b)),
+                        _ => None,
+                    };
+                    existing.severity = existing.severity.max(severity);
+                    existing.safe.extend(safe);
+                    existing.sources.insert(source);
+                }
+                None => {
+                    let entry = MergedEntry {
+                        expires,
+                        severity,
+                        safe: safe.into_iter().collect(),
+                        sources: BTreeSet::from([source.as_str()]),
+                    };
+                    merged.insert(canonical, entry);
+                }
+            }
+        }
+    }
+
+    let mut out = format!(
+        "# Merged by {} from {} source(s); {} entries\n",
+        env!("CARGO_PKG_NAME"),
+        sources.len(),
+        merged.len()
+    );
+    for (spec, entry) in &merged {
+        if cli.provenance {
+            let from: Vec<&str> = entry.sources.iter().copied().collect();
+            writeln!(out, "# from: {}", from.join(", ")).ok();
+        }
+        let mut line = spec.clone();
+        if let Some(severity) = entry.severity {
+            write!(line, " severity:{}", severity.as_str()).ok();
+        }
+        if !entry.safe.is_empty() {
+            let safe: Vec<&str> = entry.safe.iter().map(String::as_str).collect();
+            write!(line, " safe:{}", safe.join(",")).ok();
+        }
+        if let Some(date) = entry.expires {
+            write!(line, " expires:{date}").ok();
+        }
+        writeln!(out, "{line}").ok();
+    }
+    Ok(out)
+}
+
+/// Overall limit for one `fetch_url_text` download.
+const URL_FETCH_TIMEOUT_SECS: u64 = 60;
+
+/// Whether a list source is downloaded (http(s) or s3 URL) rather than read from disk.
+fn is_remote_list(source: &str) -> bool {
+    ["http://", "https://", "s3://"].iter().any(|scheme| source.starts_with(scheme))
+}
+
+/// GET `url` with curl and return the body; non-2xx responses are errors.
+/// `s3://` URLs go to `fetch_s3_text` instead.
+fn fetch_url_text(url: &str) -> Result<String> {
+    if url.starts_with("s3://") {
+        return fetch_s3_text(url);
+    }
+    let max_time = URL_FETCH_TIMEOUT_SECS.to_string();
+    let output = Command::new("curl")
+        .args(["--silent", "--show-error", "--fail", "--location"])
+        .args(["--max-time", &max_time, url])
+        .output()
+        .context("Failed to execute `curl`")?;
+    // curl's exit code for --max-time expiring
+    if output.status.code() == Some(28) {
+        return Err(anyhow!(
+            "Download of {} timed out after {}s",
+            url,
+            URL_FETCH_TIMEOUT_SECS
+        ));
+    }
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Download of {} failed: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    String::from_utf8(output.stdout).context(format!("{} is not valid UTF-8", url))
+}
+
+/// Download an `s3://bucket/key` object with the AWS CLI, which finds
+/// credentials the usual way (environment, profile, SSO, instance role).
+/// Missing credentials and denied access get errors saying so.
+fn fetch_s3_text(url: &str) -> Result<String> {
+    let timeout = URL_FETCH_TIMEOUT_SECS.to_string();
+    let output = Command::new("aws")
+        .args(["s3", "cp", url, "-", "--only-show-errors"])
+        .args(["--cli-connect-timeout", &timeout, "--cli-read-timeout", &timeout])
+        .output()
+        .context("Failed to execute `aws` (the AWS CLI is needed for s3:// lists)")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = if stderr.contains("Unable to locate credentials")
+            || stderr.contains("NoCredentials")
+        {
+            "no AWS credentials found; set AWS_PROFILE or AWS_ACCESS_KEY_ID, or use a role"
+                .to_string()
+        } else if ["AccessDenied", "Forbidden", "(403)", "ExpiredToken", "InvalidAccessKeyId"]
+            .iter()
+            .any(|code| stderr.contains(code))
+        {
+            format!("access denied; the AWS credentials in use cannot read it ({})", stderr.trim())
+        } else {
+            stderr.trim().to_string()
+        };
+        return Err(anyhow!("Download of {} failed: {}", url, reason));
+    }
+    String::from_utf8(output.stdout).context(format!("{} is not valid UTF-8", url))
+}
+
+/// Merge every SARIF file matching `pattern` into one SARIF 2.1.0 document.
+///
+/// Runs are combined per tool driver name: rules are unioned by `id` and
+/// identical results are kept once. Results are otherwise left untouched, so
+/// their locations (and logical locations) still identify where each came
+/// from; `ruleIndex` is dropped because rule order differs after merging.
+fn merge_sarif_files(pattern: &str) -> Result<Value> {
+    let mut paths: Vec<PathBuf> = glob::glob(pattern)
+        .context(format!("Invalid glob pattern: {}", pattern))?
+        .collect::<std::result::Result<_, _>>()?;
+    paths.sort();
+    if paths.is_empty() {
+        return Err(anyhow!("No SARIF files match '{}'", pattern));
+    }
+
+    #[derive(Default)]
+    struct MergedRun {
+        driver: Value,
+        rules: BTreeMap<String, Value>, // by rule id
+        results: Vec<Value>,
+        seen: HashSet<String>, // serialized results already kept
+    }
+    let mut runs: BTreeMap<String, MergedRun> = BTreeMap::new();
+    for path in &paths {
+        let data =
+            fs::read_to_string(path).context(format!("Failed to read SARIF file: {:?}", path))?;
+        let doc: Value = serde_json::from_str(&data)
+            .context(format!("Failed to parse SARIF file: {:?}", path))?;
+        let file_runs = doc
+            .get("runs")
+            .and_then(|r| r.as_array())
+            .ok_or_else(|| anyhow!("SARIF file has no runs array: {:?}", path))?;
+        for run in file_runs {
+            let driver = run.pointer("/tool/driver").cloned().unwrap_or(json!({}));
+            let driver_name = driver
+                .get("name")
+                .and_then(|n| n.as_str())
+                .unwrap_or("unknown")
+                .to_string();
+            let merged = runs.entry(driver_name).or_insert_with(|| MergedRun {
+                driver: driver.clone(),
+                ..Default::default()
+            });
+            for rule in driver.get("rules").and_then(|r| r.as_array()).into_iter().flatten() {
+                if let Some(id) = rule.get("id").and_then(|i| i.as_str()) {
+                    merged.rules.entry(id.to_string()).or_insert_with(|| rule.clone());
+                }
+            }
+            for result in run.get("results").and_then(|r| r.as_array()).into_iter().flatten() {
+                let mut result = result.clone();
+                if let Some(obj) = result.as_object_mut() {
+                    obj.remove("ruleIndex");
+                }
+                if merged.seen.insert(result.to_string()) {
+                    merged.results.push(result);
+                }
+            }
+        }
+    }
+
+    let merged_runs: Vec<Value> = runs
+        .into_values()
+        .map(|mut run| {
+            if let Some(obj) = run.driver.as_object_mut() {
+                obj.insert("rules".into(), Value::Array(run.rules.into_values().collect()));
+            }
+            json!({ "tool": { "driver": run.driver }, "results": run.results })
+        })
+        .collect();
+    Ok(json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": merged_runs,
+    }))
+}
+
+/// Magic bytes at the start of a dependency index file.
+const INDEX_MAGIC: &[u8; 8] = b"NCSINDEX";
+/// Bumped whenever the serialized `Dep` layout changes.
+const INDEX_FORMAT_VERSION: u32 = 2;
+
+/// Write deps as `INDEX_MAGIC`, a little-endian format version, then bincode.
+fn write_dep_index(path: &PathBuf, deps: &[Dep]) -> Result<()> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(INDEX_MAGIC);
+    buf.extend_from_slice(&INDEX_FORMAT_VERSION.to_le_bytes());
+    bincode::serialize_into(&mut buf, deps).context("Failed to encode dependency index")?;
+    fs::write(path, buf).context(format!("Failed to write index file: {:?}", path))
+}
+
+fn read_dep_index(path: &PathBuf) -> Result<Vec<Dep>> {
+    let data = fs::read(path)?;
+    let header_len = INDEX_MAGIC.len() + 4;
+    if data.len() < header_len || &data[..INDEX_MAGIC.len()] != INDEX_MAGIC {
+        return Err(anyhow!("Not a dependency index (bad magic)"));
+    }
+    let version = u32::from_le_bytes(data[INDEX_MAGIC.len()..header_len].try_into()?);
+    if version != INDEX_FORMAT_VERSION {
+        return Err(anyhow!(
+            "Unsupported index format version {} (expected {}); rebuild it with --build-index",
+            version,
+            INDEX_FORMAT_VERSION
+        ));
+    }
+    bincode::deserialize(&data[header_len..]).context("Corrupt dependency index")
+}
+
+/// Magic bytes at the start of a `--format bin` result.
+const RESULT_MAGIC: &[u8; 8] = b"NCSRESLT";
+/// Bumped whenever the serialized `BinResult` layout changes; the decoder
+/// reads every version up to this one.
+const RESULT_FORMAT_VERSION: u32 = 1;
+
+/// Payload of `--format bin`. Every field is always present, since bincode is
+/// not self-describing and cannot skip absent ones like the JSON output does.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct BinResult {
+    matches: Vec<BinMatch>,
+    duplicates: Option<Vec<BinDuplicate>>,
+    result_hash: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct BinMatch {
+    match_type: String,
+    name: String,
+    version: String,
+    direct: bool,
+    entry: Option<String>,
+    below_floor: Option<String>,
+    skeleton: Option<String>,
+    reachable: Option<bool>,
+    severity: Option<Severity>,
+    origin: String,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct BinDuplicate {
+    name: String,
+    versions: Vec<String>,
+}
+
+/// Write `RESULT_MAGIC`, a little-endian format version, then the bincode `BinResult`.
+fn encode_bin_result(
+    matches: &[MatchRecord],
+    duplicates: Option<&[DuplicateRecord]>,
+    result_hash: Option<&str>,
+) -> Result<Vec<u8>> {
+    let result = BinResult {
+        matches: matches
+            .iter()
+            .map(|m| BinMatch {
+                match_type: m.match_type.clone(),
+                name: m.name.clone(),
+                version: m.version.clone(),
+                direct: m.direct,
+                entry: m.entry.clone(),
+                below_floor: m.below_floor.clone(),
+                skeleton: m.skeleton.clone(),
+                reachable: m.reachable,
+                severity: m.severity,
+                origin: m.origin.to_string(),
+            })
+            .collect(),
+        duplicates: duplicates.map(|dups| {
+            dups.iter()
+                .map(|d| BinDuplicate {
+                    name: d.name.clone(),
+                    versions: d.versions.clone(),
+                })
+                .collect()
+        }),
+        result_hash: result_hash.map(str::to_string),
+    };
+    let mut buf = Vec::new();
+    buf.extend_from_slice(RESULT_MAGIC);
+    buf.extend_from_slice(&RESULT_FORMAT_VERSION.to_le_bytes());
+    bincode::serialize_into(&mut buf, &result).context("Failed to encode binary result")?;
+    Ok(buf)
+}
+
+fn decode_bin_result(data: &[u8]) -> Result<(u32, BinResult)> {
+    let header_len = RESULT_MAGIC.len() + 4;
+    if data.len() < header_len || &data[..RESULT_MAGIC.len()] != RESULT_MAGIC {
+        return Err(anyhow!("Not a binary scan result (bad magic)"));
+    }
+    let version = u32::from_le_bytes(data[RESULT_MAGIC.len()..header_len].try_into()?);
+    if version > RESULT_FORMAT_VERSION {
+        return Err(anyhow!(
+            "Binary result format version {} is newer than this release supports ({}); upgrade to decode it",
+            version,
+            RESULT_FORMAT_VERSION
+        ));
+    }
+    let result = bincode::deserialize(&data[header_len..]).context("Corrupt binary result")?;
+    Ok((version, result))
+}
+
+/// Magic bytes at the start of a list index file.
+const LIST_INDEX_MAGIC: &[u8; 8] = b"NCSLISTX";
+/// Bumped whenever the serialized `IndexedEntry` layout changes.
+const LIST_INDEX_FORMAT_VERSION: u32 = 7;
+
+/// A list entry as stored in a list index. Ranges keep their text and are
+/// re-parsed on load; dates are days since 0001-01-01 (chrono's CE count).
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct IndexedEntry {
+    kind: IndexedKind,
+    expires: Option<i32>,
+    severity: Option<Severity>,
+    safe: Vec<String>,
+    source: String,
+    advisory: Option<Advisory>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+enum IndexedKind {
+    Name(String),
+    Repo(String),
+    Exact { name: String, version: String },
+    Range { name: String, range: String },
+    Integrity(IntegrityEntry),
+    /// As written, re-parsed on load
+    Pattern(String),
+}
+
+/// Write entries as `LIST_INDEX_MAGIC`, a little-endian format version, the
+/// FNV-1a checksum of the payload, then the bincode payload.
+fn write_list_index(path: &PathBuf, entries: &[ListEntry]) -> Result<()> {
+    let indexed: Vec<IndexedEntry> = entries
+        .iter()
+        .map(|e| IndexedEntry {
+            kind: match &e.entry {
+                CompEntry::Name(name) => IndexedKind::Name(name.clone()),
+                CompEntry::Repo(url) => IndexedKind::Repo(url.clone()),
+                CompEntry::Exact { name, version } => IndexedKind::Exact {
+                    name: name.clone(),
+                    version: version.clone(),
+                },
+                CompEntry::Range { name, range } => IndexedKind::Range {
+                    name: name.clone(),
+                    range: range.raw.clone(),
+                },
+                CompEntry::Integrity(integrity) => IndexedKind::Integrity(integrity.clone()),
+                CompEntry::Pattern(pattern) => IndexedKind::Pattern(pattern.spec()),
+                CompEntry::Invalid(_) => unreachable!(),
+            },
+            expires: e.expires.map(|d| d.num_days_from_ce()),
+            severity: e.severity,
+            safe: e.safe.clone(),
+            source: e.source.clone(),
+            advisory: e.advisory.clone(),
+        })
+        .collect();
+    let payload = bincode::serialize(&indexed).context("Failed to encode list index")?;
+    let mut buf = Vec::new();
+    buf.extend_from_slice(LIST_INDEX_MAGIC);
+    buf.extend_from_slice(&LIST_INDEX_FORMAT_VERSION.to_le_bytes());
+    buf.extend_from_slice(&fnv1a64(&payload).to_le_bytes());
+    buf.extend_from_slice(&payload);
+    fs::write(path, buf).context(format!("Failed to write list index: {:?}", path))
+}
+
+fn read_list_index(path: &PathBuf) -> Result<Vec<ListEntry>> {
+    let data = fs::read(path)?;
+    let header_len = LIST_INDEX_MAGIC.len() + 4 + 8;
+    if data.len() < header_len || &data[..LIST_INDEX_MAGIC.len()] != LIST_INDEX_MAGIC {
+        return Err(anyhow!("Not a list index (bad magic)"));
+    }
+    let version_end = LIST_INDEX_MAGIC.len() + 4;
+    let version = u32::from_le_bytes(data[LIST_INDEX_MAGIC.len()..version_end].try_into()?);
+    if version != LIST_INDEX_FORMAT_VERSION {
+        return Err(anyhow!(
+            "Unsupported list index format version {} (expected {}); rebuild it with --build-list-index",
+            version,
+            LIST_INDEX_FORMAT_VERSION
+        ));
+    }
+    let checksum = u64::from_le_bytes(data[version_end..header_len].try_into()?);
+    let payload = &data[header_len..];
+    if fnv1a64(payload) != checksum {
+        return Err(anyhow!("List index checksum mismatch; the file is corrupt or truncated"));
+    }
+    let indexed: Vec<IndexedEntry> =
+        bincode::deserialize(payload).context("Corrupt list index")?;
+    indexed
+        .into_iter()
+        .map(|e| {
+            let (spec, entry) = match e.kind {
+                IndexedKind::Name(name) => (name.clone(), CompEntry::Name(name)),
+                IndexedKind::Repo(url) => (format!("repo:{url}"), CompEntry::Repo(url)),
+                IndexedKind::Exact { name, version } => {
+                    (format!("{name}@{version}"), CompEntry::Exact { name, version })
+                }
+                IndexedKind::Range { name, range } => {
+                    let spec = format!("{name}@{range}");
+                    let range = VersionRange::parse(&range)
+                        .map_err(|e| anyhow!("Corrupt list index: {}: {}", spec, e))?;
+                    (spec, CompEntry::Range { name, range })
+                }
+                IndexedKind::Integrity(integrity) => {
+                    (format!("integrity:{}", integrity.spec()), CompEntry::Integrity(integrity))
+                }
+                IndexedKind::Pattern(spec) => match parse_compromised_entry(&spec, '@') {
+                    CompEntry::Pattern(pattern) => (spec, CompEntry::Pattern(pattern)),
+                    _ => return Err(anyhow!("Corrupt list index: bad pattern {}", spec)),
+                },
+            };
+            let expires = match e.expires {
+                Some(days) => Some(
+                    NaiveDate::from_num_days_from_ce_opt(days)
+                        .ok_or_else(|| anyhow!("Corrupt list index: bad expiry for {}", spec))?,
+                ),
+                None => None,
+            };
+            Ok(ListEntry {
+                spec,
+                entry,
+                expires,
+                severity: e.severity,
+                safe: e.safe,
+                source: e.source,
+                advisory: e.advisory,
+            })
+        })
+        .collect()
+}
+
+/// SHA-256 (FIPS 180-4) as lowercase hex, for `list_sources` in the JSON report.
+fn sha256_hex(bytes: &[u8]) -> String {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+        0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+        0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+        0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+        0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+        0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+        0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+        0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+        0xc67178f2,
+    ];
+    let mut h: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+    let mut message = bytes.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((bytes.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = hh
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            hh = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (state, value) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+    h.iter().map(|word| format!("{word:08x}")).collect()
+}
+
+/// 64-bit FNV-1a.
+fn fnv1a64(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+#[derive(Debug, serde::Serialize)]
+struct HistoryRecord {
+    name: String,
+    version: String,
+    match_type: String,
+    first_flagged: String, // YYYY-MM-DD from the list file name
+    list_file: String,
+}
+
+/// Match the tree against every dated list in `dir` (oldest first) and report,
+/// per match, the earliest list that would have flagged it.
+fn run_list_history(dir: &PathBuf, deps: &[Dep], cli: &Cli) -> Result<()> {
+    let mut snapshots = Vec::new();
+    let entries =
+        fs::read_dir(dir).context(format!("Failed to read list directory: {:?}", dir))?;
+    for entry in entries {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        match date_in_file_name(&file_name) {
+            Some(date) => snapshots.push((date, path)),
+            None => diag!("Warning: skipping {:?} (no YYYY-MM-DD date in file name)", path),
+        }
+    }
+    snapshots.sort();
+
+    let mut first_seen: BTreeMap<(String, String), HistoryRecord> = BTreeMap::new();
+    for (date, path) in &snapshots {
+        let lists = parse_compromised_file(path, &parse_options(cli))
+            .context(format!("Failed to parse compromised list: {:?}", path))?;
+        let matches = find_matches_with(deps, &lists, &match_options(cli));
+        for m in matches {
+            first_seen
+                .entry((m.name.clone(), m.version.clone()))
+                .or_insert_with(|| HistoryRecord {
+                    name: m.name,
+                    version: m.version,
+                    match_type: m.match_type,
+                    first_flagged: date.clone(),
+                    list_file: path.display().to_string(),
+                });
+        }
+    }
+    let history: Vec<HistoryRecord> = first_seen.into_values().collect();
+
+    match cli.format.as_str() {
+        "json" => {
+            #[derive(serde::Serialize)]
+            struct HistoryOutput<'a> {
+                lists_scanned: usize,
+                history: &'a [HistoryRecord],
+            }
+            let out = HistoryOutput {
+                lists_scanned: snapshots.len(),
+                history: &history,
+            };
+            println!("{}", serde_json::to_string_pretty(&out)?);
+        }
+        _ => {
+            if history.is_empty() {
+                println!(
+                    "No dependency was flagged by any of {} list(s).",
+                    snapshots.len()
+                );
+            }
+            for h in &history {
+                println!(
+                    "{}  {}@{} ({}, {})",
+                    h.first_flagged, h.name, h.version, h.match_type, h.list_file
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// First `YYYY-MM-DD` substring of a file name, if any.
+fn date_in_file_name(name: &str) -> Option<String> {
+    name.as_bytes()
+        .windows(10)
+        .position(|w| {
+            w.iter().enumerate().all(|(i, b)| match i {
+                4 | 7 => *b == b'-',
+                _ => b.is_ascii_digit(),
+            })
+        })
+        .map(|start| name[start..start + 10].to_string())
+}
+
+/// One line of text output, e.g. `[EXACT MATCH] left-pad@1.3.0`.
+fn format_text_match(m: &MatchRecord) -> String {
+    let entry = m.entry.as_deref().unwrap_or_default();
+    let (advisory, match_type) = match m.match_type.strip_prefix(ADVISORY_PREFIX) {
+        Some(t) => (true, t),
+        None => (false, m.match_type.as_str()),
+    };
+    let mut line = match match_type {
+        "exact" if m.entry.is_some() => {
+            format!("[EXACT MATCH] {}@{} (entry {})", m.name, m.version, entry)
+        }
+        "exact" => format!("[EXACT MATCH] {}@{}", m.name, m.version),
+        "range" => format!("[RANGE MATCH] {}@{} ({})", m.name, m.version, entry),
+        "basename" => format!("[BASE MATCH ] {}@{} (entry {})", m.name, m.version, entry),
+        "pattern" => format!("[PATTERN    ] {}@{} (pattern {})", m.name, m.version, entry),
+        "repo" => format!("[REPO MATCH ] {}@{} (repo:{})", m.name, m.version, entry),
+        "registry" => format!(
+            "[REGISTRY   ] {}@{} (resolved {})",
+            m.name,
+            m.version,
+            m.resolved.as_deref().unwrap_or_default()
+        ),
+        "integrity-collision" => {
+            format!("[COLLISION  ] {}@{} (same integrity as {})", m.name, m.version, entry)
+        }
+        "integrity" | "integrity-prefix" => {
+            let (label, truncated) = match match_type {
+                "integrity" => ("[INTEGRITY  ]", ""),
+                _ => ("[INTEG PREFIX]", "..."),
+            };
+            let mut line =
+                format!("{label} {}@{} (integrity:{entry}{truncated})", m.name, m.version);
+            if let Some(url) = &m.resolved {
+                write!(line, " from {url}").ok();
+            }
+            line
+        }
+        "signature-mismatch" => format!(
+            "[SIGNATURE  ] {}@{} (integrity {}; known-good {})",
+            m.name,
+            m.version,
+            m.integrity.as_deref().unwrap_or("missing"),
+            m.expected_integrity.as_deref().unwrap_or_default()
+        ),
+        "confusable" => format!(
+            "[CONFUSABLE ] {}@{} (looks like {}; skeleton {})",
+            m.name,
+            m.version,
+            entry,
+            m.skeleton.as_deref().unwrap_or_default()
+        ),
+        _ => format!("[NAME MATCH ] {}@{}", m.name, m.version),
+    };
+    if let Some(floor) = &m.below_floor {
+        write!(line, " (below floor {floor}; not failing)").ok();
+    }
+    if m.reachable == Some(false) {
+        line.push_str(" [unpublished]");
+    }
+    if let Some(first_seen) = &m.first_seen {
+        write!(line, " [first seen {first_seen}]").ok();
+    }
+    if let Some(severity) = m.severity {
+        write!(line, " [{}]", severity.as_str()).ok();
+    }
+    if !m.advisories.is_empty() {
+        let advisories: Vec<String> = m
+            .advisories
+            .iter()
+            .map(|a| match &a.summary {
+                Some(summary) => format!("{}: {summary}", a.id),
+                None => a.id.clone(),
+            })
+            .collect();
+        write!(line, " ({})", advisories.join("; ")).ok();
+    }
+    if let Some(license) = &m.license {
+        write!(line, " (license {license})").ok();
+    }
+    match (&m.safe_version, m.upgrade_risk.as_deref()) {
+        (Some(safe), Some("downgrade")) => write!(line, " (safe: {safe}, a downgrade)").ok(),
+        (Some(safe), Some("unknown")) => write!(line, " (safe: {safe}, bump size unknown)").ok(),
+        (Some(safe), Some(risk)) => write!(line, " (safe: {safe}, {risk} upgrade)").ok(),
+        (None, Some(_)) => write!(line, " (no safe version available)").ok(),
+        _ => None,
+    };
+    if advisory {
+        line.insert_str(0, "[ADVISORY] ");
+    }
+    if m.origin != "project" {
+        write!(line, " [{}]", m.origin).ok();
+    }
+    line
+}
+
+/// Mask the version of a match for --redact-versions, including the
+/// versions its entry, tarball URL, context, and paths repeat. The upgrade
+/// advice and floor entry are dropped, since they are versions too.
+fn redact_match(m: &mut MatchRecord) {
+    if let Some(url) = &mut m.resolved {
+        *url = url.replace(
+            &format!("-{}.tgz", m.version),
+            &format!("-{REDACTED_VERSION}.tgz"),
+        );
+    }
+    m.version = REDACTED_VERSION.to_string();
+    let match_type = m
+        .match_type
+        .strip_prefix(ADVISORY_PREFIX)
+        .unwrap_or(&m.match_type);
+    if let Some(entry) = &mut m.entry {
+        match match_type {
+            // Repo URLs may hold an `@` (git@host) but never a version.
+            "repo" => {}
+            "integrity" | "integrity-prefix" => *entry = redact_integrity_spec(entry),
+            _ => *entry = redact_spec(entry),
+        }
+    }
+    m.below_floor = None;
+    m.safe_version = None;
+    m.upgrade_risk = None;
+    if let Some(ctx) = &mut m.context {
+        for spec in ctx.ancestors.iter_mut().chain(ctx.children.iter_mut()) {
+            *spec = redact_spec(spec);
+        }
+    }
+    for spec in m.paths.iter_mut().flatten() {
+        *spec = redact_spec(spec);
+    }
+}
+
+/// Replace the version in a `name@version#<alg>-<digest>` integrity entry;
+/// a bare digest names no version.
+fn redact_integrity_spec(spec: &str) -> String {
+    match spec.split_once('#') {
+        Some((package, digest)) => format!("{}#{digest}", redact_spec(package)),
+        None => spec.to_string(),
+    }
+}
+
+/// Replace the version in a `name@version` (or `@scope/name@version`) spec.
+fn redact_spec(spec: &str) -> String {
+    match spec.rfind('@').filter(|&i| i > 0) {
+        Some(i) => format!("{}@{}", &spec[..i], REDACTED_VERSION),
+        None => spec.to_string(),
+    }
+}
+
+/// Build a CycloneDX 1.5 VEX document with one vulnerability per match.
+///
+/// Exact matches are marked `exploitable`, name matches `in_triage`.
+fn render_cyclonedx_vex(matches: &[MatchRecord]) -> Value {
+    let tool = json!({
+        "type": "application",
+        "name": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+    });
+    let mut components = Vec::new();
+    let mut vulnerabilities = Vec::new();
+    for m in matches {
+        let purl = npm_purl(&m.name, &m.version);
+        let (state, detail) = match m.match_type.as_str() {
+            "exact" => ("exploitable", "Installed version is listed as compromised."),
+            "range" => (
+                "exploitable",
+                "Installed version falls within a compromised version range.",
+            ),
+            "signature-mismatch" => (
+                "exploitable",
+                "Installed integrity differs from the known-good value.",
+            ),
+            "integrity" => (
+                "exploitable",
+                "Installed integrity is listed as compromised.",
+            ),
+            "integrity-prefix" => (
+                "exploitable",
+                "Installed integrity starts with a hash prefix listed as compromised.",
+            ),
+            "registry" => (
+                "in_triage",
+                "Resolved from a registry outside --allowed-registry.",
+            ),
+            "integrity-collision" => (
+                "in_triage",
+                "Installed integrity is shared by a package of another name.",
+            ),
+            "pattern" => (
+                "in_triage",
+                "Package name matches a name pattern listed as compromised.",
+            ),
+            _ => (
+                "in_triage",
+                "Package name is listed as compromised; version not confirmed.",
+            ),
+        };
+        components.push(json!({
+            "type": "library",
+            "bom-ref": purl,
+            "name": m.name,
+            "version": m.version,
+            "purl": purl,
+        }));
+        vulnerabilities.push(json!({
+            "id": format!("{}:{}@{}", m.match_type, m.name, m.version),
+            "source": { "name": env!("CARGO_PKG_NAME") },
+            "description": format!("{}@{} matched the compromised list ({} match)", m.name, m.version, m.match_type),
+            "affects": [{ "ref": purl }],
+            "analysis": { "state": state, "detail": detail },
+        }));
+    }
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "version": 1,
+        "metadata": {
+            "tools": { "components": [tool] },
+            "authors": [{ "name": env!("CARGO_PKG_NAME") }],
+        },
+        "components": components,
+        "vulnerabilities": vulnerabilities,
+    })
+}
+
+/// SARIF 2.1.0 for GitHub code scanning: one rule per matched list entry and
+/// match type, one result per match, all located in `artifact_path` on the
+/// first line that mentions the package (line 1 if the file cannot be read).
+fn render_sarif(matches: &[MatchRecord], artifact_path: &str) -> Value {
+    let artifact = fs::read_to_string(artifact_path).unwrap_or_default();
+    let line_of = |name: &str| {
+        let quoted = format!("\"{name}\"");
+        artifact.lines().position(|l| l.contains(&quoted)).map_or(1, |i| i + 1)
+    };
+    let level = |m: &MatchRecord| {
+        if m.below_floor.is_some() || m.match_type.starts_with(ADVISORY_PREFIX) {
+            "note"
+        } else if is_version_confirmed(&m.match_type) {
+            "error"
+        } else {
+            "warning"
+        }
+    };
+    let uri = artifact_path.replace('\\', "/");
+
+    let mut rules: Vec<Value> = Vec::new();
+    let mut rule_index: HashMap<String, usize> = HashMap::new();
+    let mut results = Vec::new();
+    for m in matches {
+        let entry = CompromisedList::entry_key(m);
+        let id = format!("{}/{}", m.match_type, entry);
+        let index = *rule_index.entry(id.clone()).or_insert_with(|| {
+            rules.push(json!({
+                "id": id,
+                "shortDescription": {
+                    "text": format!("Compromised list entry {} ({} match)", entry, m.match_type),
+                },
+                "defaultConfiguration": { "level": level(m) },
+                "properties": {
+                    "match_type": m.match_type,
+                    "entry": entry,
+                    "tags": ["security", "supply-chain"],
+                },
+            }));
+            rules.len() - 1
+        });
+        results.push(json!({
+            "ruleId": id,
+            "ruleIndex": index,
+            "level": level(m),
+            "message": {
+                "text": format!(
+                    "{}@{} matched the compromised list ({} match on {})",
+                    m.name, m.version, m.match_type, entry
+                ),
+            },
+            "locations": [{
+                "physicalLocation": {
+                    "artifactLocation": { "uri": uri },
+                    "region": { "startLine": line_of(&m.name) },
+                },
+            }],
+        }));
+    }
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "semanticVersion": env!("CARGO_PKG_VERSION"),
+                    "informationUri": env!("CARGO_PKG_REPOSITORY"),
+                    "rules": rules,
+                },
+            },
+            "results": results,
+        }],
+    })
+}
+
+/// The file `--format github` annotations point at: `--annotate-file`, or the
+/// lockfile being scanned when there is exactly one.
+fn annotation_file(cli: &Cli) -> Option<&std::path::Path> {
+    if let Some(path) = &cli.annotate_file {
+        return Some(path);
+    }
+    match cli.package_lock.as_slice() {
+        [path] => Some(path),
+        [] => cli.yarn_lock.as_deref().or(cli.pnpm_lock.as_deref()),
+        _ => None,
+    }
+}
+
+/// `--format github`: one workflow command per match, `::error` for
+/// version-confirmed matches and `::warning` for the rest, or a `::notice`
+/// with the summary line when the scan is clean. With `file`, each annotation
+/// carries `file=` and the `line=` where the package is declared in it.
+fn render_github(
+    matches: &[MatchRecord],
+    dep_count: usize,
+    file: Option<&std::path::Path>,
+) -> String {
+    let text = file.and_then(|path| fs::read_to_string(path).ok());
+    let mut out = String::new();
+    if matches.is_empty() {
+        let summary = output::summary_line(dep_count, matches);
+        writeln!(out, "::notice title=npm-compromised-scan::{}", escape_workflow_data(&summary))
+            .ok();
+        return out;
+    }
+    for m in matches {
+        let confirmed = is_version_confirmed(&m.match_type)
+            && !m.match_type.starts_with(ADVISORY_PREFIX)
+            && m.below_floor.is_none();
+        let (command, title) = if confirmed {
+            ("error", "Compromised dependency")
+        } else {
+            ("warning", "Possibly compromised dependency")
+        };
+        let mut params = Vec::new();
+        if let Some(path) = file {
+            let uri = path.display().to_string().replace('\\', "/");
+            params.push(format!("file={}", escape_workflow_property(&uri)));
+            let line = text.as_deref().and_then(|t| declaration_line(t, &m.name, &m.version));
+            if let Some(line) = line {
+                params.push(format!("line={line}"));
+            }
+        }
+        params.push(format!("title={}", escape_workflow_property(title)));
+        let message = format!(
+            "{}@{} matches {} entry {}",
+            m.name,
+            m.version,
+            m.match_type,
+            CompromisedList::entry_key(m)
+        );
+        writeln!(out, "::{command} {}::{}", params.join(","), escape_workflow_data(&message))
+            .ok();
+    }
+    out
+}
+
+/// 1-based line declaring `name` in a lockfile or manifest: the
+/// `node_modules/<name>` key of a package-lock (the install of `version` if
+/// there is one), else the first line naming the package the way package.json,
+/// yarn.lock, or pnpm-lock.yaml do.
+fn declaration_line(text: &str, name: &str, version: &str) -> Option<usize> {
+    let lines: Vec<&str> = text.lines().collect();
+    let key = format!("node_modules/{name}\":");
+    let installs: Vec<usize> = (0..lines.len()).filter(|&i| lines[i].contains(&key)).collect();
+    let version_field = format!("\"version\": \"{version}\"");
+    let installed_version = |start: usize| {
+        lines[start + 1..]
+            .iter()
+            .find(|l| l.trim_start().starts_with("\"version\""))
+            .is_some_and(|l| l.contains(&version_field))
+    };
+    if let Some(&i) = installs.iter().find(|&&i| installed_version(i)).or(installs.first()) {
+        return Some(i + 1);
+    }
+    let quoted = format!("\"{name}\"");
+    let (spec, path) = (format!("{name}@"), format!("{name}/"));
+    lines
+        .iter()
+        .position(|l| {
+            let entry = l.trim_start().trim_start_matches(['"', '\'', '/']);
+            l.contains(&quoted) || entry.starts_with(&spec) || entry.starts_with(&path)
+        })
+        .map(|i| i + 1)
+}
+
+/// Escape the message of a workflow command.
+fn escape_workflow_data(s: &str) -> String {
+    s.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+/// Escape a workflow command parameter value, which also may not hold `:` or `,`.
+fn escape_workflow_property(s: &str) -> String {
+    escape_workflow_data(s).replace(':', "%3A").replace(',', "%2C")
+}
+
+/// Markdown appended to `$GITHUB_STEP_SUMMARY` by `--format github`: the
+/// summary line over every match, then a table of the `shown` ones.
+fn render_step_summary(matches: &[MatchRecord], shown: &[MatchRecord], dep_count: usize) -> String {
+    let cell = |s: &str| s.replace('|', "\\|");
+    let mut out = String::from("### npm-compromised-scan\n\n");
+    writeln!(out, "{}\n", output::summary_line(dep_count, matches)).ok();
+    if matches.is_empty() {
+        return out;
+    }
+    out.push_str("| Package | Version | Match | Entry |\n| --- | --- | --- | --- |\n");
+    for m in shown {
+        writeln!(
+            out,
+            "| {} | {} | {} | {} |",
+            cell(&m.name),
+            cell(&m.version),
+            m.match_type,
+            cell(&CompromisedList::entry_key(m))
+        )
+        .ok();
+    }
+    if shown.len() < matches.len() {
+        writeln!(out, "\n... and {} more match(es) (--sample)", matches.len() - shown.len()).ok();
+    }
+    out.push('\n');
+    out
+}
+
+/// Render `--template` with the match set. Values are inserted unescaped.
+fn render_template(path: &PathBuf, matches: &[MatchRecord]) -> Result<String> {
+    #[derive(serde::Serialize)]
+    struct TemplateContext<'a> {
+        matches: &'a [MatchRecord],
+        match_count: usize,
+        distinct_package_count: usize,
+        has_matches: bool,
+    }
+    let source = fs::read_to_string(path)?;
+    let mut tt = tinytemplate::TinyTemplate::new();
+    tt.set_default_formatter(&tinytemplate::format_unescaped);
+    tt.add_template("report", &source)?;
+    let context = TemplateContext {
+        matches,
+        match_count: matches.len(),
+        distinct_package_count: distinct_package_count(matches.iter()),
+        has_matches: !matches.is_empty(),
+    };
+    Ok(tt.render("report", &context)?)
+}
+
+/// Render a JUnit XML report. By default version-confirmed matches are
+/// failing testcases in an `exact` suite and name-only matches skipped ones in
+/// a `name` suite; an empty suite holds one passing testcase. `single_suite`
+/// keeps the older layout: one suite of failing testcases, or a passing one
+/// when the scan is clean. Matches below a floor pass.
+fn render_junit(
+    matches: &[MatchRecord],
+    suite_name: &str,
+    classname: &str,
+    single_suite: bool,
+) -> String {
+    let suite = xml_escape(suite_name);
+    let classname = xml_escape(classname);
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    if single_suite {
+        let failures = matches.iter().filter(|m| m.below_floor.is_none()).count();
+        let tests = matches.len().max(1);
+        writeln!(
+            out,
+            r#"<testsuites name="{suite}" tests="{tests}" failures="{failures}">"#
+        )
+        .ok();
+        let all: Vec<&MatchRecord> = matches.iter().collect();
+        write_junit_suite(&mut out, &suite, &classname, &all, false, "no compromised dependencies");
+        out.push_str("</testsuites>\n");
+        return out;
+    }
+    let (exact, name): (Vec<&MatchRecord>, Vec<&MatchRecord>) =
+        matches.iter().partition(|m| is_version_confirmed(&m.match_type));
+    let tests = exact.len().max(1) + name.len().max(1);
+    writeln!(
+        out,
+        r#"<testsuites name="{suite}" tests="{tests}" failures="{}">"#,
+        exact.len()
+    )
+    .ok();
+    write_junit_suite(&mut out, "exact", &classname, &exact, false, "no version-confirmed matches");
+    write_junit_suite(&mut out, "name", &classname, &name, true, "no name-only matches");
+    out.push_str("</testsuites>\n");
+    out
+}
+
+/// One `<testsuite>` of `render_junit`: a testcase per match, failing, or
+/// skipped with `skip`; a passing `clean` testcase when there are none.
+fn write_junit_suite(
+    out: &mut String,
+    suite: &str,
+    classname: &str,
+    matches: &[&MatchRecord],
+    skip: bool,
+    clean: &str,
+) {
+    let tests = matches.len().max(1);
+    let flagged = matches.iter().filter(|m| m.below_floor.is_none()).count();
+    let (failures, skipped) = if skip { (0, flagged) } else { (flagged, 0) };
+    writeln!(
+        out,
+        r#"  <testsuite name="{suite}" tests="{tests}" failures="{failures}" errors="0" skipped="{skipped}">"#
+    )
+    .ok();
+    if matches.is_empty() {
+        writeln!(out, r#"    <testcase classname="{classname}" name="{clean}"/>"#).ok();
+    }
+    for m in matches {
+        let name = xml_escape(&format!("{}@{}", m.name, m.version));
+        if m.below_floor.is_some() {
+            writeln!(out, r#"    <testcase classname="{classname}" name="{name}"/>"#).ok();
+            continue;
+        }
+        writeln!(out, r#"    <testcase classname="{classname}" name="{name}">"#).ok();
+        let message = xml_escape(&format_text_match(m));
+        if skip {
+            writeln!(out, r#"      <skipped message="{message}"/>"#).ok();
+        } else {
+            let kind = xml_escape(&m.match_type);
+            writeln!(out, r#"      <failure type="{kind}" message="{message}"/>"#).ok();
+        }
+        writeln!(out, "    </testcase>").ok();
+    }
+    out.push_str("  </testsuite>\n");
+}
+
+/// Escape text for use in XML attribute values.
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Read a CycloneDX JSON SBOM and attach the matches to it: each matching
+/// component (by name, or group/name, and version) gains an
+/// `npm-compromised-scan:match` property, and a vulnerability referencing it
+/// is appended to `vulnerabilities`. Everything else is left as it was.
+fn annotate_sbom(path: &PathBuf, matches: &[MatchRecord]) -> Result<String> {
+    let data = fs::read_to_string(path)?;
+    let mut sbom: Value = serde_json::from_str(&data).context("SBOM is not valid JSON")?;
+    if sbom.get("bomFormat").and_then(|f| f.as_str()) != Some("CycloneDX") {
+        return Err(anyhow!("not a CycloneDX SBOM (bomFormat is not \"CycloneDX\")"));
+    }
+    let by_key: HashMap<(String, String), &MatchRecord> = matches
+        .iter()
+        .map(|m| ((m.name.clone(), m.version.clone()), m))
+        .collect();
+
+    let mut vulnerabilities = Vec::new();
+    if let Some(components) = sbom.get_mut("components") {
+        annotate_components(components, &by_key, &mut vulnerabilities);
+    }
+    if !vulnerabilities.is_empty() {
+        let obj = sbom.as_object_mut().unwrap();
+        let existing = obj.entry("vulnerabilities").or_insert_with(|| json!([]));
+        let Some(list) = existing.as_array_mut() else {
+            return Err(anyhow!("SBOM `vulnerabilities` is not an array"));
+        };
+        list.extend(vulnerabilities);
+    }
+
+    let rendered = serde_json::to_string_pretty(&sbom)?;
+    let reparsed: Value = serde_json::from_str(&rendered)?;
+    if reparsed.get("bomFormat") != sbom.get("bomFormat")
+        || reparsed.get("specVersion") != sbom.get("specVersion")
+    {
+        return Err(anyhow!("annotated SBOM no longer parses as CycloneDX"));
+    }
+    Ok(rendered)
+}
+
+fn annotate_components(
+    components: &mut Value,
+    by_key: &HashMap<(String, String), &MatchRecord>,
+    vulnerabilities: &mut Vec<Value>,
+) {
+    for component in components.as_array_mut().into_iter().flatten() {
+        let str_field = |key: &str| component.get(key).and_then(|v| v.as_str()).unwrap_or("");
+        let name = match (str_field("group"), str_field("name")) {
+            ("", name) => name.to_string(),
+            (group, name) => format!("{group}/{name}"),
+        };
+        let version = str_field("version").to_string();
+        if let Some(m) = by_key.get(&(name, version)) {
+            let Some(obj) = component.as_object_mut() else {
+                continue;
+            };
+            let bom_ref = obj
+                .entry("bom-ref")
+                .or_insert_with(|| json!(npm_purl(&m.name, &m.version)))
+                .clone();
+            let properties = obj.entry("properties").or_insert_with(|| json!([]));
+            if let Some(props) = properties.as_array_mut() {
+                props.push(json!({
+                    "name": format!("{}:match", env!("CARGO_PKG_NAME")),
+                    "value": m.match_type,
+                }));
+            }
+            vulnerabilities.push(json!({
+                "id": format!("{}:{}@{}", m.match_type, m.name, m.version),
+                "source": { "name": env!("CARGO_PKG_NAME") },
+                "description": format!("{}@{} matched the compromised list ({} match)", m.name, m.version, m.match_type),
+                "affects": [{ "ref": bom_ref }],
+            }));
+        }
+        if let Some(nested) = component.get_mut("components") {
+            annotate_components(nested, by_key, vulnerabilities);
+        }
+    }
+}
+
+/// Render a shields.io-style flat SVG badge: green "clean" or red "N compromised".
+fn render_badge(match_count: usize) -> String {
+    let label = "npm scan";
+    let (message, color) = if match_count == 0 {
+        ("clean".to_string(), "#4c1")
+    } else {
+        (format!("{match_count} compromised"), "#e05d44")
+    };
+    // Approximate Verdana 11px glyph width used by shields.io.
+    let text_width = |t: &str| t.chars().count() * 7 + 10;
+    let lw = text_width(label);
+    let mw = text_width(&message);
+    let total = lw + mw;
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{total}" height="20" role="img" aria-label="{label}: {message}">
+  <title>{label}: {message}</title>
+  <linearGradient id="s" x2="0" y2="100%"><stop offset="0" stop-color="#bbb" stop-opacity=".1"/><stop offset="1" stop-opacity=".1"/></linearGradient>
+  <clipPath id="r"><rect width="{total}" height="20" rx="3" fill="#fff"/></clipPath>
+  <g clip-path="url(#r)">
+    <rect width="{lw}" height="20" fill="#555"/>
+    <rect x="{lw}" width="{mw}" height="20" fill="{color}"/>
+    <rect width="{total}" height="20" fill="url(#s)"/>
+  </g>
+  <g fill="#fff" text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="11">
+    <text x="{lx}" y="14">{label}</text>
+    <text x="{mx}" y="14">{message}</text>
+  </g>
+</svg>"##,
+        lx = lw / 2,
+        mx = lw + mw / 2,
+    )
+}
+
+/// Render a Graphviz DOT graph of every tree path from the root to a matched
+/// `(name, version)`. Nodes off those paths are left out; matches are red.
+fn render_dot(tree: Option<&Value>, matched: &HashSet<(String, String)>, redact: bool) -> String {
+    let label = |name: &str, version: &str| {
+        let spec = format!("{name}@{version}");
+        if redact {
+            redact_spec(&spec)
+        } else {
+            spec
+        }
+    };
+    let root = tree
+        .map(|t| {
+            let name = t.get("name").and_then(|n| n.as_str()).unwrap_or("root");
+            match t.get("version").and_then(|v| v.as_str()) {
+                Some(version) => label(name, version),
+                None => name.to_string(),
+            }
+        })
+        .unwrap_or_else(|| "root".to_string());
+
+    let mut red = BTreeSet::new();
+    let mut edges = BTreeSet::new();
+    let children = tree.and_then(|t| t.get("dependencies")).and_then(|d| d.as_object());
+    for (name, node) in children.into_iter().flatten() {
+        if let Some(spec) = dot_walk(name, node, matched, &label, &mut red, &mut edges) {
+            edges.insert((root.clone(), spec));
+        }
+    }
+
+    let mut out = String::from("digraph compromised {\n  rankdir=LR;\n  node [shape=box];\n");
+    writeln!(out, "  {root:?};").ok();
+    for spec in &red {
+        writeln!(out, "  {spec:?} [color=red, fontcolor=red];").ok();
+    }
+    for (from, to) in &edges {
+        writeln!(out, "  {from:?} -> {to:?};").ok();
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// Record edges below `node` that lead to a match; returns the node's label
+/// if it is a match or an ancestor of one.
+fn dot_walk(
+    name: &str,
+    node: &Value,
+    matched: &HashSet<(String, String)>,
+    label: &dyn Fn(&str, &str) -> String,
+    red: &mut BTreeSet<String>,
+    edges: &mut BTreeSet<(String, String)>,
+) -> Option<String> {
+    let version = node.get("version").and_then(|v| v.as_str()).unwrap_or("");
+    let spec = label(name, version);
+    let mut on_path = matched.contains(&(name.to_string(), version.to_string()));
+    if on_path {
+        red.insert(spec.clone());
+    }
+    let children = node.get("dependencies").and_then(|d| d.as_object());
+    for (child_name, child_node) in children.into_iter().flatten() {
+        if let Some(child) = dot_walk(child_name, child_node, matched, label, red, edges) {
+            edges.insert((spec.clone(), child));
+            on_path = true;
+        }
+    }
+    on_path.then_some(spec)
+}
+
+/// Package URL for an npm package; the scope '@' is percent-encoded.
+fn npm_purl(name: &str, version: &str) -> String {
+    match name.strip_prefix('@') {
+        Some(scoped) => format!("pkg:npm/%40{scoped}@{version}"),
+        None => format!("pkg:npm/{name}@{version}"),
+    }
+}
+
+fn parse_options(cli: &Cli) -> ParseOptions {
+    ParseOptions {
+        as_of: cli.as_of.unwrap_or_else(|| chrono::Utc::now().date_naive()),
+        separator: cli.list_separator,
+        verbose: cli.verbose,
+    }
+}
+
+fn match_options(cli: &Cli) -> MatchOptions {
+    MatchOptions {
+        match_basename: cli.match_basename,
+        confusable_check: cli.confusable_check,
+        strict_build_metadata: cli.strict_build_metadata,
+        show_upgrade_risk: cli.show_upgrade_risk,
+        trusted_scopes: cli.trusted_scopes.clone().unwrap_or_default(),
+        match_all_rules: cli.match_all_rules,
+    }
+}
+
+/// A list file read for the scan, as recorded in the JSON report's `list_sources`.
+/// A `--preset` is recorded as `preset:<name>`, with its origin and date.
+#[derive(Debug, Default, serde::Serialize)]
+struct ListSourceRecord {
+    path: String,
+    sha256: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    origin: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date: Option<&'static str>,
+}
+
+/// Load `--list-index`, or parse `--list` with `--list-patch` applied on top when given.
+fn load_compromised_list(cli: &Cli) -> Result<(CompromisedList, Vec<ListSourceRecord>)> {
+    let opts = parse_options(cli);
+    let (entries, sources) = match &cli.list_index {
+        Some(path) => {
+            let entries =
+                read_list_index(path).context(format!("Failed to load list index: {:?}", path))?;
+            let bytes = fs::read(path).context(format!("Unable to read list index: {:?}", path))?;
+            let source = ListSourceRecord {
+                path: path.display().to_string(),
+                sha256: sha256_hex(&bytes),
+                ..Default::default()
+            };
+            (entries, vec![source])
+        }
+        None => load_list_entries(cli)?,
+    };
+    Ok((CompromisedList::from_entries(entries, opts.as_of), sources))
+}
+
+/// `--preset` value: a built-in preset's name, or `list`.
+fn parse_preset_name(s: &str) -> std::result::Result<String, String> {
+    if s == "list" || Preset::find(s).is_some() {
+        return Ok(s.to_string());
+    }
+    let names: Vec<&str> = PRESETS.iter().map(|p| p.name).collect();
+    Err(format!("unknown preset '{s}' (available: {})", names.join(", ")))
+}
+
+/// `--preset list`: each preset with its entry count, origin, and date.
+fn print_presets() -> Result<()> {
+    for preset in PRESETS {
+        let count = preset.entries()?.len();
+        println!("{}  {} entries  {} (as of {})", preset.name, count, preset.origin, preset.date);
+    }
+    Ok(())
+}
+
+/// The lists being scanned against, for messages.
+fn list_label(cli: &Cli) -> String {
+    match &cli.list_index {
+        Some(path) => format!("{:?}", path),
+        None => {
+            let files: Vec<String> = cli
+                .list_files
+                .iter()
+                .chain(&cli.advisories)
+                .map(|p| format!("{:?}", p))
+                .chain(cli.presets.iter().map(|name| format!("preset:{name}")))
+                .collect();
+            files.join(", ")
+        }
+    }
+}
+
+/// Validated entries of every `--list` and then every `--advisory`, in order, with
+/// `--list-patch` applied on top when given, and the SHA-256 of each file's text
+/// (the patch included).
+fn load_list_entries(cli: &Cli) -> Result<(Vec<ListEntry>, Vec<ListSourceRecord>)> {
+    let mut entries = Vec::new();
+    let mut sources = Vec::new();
+    for list_file in &cli.list_files {
+        if list_file.as_os_str() == DEFAULT_LIST && !list_file.exists() {
+            return Err(anyhow!(
+                "No {} here; pass --list <file or URL>, or a built-in --preset (`--preset list` shows them)",
+                DEFAULT_LIST
+            ));
+        }
+        let text = read_list_text(list_file, cli)?;
+        let source = list_file.display().to_string();
+        sources.push(ListSourceRecord {
+            path: source.clone(),
+            sha256: sha256_hex(text.as_bytes()),
+            ..Default::default()
+        });
+        entries.extend(
+            parse_list_text(&text, cli.list_separator, &source, cli.verbose)
+                .context(format!("Failed to parse compromised list: {:?}", list_file))?,
+        );
+    }
+    for path in &cli.advisories {
+        let text =
+            fs::read_to_string(path).context(format!("Unable to read advisory: {:?}", path))?;
+        let source = path.display().to_string();
+        let (advisory_entries, skipped, withdrawn) = parse_osv_entries(&text, &source)
+            .context(format!("Failed to parse advisory: {:?}", path))?;
+        if cli.verbose {
+            diag!(
+                "Read {} entry(ies) from advisory {} ({} non-npm affected package(s) and {} withdrawn advisory(ies) skipped)",
+                advisory_entries.len(),
+                source,
+                skipped,
+                withdrawn
+            );
+        }
+        entries.extend(advisory_entries);
+        sources.push(ListSourceRecord {
+            path: source,
+            sha256: sha256_hex(text.as_bytes()),
+            ..Default::default()
+        });
+    }
+    for name in &cli.presets {
+        let preset = Preset::find(name).expect("validated by parse_preset_name");
+        entries.extend(
+            preset.entries().context(format!("Failed to parse preset: {}", preset.name))?,
+        );
+        if cli.verbose {
+            diag!("Preset {}: {} (as of {})", preset.name, preset.origin, preset.date);
+        }
+        sources.push(ListSourceRecord {
+            path: format!("preset:{}", preset.name),
+            sha256: sha256_hex(preset.text.as_bytes()),
+            origin: Some(preset.origin),
+            date: Some(preset.date),
+        });
+    }
+    if let Some(patch) = &cli.list_patch {
+        apply_list_patch(&mut entries, patch, cli.list_separator)
+            .context(format!("Failed to apply list patch: {:?}", patch))?;
+        let bytes = fs::read(patch).context(format!("Unable to read list patch: {:?}", patch))?;
+        sources.push(ListSourceRecord {
+            path: patch.display().to_string(),
+            sha256: sha256_hex(&bytes),
+            ..Default::default()
+        });
+    }
+    Ok((entries, sources))
+}
+
+/// Text of a --list: read from disk or, for an http(s) or s3 URL, downloaded.
+/// With --cache-dir a download is reused until it is --cache-ttl seconds old,
+/// and a stale copy stands in (with a warning) when the download fails.
+fn read_list_text(list_file: &PathBuf, cli: &Cli) -> Result<String> {
+    let source = list_file.to_string_lossy();
+    if !is_remote_list(&source) {
+        return fs::read_to_string(list_file)
+            .context(format!("Unable to read compromised list file: {:?}", list_file));
+    }
+    let cached = cli.cache_dir.as_ref().map(|dir| {
+        let file: String = source
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '.' { c } else { '_' })
+            .collect();
+        dir.join(file)
+    });
+    if let Some(path) = cached.as_ref().filter(|_| !cli.no_cache) {
+        let age = fs::metadata(path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.elapsed().ok());
+        if age.is_some_and(|age| age.as_secs() < cli.cache_ttl) {
+            if cli.verbose {
+                diag!("Using cached copy of {} from {:?}.", source, path);
+            }
+            return fs::read_to_string(path)
+                .context(format!("Unable to read cached list: {:?}", path));
+        }
+    }
+    match fetch_url_text(&source) {
+        Ok(text) => {
+            if let Some(path) = &cached {
+                let written = path
+                    .parent()
+                    .map_or(Ok(()), fs::create_dir_all)
+                    .and_then(|_| fs::write(path, &text));
+                if let Err(e) = written {
+                    diag!("Warning: could not cache {} at {:?}: {}", source, path, e);
+                }
+            }
+            Ok(text)
+        }
+        Err(e) => match cached.filter(|p| !cli.no_cache && p.exists()) {
+            Some(path) => {
+                diag!("Warning: {e:#}; falling back to the cached copy at {:?}.", path);
+                fs::read_to_string(&path).context(format!("Unable to read cached list: {:?}", path))
+            }
+            None => Err(e),
+        },
+    }
+}
+
+/// Apply a list patch to the base list entries.
+///
+/// Each non-blank, non-comment patch line is `+<entry>` (appended, with the
+/// patch as its source) or `-<entry>` (removes base entries with that entry,
+/// ignoring annotations). Removing an entry the base does not contain only warns.
+fn apply_list_patch(
+    entries: &mut Vec<ListEntry>,
+    patch_path: &PathBuf,
+    separator: char,
+) -> Result<()> {
+    let patch = fs::read_to_string(patch_path)?;
+    let source = patch_path.display().to_string();
+    for (lineno, raw_line) in patch.lines().enumerate() {
+        let line = raw_line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |reason: String| {
+            anyhow!(
+                "Invalid patch line {}: '{}' ({})",
+                lineno + 1,
+                line,
+                reason
+            )
+        };
+        let (add, entry) = match (line.strip_prefix('+'), line.strip_prefix('-')) {
+            (Some(entry), _) => (true, entry.trim()),
+            (_, Some(entry)) => (false, entry.trim()),
+            _ => return Err(invalid("expected a '+' or '-' prefix".into())),
+        };
+        if add {
+            entries.push(parse_list_line(entry, separator, &source).map_err(invalid)?);
+            continue;
+        }
+        let (spec, _) = split_entry_annotations(entry);
+        if let CompEntry::Invalid(reason) = parse_compromised_entry(spec, separator) {
+            return Err(invalid(reason));
+        }
+        let before = entries.len();
+        entries.retain(|e| e.spec != spec);
+        if entries.len() == before {
+            diag!(
+                "Warning: list patch line {} removes '{}', which is not in the base list",
+                lineno + 1,
+                spec
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Load npm dependency tree JSON (Value), and whether npm succeeded: its exit
+/// status when run here, or the absence of a top-level `error` in provided JSON.
+fn load_npm_tree_json(cli: &Cli, long: bool) -> Result<(Value, bool)> {
+    if let Some(src) = cli.npm_json.first() {
+        if src == "-" {
+            let mut buf = String::new();
+            io::stdin()
+                .read_to_string(&mut buf)
+                .context("Failed to read stdin")?;
+            let mut v: Value =
+                serde_json::from_str(&buf).context("Failed to parse JSON from stdin (--npm-json -)")?;
+            if !cli.npm_query {
+                use_root_key(&mut v, &cli.root_key).context("Unexpected JSON on stdin")?;
+            }
+            if !cli.skip_validation {
+                validate_npm_json(&v, cli.npm_query).context("Unexpected JSON on stdin")?;
+            }
+            let ok = v.get("error").is_none();
+            return Ok((v, ok));
+        } else {
+            return read_npm_json_file(cli, src);
+        }
+    }
+
+    if cli.no_run_npm {
+        return Err(anyhow!(
+            "no-run-npm specified but no --npm-json source provided"
+        ));
+    }
+    run_npm_json(cli, None, long, None)
+}
+
+/// Read and validate one `--npm-json` file, returning its JSON and whether npm succeeded.
+fn read_npm_json_file(cli: &Cli, src: &str) -> Result<(Value, bool)> {
+    let file = fs::File::open(src).context(format!("Failed to read npm JSON file: {}", src))?;
+    let mut v: Value = serde_json::from_reader(io::BufReader::new(file))
+        .context("Failed to parse provided npm JSON file")?;
+    if !cli.npm_query {
+        use_root_key(&mut v, &cli.root_key).context(format!("Unexpected JSON in {}", src))?;
+    }
+    if !cli.skip_validation {
+        validate_npm_json(&v, cli.npm_query).context(format!("Unexpected JSON in {}", src))?;
+    }
+    let ok = v.get("error").is_none();
+    Ok((v, ok))
+}
+
+/// Whether `npm ls` must run with `--long`: for the dev/optional/peer node
+/// flags (`--scan-scope`) or the `repository` metadata (`repo:` list entries).
+fn wants_long_output(cli: &Cli, lists: Option<&CompromisedList>) -> bool {
+    cli.scan_scope != "all" || cli.require_license || lists.is_some_and(|l| !l.repos.is_empty())
+}
+
+/// Run `npm ls` (or `npm query`), in `dir` if given, returning its JSON and exit success.
+/// Killed after `timeout`, if given, failing with `NpmTimeout`.
+fn run_npm_json(
+    cli: &Cli,
+    dir: Option<&std::path::Path>,
+    long: bool,
+    timeout: Option<std::time::Duration>,
+) -> Result<(Value, bool)> {
+    let mut args = if cli.npm_query {
+        vec!["query", "*", "--json"]
+    } else {
+        vec!["ls", "--all", "--json"]
+    };
+    if !cli.npm_query && long {
+        args.push("--long");
+    }
+    let omit: Vec<String> = cli.omit.iter().map(|t| format!("--omit={t}")).collect();
+    if !cli.npm_query {
+        args.extend(omit.iter().map(String::as_str));
+    }
+    if cli.global {
+        args.insert(1, "-g");
+    }
+    if cli.confirm_npm {
+        confirm_npm_run(cli, &args, dir)?;
+    }
+    let mut command = Command::new("npm");
+    if let Some(dir) = dir {
+        command.current_dir(dir);
+    }
+    command.args(&args);
+    let failed_to_run = || format!("Failed to execute `npm {}`", args.join(" "));
+    let (parsed, status) = match timeout {
+        Some(limit) => {
+            let output = output_with_timeout(&mut command, limit).context(failed_to_run())?;
+            (serde_json::from_slice(&output.stdout), output.status)
+        }
+        None => parse_npm_stdout(&mut command).context(failed_to_run())?,
+    };
+
+    if !status.success() {
+        diag!(
+            "Warning: npm {} exited with non-zero status ({:?}). Still attempting to parse output.",
+            args[0],
+            status.code()
+        );
+    }
+    let mut v: Value =
+        parsed.context(format!("Failed to parse JSON from `npm {}` output", args[0]))?;
+    if !cli.npm_query {
+        use_root_key(&mut v, &cli.root_key)
+            .context(format!("Unexpected JSON from `npm {}`", args[0]))?;
+    }
+    Ok((v, status.success()))
+}
+
+/// `--confirm-npm`: show the npm command and ask on the terminal before the
+/// first run. The answer stands for the rest of the run, so --scan-dir and
+/// --recursive ask once; the lock keeps their worker threads from asking together.
+fn confirm_npm_run(cli: &Cli, args: &[&str], dir: Option<&std::path::Path>) -> Result<()> {
+    static ANSWER: std::sync::Mutex<Option<bool>> = std::sync::Mutex::new(None);
+    let mut answer = ANSWER.lock().unwrap_or_else(|e| e.into_inner());
+    let command = format!("npm {}", args.join(" "));
+    let approved = match *answer {
+        Some(approved) => approved,
+        None if cli.yes => true,
+        None if !(io::stdin().is_terminal() && io::stderr().is_terminal()) => {
+            return Err(anyhow!(
+                "--confirm-npm cannot ask before running `{}` without a terminal; pass --yes to run it, or give --npm-json or a lockfile so npm is not needed",
+                command
+            ));
+        }
+        None => {
+            let location = dir.map_or_else(|| "here".to_string(), |d| format!("in {:?}", d));
+            eprint!("Run `{}` {}? [y/N] ", command, location);
+            io::stderr().flush()?;
+            let mut reply = String::new();
+            io::stdin().read_line(&mut reply)?;
+            matches!(reply.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+        }
+    };
+    *answer = Some(approved);
+    if !approved {
+        return Err(anyhow!("Not running `{}` (declined at the --confirm-npm prompt)", command));
+    }
+    Ok(())
+}
+
+/// Run npm and parse its stdout as it arrives, so a large tree is never held
+/// as text as well as JSON. Like `Command::output`, stderr is not shown.
+fn parse_npm_stdout(
+    command: &mut Command,
+) -> Result<(serde_json::Result<Value>, std::process::ExitStatus)> {
+    use std::process::Stdio;
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    // On a parse error the pipe is dropped here, so npm cannot block writing the rest.
+    let parsed = serde_json::from_reader(io::BufReader::new(stdout));
+    Ok((parsed, child.wait()?))
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("npm did not finish within {0:?}")]
+struct NpmTimeout(std::time::Duration);
+
+/// Like `Command::output`, but kill the child and fail with `NpmTimeout` once `limit` passes.
+fn output_with_timeout(
+    command: &mut Command,
+    limit: std::time::Duration,
+) -> Result<std::process::Output> {
+    use std::process::Stdio;
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // Drain the pipes on their own threads so a chatty child cannot block on a full pipe.
+    let drain = |pipe: Option<Box<dyn Read + Send>>| {
+        std::thread::spawn(move || {
+            let mut buf = Vec::new();
+            if let Some(mut pipe) = pipe {
+                pipe.read_to_end(&mut buf).ok();
+            }
+            buf
+        })
+    };
+    let stdout = drain(child.stdout.take().map(|p| Box::new(p) as _));
+    let stderr = drain(child.stderr.take().map(|p| Box::new(p) as _));
+    let deadline = std::time::Instant::now() + limit;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if std::time::Instant::now() >= deadline {
+            child.kill().ok();
+            child.wait().ok();
+            return Err(NpmTimeout(limit).into());
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    };
+    Ok(std::process::Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+/// Check that provided JSON looks like `npm ls --json` output (an object with
+/// `name`, `version`, `dependencies`, `problems`, or `error`), or like an
+/// `npm query` array, so an unrelated file cannot yield a false clean result.
+fn validate_npm_json(v: &Value, npm_query: bool) -> Result<()> {
+    if npm_query {
+        return match v.as_array() {
+            Some(_) => Ok(()),
+            None => Err(anyhow!(
+                "expected a JSON array from `npm query`; use --skip-validation to bypass"
+            )),
+        };
+    }
+    let Some(obj) = v.as_object() else {
+        return Err(anyhow!(
+            "expected a JSON object from `npm ls --all --json`; use --skip-validation to bypass"
+        ));
+    };
+    const ROOT_KEYS: [&str; 5] = ["name", "version", "dependencies", "problems", "error"];
+    if !ROOT_KEYS.iter().any(|k| obj.contains_key(*k)) {
+        return Err(anyhow!(
+            "does not look like `npm ls --all --json` output (none of {} at the top level); \
+             use --root-key for another envelope, or --skip-validation to bypass",
+            ROOT_KEYS.join(", ")
+        ));
+    }
+    if let Some(deps) = obj.get("dependencies") {
+        if !deps.is_object() {
+            return Err(anyhow!("`dependencies` is not an object"));
+        }
+    }
+    Ok(())
+}
+
+/// Shape of the npm tree, for `--tree-stats`.
+#[derive(Debug, Default, serde::Serialize)]
+struct TreeStats {
+    total_nodes: usize,
+    unique_packages: usize, // distinct name@version
+    max_depth: usize,       // top-level dependencies are depth 1
+    deduped: usize,
+    unresolved: usize, // `missing`, or no version
+    links: usize,      // `file:` / `link:` installs
+    largest_fan_out: usize,
+    largest_fan_out_package: Option<String>,
+}
+
+impl TreeStats {
+    fn summary(&self) -> String {
+        let mut line = format!(
+            "Tree: {} node(s), {} unique package(s), max depth {}, {} deduped, {} unresolved, {} link(s), largest fan-out {}",
+            self.total_nodes,
+            self.unique_packages,
+            self.max_depth,
+            self.deduped,
+            self.unresolved,
+            self.links,
+            self.largest_fan_out
+        );
+        if let Some(pkg) = &self.largest_fan_out_package {
+            write!(line, " ({pkg})").ok();
+        }
+        line
+    }
+}
+
+fn tree_stats(root: &Value) -> TreeStats {
+    fn walk(
+        deps: Option<&Value>,
+        depth: usize,
+        stats: &mut TreeStats,
+        unique: &mut HashSet<(String, String)>,
+    ) {
+        for (name, node) in deps.and_then(|d| d.as_object()).into_iter().flatten() {
+            stats.total_nodes += 1;
+            stats.max_depth = stats.max_depth.max(depth);
+            let flag = |key: &str| node.get(key).and_then(|f| f.as_bool()).unwrap_or(false);
+            let version = node.get("version").and_then(|v| v.as_str());
+            match version {
+                Some(version) if !flag("missing") => {
+                    unique.insert((name.clone(), version.to_string()));
+                }
+                _ => stats.unresolved += 1,
+            }
+            if flag("deduped") {
+                stats.deduped += 1;
+            }
+            let resolved = node.get("resolved").and_then(|r| r.as_str()).unwrap_or("");
+            if resolved.starts_with("file:") || resolved.starts_with("link:") {
+                stats.links += 1;
+            }
+            let children = node.get("dependencies");
+            let fan_out = children.and_then(|c| c.as_object()).map_or(0, |c| c.len());
+            if fan_out > stats.largest_fan_out {
+                stats.largest_fan_out = fan_out;
+                stats.largest_fan_out_package =
+                    Some(format!("{name}@{}", version.unwrap_or_default()));
+            }
+            walk(children, depth + 1, stats, unique);
+        }
+    }
+    let mut stats = TreeStats::default();
+    let mut unique = HashSet::new();
+    walk(root.get("dependencies"), 1, &mut stats, &mut unique);
+    stats.unique_packages = unique.len();
+    stats
+}
+
+/// A node of the npm tree the scan could not check, or one npm flags as wrong.
+#[derive(Debug, serde::Serialize)]
+struct TreeNodeIssue {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<String>,
+    /// `missing` or `no version` (skipped), `invalid` or `extraneous` (scanned)
+    reason: &'static str,
+    #[serde(skip)]
+    direct: bool,
+}
+
+impl TreeNodeIssue {
+    fn describe(&self) -> String {
+        match &self.version {
+            Some(version) => format!("{}@{} ({})", self.name, version, self.reason),
+            None => format!("{} ({})", self.name, self.reason),
+        }
+    }
+}
+
+/// Nodes of a degraded npm tree, each reported once.
+#[derive(Debug, Default)]
+struct TreeNodeIssues {
+    /// Nodes without a version to match, npm's `missing` ones included
+    skipped: Vec<TreeNodeIssue>,
+    /// `invalid` (installed version does not satisfy the dependent) and
+    /// `extraneous` (installed but not depended on) nodes, which are scanned
+    flagged: Vec<TreeNodeIssue>,
+}
+
+/// Collect the `--scan-scope` nodes `collect_deps` skips or npm flags.
+fn tree_node_issues(root: &Value, scope: &str) -> TreeNodeIssues {
+    fn walk(
+        deps: Option<&Value>,
+        direct: bool,
+        scope: &str,
+        issues: &mut TreeNodeIssues,
+        seen: &mut HashSet<(String, Option<String>, &'static str)>,
+    ) {
+        for (name, node) in deps.and_then(|d| d.as_object()).into_iter().flatten() {
+            if !in_scan_scope(node, scope) {
+                continue;
+            }
+            let flag = |key: &str| node.get(key).is_some_and(|f| f.as_bool() != Some(false));
+            let version = node.get("version").and_then(|v| v.as_str()).map(str::to_string);
+            let (reason, skipped) = match &version {
+                _ if node.get("missing").and_then(|f| f.as_bool()) == Some(true) => {
+                    ("missing", true)
+                }
+                None => ("no version", true),
+                Some(_) if flag("invalid") => ("invalid", false),
+                Some(_) if node.get("extraneous").and_then(|f| f.as_bool()) == Some(true) => {
+                    ("extraneous", false)
+                }
+                Some(_) => ("", false),
+            };
+            if !reason.is_empty() && seen.insert((name.clone(), version.clone(), reason)) {
+                let issue = TreeNodeIssue {
+                    name: name.clone(),
+                    version: version.filter(|_| !skipped),
+                    reason,
+                    direct,
+                };
+                if skipped {
+                    issues.skipped.push(issue);
+                } else {
+                    issues.flagged.push(issue);
+                }
+            }
+            walk(node.get("dependencies"), false, scope, issues, seen);
+        }
+    }
+    let mut issues = TreeNodeIssues::default();
+    walk(root.get("dependencies"), true, scope, &mut issues, &mut HashSet::new());
+    issues
+}
+
+/// Name-level matches for listed names among the skipped nodes, which have no
+/// version to check, so a compromised package npm failed to resolve is still
+/// reported. The version is recorded as `unknown`.
+fn find_unversioned_name_matches(
+    cli: &Cli,
+    skipped: &[TreeNodeIssue],
+    lists: &CompromisedList,
+) -> Vec<MatchRecord> {
+    let opts = match_options(cli);
+    let mut found: Vec<MatchRecord> = Vec::new();
+    for node in skipped {
+        if !lists.names.contains(&node.name)
+            || opts.is_trusted(&node.name)
+            || (cli.top_level_only && !node.direct)
+        {
+            continue;
+        }
+        if let Some(m) = found.iter_mut().find(|m| m.name == node.name) {
+            m.direct |= node.direct;
+            continue;
+        }
+        let dep = Dep {
+            name: node.name.clone(),
+            version: UNKNOWN_VERSION.to_string(),
+            direct: node.direct,
+            kind: DepKind::default(),
+        };
+        let mut m = MatchRecord::new("name", &dep, None);
+        lists.annotate(&mut m, opts.show_upgrade_risk);
+        found.push(m);
+    }
+    found
+}
+
+/// Drop matches installed fewer than --min-occurrences times, counted in the
+/// npm tree or else across the install locations of `lockfiles`. Inputs that
+/// record no install locations are an error rather than a count of one.
+fn filter_min_occurrences(
+    cli: &Cli,
+    tree: Option<&Value>,
+    lockfiles: &[PathBuf],
+    matches: &mut Vec<MatchRecord>,
+) -> Result<()> {
+    let Some(min) = cli.min_occurrences else {
+        return Ok(());
+    };
+    let counts = match tree {
+        Some(tree) => count_occurrences(tree),
+        None if !lockfiles.is_empty() => {
+            let mut counts = HashMap::new();
+            for path in sorted_inputs(lockfiles) {
+                let locked = read_package_lock(path, &cli.scan_scope)
+                    .context(format!("Failed to load package lock: {:?}", path))?;
+                for p in locked {
+                    *counts.entry((p.name, p.version)).or_default() += 1;
+                }
+            }
+            counts
+        }
+        None => {
+            return Err(anyhow!(
+                "--min-occurrences needs the npm ls tree or --package-lock to count installs"
+            ))
+        }
+    };
+    matches.retain(|m| {
+        (cli.occurrences_exact_exempt && is_occurrence_exempt(&m.match_type))
+            || counts
+                .get(&(m.name.clone(), m.version.clone()))
+                .copied()
+                .unwrap_or(1)
+                >= min
+    });
+    Ok(())
+}
+
+/// Match types --occurrences-exact-exempt keeps: exact, integrity, and
+/// signature-mismatch matches, advisory ones included.
+fn is_occurrence_exempt(match_type: &str) -> bool {
+    let category = match_type
+        .strip_prefix(ADVISORY_PREFIX)
+        .unwrap_or(match_type);
+    matches!(
+        category,
+        "exact" | "integrity" | "integrity-prefix" | "signature-mismatch"
+    )
+}
+
+/// How many times each (name, version) is installed in the tree.
+/// Entries npm marks as `deduped` point at another copy and are not counted.
+fn count_occurrences(root: &Value) -> HashMap<(String, String), usize> {
+    fn walk(deps: Option<&Value>, counts: &mut HashMap<(String, String), usize>) {
+        for (name, node) in deps.and_then(|d| d.as_object()).into_iter().flatten() {
+            let deduped = node.get("deduped").and_then(|d| d.as_bool()).unwrap_or(false);
+            if let Some(version) = node.get("version").and_then(|v| v.as_str()) {
+                if !deduped {
+                    *counts.entry((name.clone(), version.to_string())).or_default() += 1;
+                }
+            }
+            walk(node.get("dependencies"), counts);
+        }
+    }
+    let mut counts = HashMap::new();
+    walk(root.get("dependencies"), &mut counts);
+    counts
+}
+
+/// True if any package in npm output carries a `dev` flag (true or false).
+fn has_dependency_type_flags(v: &Value) -> bool {
+    match v {
+        Value::Array(items) => items.iter().any(|i| i.get("dev").is_some()),
+        _ => v
+            .get("dependencies")
+            .and_then(|d| d.as_object())
+            .into_iter()
+            .flatten()
+            .any(|(_, node)| node.get("dev").is_some() || has_dependency_type_flags(node)),
+    }
+}
+
+/// License as a package.json (or an `npm ls --long` node or lockfile entry)
+/// records it: a `license` string or `{ "type": ... }` object, or the legacy
+/// `licenses` array, joined with ` OR `.
+fn license_of(node: &Value) -> Option<String> {
+    fn kind(license: &Value) -> Option<&str> {
+        license.as_str().or_else(|| license.get("type").and_then(|t| t.as_str()))
+    }
+    if let Some(license) = node.get("license") {
+        return kind(license).map(String::from);
+    }
+    let kinds: Vec<&str> = node.get("licenses")?.as_array()?.iter().filter_map(kind).collect();
+    (!kinds.is_empty()).then(|| kinds.join(" OR "))
+}
+
+/// No license, or a placeholder that asserts none.
+fn is_unknown_license(license: Option<&str>) -> bool {
+    license.map(str::trim).is_none_or(|l| {
+        l.is_empty() || l.eq_ignore_ascii_case("UNKNOWN") || l.eq_ignore_ascii_case("NOASSERTION")
+    })
+}
+
+/// Fill in each match's license from the `npm ls --long` tree and any
+/// --package-lock, the first one found for its (name, version).
+fn attach_licenses(cli: &Cli, tree: Option<&Value>, matches: &mut [MatchRecord]) -> Result<()> {
+    fn walk(deps: Option<&Value>, licenses: &mut HashMap<(String, String), String>) {
+        for (name, node) in deps.and_then(|d| d.as_object()).into_iter().flatten() {
+            let version = node.get("version").and_then(|v| v.as_str()).unwrap_or("");
+            if let Some(license) = license_of(node) {
+                licenses.entry((name.clone(), version.to_string())).or_insert(license);
+            }
+            walk(node.get("dependencies"), licenses);
+        }
+    }
+    if matches.is_empty() {
+        return Ok(());
+    }
+    let mut licenses = HashMap::new();
+    if let Some(tree) = tree {
+        walk(tree.get("dependencies"), &mut licenses);
+    }
+    for path in sorted_inputs(&cli.package_lock) {
+        let locked = read_package_lock(path, "all")
+            .context(format!("Failed to load package lock: {:?}", path))?;
+        for p in locked {
+            if let Some(license) = p.license {
+                licenses.entry((p.name, p.version)).or_insert(license);
+            }
+        }
+    }
+    for m in matches {
+        m.license = licenses.get(&(m.name.clone(), m.version.clone())).cloned();
+    }
+    Ok(())
+}
+
+/// Walk the original tree and record every path to each match, keeping at
+/// most `max_paths` per match. Nodes outside `scope` are skipped with their subtrees.
+fn attach_match_paths(
+    root: &Value,
+    matches: &mut [MatchRecord],
+    scope: &str,
+    max_paths: Option<usize>,
+) {
+    fn walk(
+        deps: Option<&Value>,
+        scope: &str,
+        stack: &mut Vec<String>,
+        found: &mut HashMap<(String, String), Vec<Vec<String>>>,
+    ) {
+        for (name, node) in deps.and_then(|d| d.as_object()).into_iter().flatten() {
+            if !in_scan_scope(node, scope) {
+                continue;
+            }
+            let version = node.get("version").and_then(|v| v.as_str()).unwrap_or("");
+            stack.push(format!("{name}@{version}"));
+            if let Some(paths) = found.get_mut(&(name.clone(), version.to_string())) {
+                paths.push(stack.clone());
+            }
+            walk(node.get("dependencies"), scope, stack, found);
+            stack.pop();
+        }
+    }
+
+    let mut found: HashMap<(String, String), Vec<Vec<String>>> = matches
+        .iter()
+        .map(|m| ((m.name.clone(), m.version.clone()), Vec::new()))
+        .collect();
+    walk(root.get("dependencies"), scope, &mut Vec::new(), &mut found);
+    for m in matches {
+        let mut paths = found
+            .get(&(m.name.clone(), m.version.clone()))
+            .cloned()
+            .unwrap_or_default();
+        if let Some(max) = max_paths.filter(|&max| paths.len() > max) {
+            m.omitted_paths = paths.len() - max;
+            paths.truncate(max);
+        }
+        m.paths = paths;
+    }
+}
+
+/// Walk the original tree and attach a `MatchContext` to each match,
+/// using the first occurrence of its (name, version).
+fn attach_match_context(root: &Value, matches: &mut [MatchRecord]) {
+    let wanted: HashSet<(String, String)> = matches
+        .iter()
+        .map(|m| (m.name.clone(), m.version.clone()))
+        .collect();
+    let mut found = HashMap::new();
+    let mut ancestors = Vec::new();
+    if let Some(obj) = root.get("dependencies").and_then(|d| d.as_object()) {
+        for (name, node) in obj {
+            context_walk(name, node, &mut ancestors, &wanted, &mut found);
+        }
+    }
+    for m in matches {
+        m.context = found.remove(&(m.name.clone(), m.version.clone()));
+    }
+}
+
+fn context_walk(
+    name: &str,
+    node: &Value,
+    ancestors: &mut Vec<String>,
+    wanted: &HashSet<(String, String)>,
+    found: &mut HashMap<(String, String), MatchContext>,
+) {
+    let version = node.get("version").and_then(|v| v.as_str()).unwrap_or("");
+    let children = node.get("dependencies").and_then(|d| d.as_object());
+    let key = (name.to_string(), version.to_string());
+    if wanted.contains(&key) && !found.contains_key(&key) {
+        let children = children
+            .map(|c| {
+                c.iter()
+                    .map(|(n, v)| {
+                        let ver = v.get("version").and_then(|v| v.as_str()).unwrap_or("");
+                        format!("{n}@{ver}")
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        found.insert(
+            key,
+            MatchContext {
+                ancestors: ancestors.clone(),
+                children,
+            },
+        );
+    }
+    if let Some(children) = children {
+        ancestors.push(format!("{name}@{version}"));
+        for (child_name, child_node) in children {
+            context_walk(child_name, child_node, ancestors, wanted, found);
+        }
+        ancestors.pop();
+    }
+}
+
+/// Packages present at two or more distinct versions, sorted by name.
+fn find_duplicates(deps: &[Dep]) -> Vec<DuplicateRecord> {
+    let mut by_name: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for d in deps {
+        by_name.entry(&d.name).or_default().insert(&d.version);
+    }
+    by_name
+        .into_iter()
+        .filter(|(_, versions)| versions.len() > 1)
+        .map(|(name, versions)| DuplicateRecord {
+            name: name.to_string(),
+            versions: versions.into_iter().map(str::to_string).collect(),
+        })
+        .collect()
+}
+
+/// Collect dependencies from `npm query '*' --json` output: a flat array of
+/// package objects with `name`, `version`, and `location`.
+///
+/// The root project (empty location) is skipped; a package is direct when the
+/// root declares it and it is installed at `node_modules/<name>`.
+fn collect_npm_query_deps(root: &Value, scope: &str) -> Result<Vec<Dep>> {
+    let items = root
+        .as_array()
+        .ok_or_else(|| anyhow!("Expected a JSON array from `npm query` output"))?;
+    let location = |item: &Value| {
+        item.get("location")
+            .and_then(|l| l.as_str())
+            .map(str::to_string)
+    };
+
+    let mut declared = HashSet::new();
+    if let Some(project) = items.iter().find(|i| location(i).as_deref() == Some("")) {
+        for section in DEPENDENCY_SECTIONS {
+            if let Some(obj) = project.get(section).and_then(|d| d.as_object()) {
+                declared.extend(obj.keys().cloned());
+            }
+        }
+    }
+
+    let mut acc: Vec<Dep> = Vec::new();
+    let mut seen: HashMap<(String, String), usize> = HashMap::new();
+    for item in items {
+        let loc = location(item).unwrap_or_default();
+        if loc.is_empty() || !in_scan_scope(item, scope) {
+            continue;
+        }
+        let (Some(name), Some(version)) = (
+            item.get("name").and_then(|n| n.as_str()),
+            item.get("version").and_then(|v| v.as_str()),
+        ) else {
+            continue;
+        };
+        let direct = declared.contains(name) && loc == format!("node_modules/{name}");
+        let kind = DepKind::of(item);
+        let key = (name.to_string(), version.to_string());
+        if let Some(&idx) = seen.get(&key) {
+            acc[idx].direct |= direct;
+            acc[idx].kind = acc[idx].kind.merge(kind);
+        } else {
+            seen.insert(key.clone(), acc.len());
+            acc.push(Dep {
+                name: key.0,
+                version: key.1,
+                direct,
+                kind,
+            });
+        }
+    }
+    acc.sort();
+    Ok(acc)
+}
+
+/// Collect dependencies from a package-lock.json without installing anything.
+///
+/// v2/v3 lockfiles list every install location in the flat `packages` map
+/// (`node_modules/a/node_modules/@scope/b`); v1 lockfiles nest them under
+/// `dependencies`. The real name of an aliased package comes from the entry's
+/// `name` (v2/v3) or its `npm:<name>@<version>` version (v1). A package is
+/// direct when it sits in the top-level `node_modules` under a name the root
+/// declares: `packages[""]` in v2/v3, the package.json beside a v1 lockfile.
+fn collect_package_lock_deps(path: &PathBuf, scope: &str) -> Result<Vec<Dep>> {
+    let mut acc: Vec<Dep> = Vec::new();
+    let mut seen: HashMap<(String, String), usize> = HashMap::new();
+    for LockedPackage {
+        name,
+        version,
+        direct,
+        kind,
+        ..
+    } in read_package_lock(path, scope)?
+    {
+        let key = (name, version);
+        if let Some(&idx) = seen.get(&key) {
+            acc[idx].direct |= direct;
+            acc[idx].kind = acc[idx].kind.merge(kind);
+        } else {
+            seen.insert(key.clone(), acc.len());
+            acc.push(Dep {
+                name: key.0,
+                version: key.1,
+                direct,
+                kind,
+            });
+        }
+    }
+    acc.sort();
+    Ok(acc)
+}
+
+/// One install location in a package-lock.json.
+struct LockedPackage {
+    name: String,
+    version: String,
+    direct: bool,
+    kind: DepKind,
+    integrity: Option<String>,
+    resolved: Option<String>,
+    license: Option<String>, // lockfile v2+ only
+}
+
+/// Every install location in a package-lock.json, see `collect_package_lock_deps`.
+fn read_package_lock(path: &PathBuf, scope: &str) -> Result<Vec<LockedPackage>> {
+    let data = fs::read_to_string(path)?;
+    let lock: Value = serde_json::from_str(&data).context("Failed to parse lockfile JSON")?;
+    let integrity = |node: &Value| node.get("integrity").and_then(|i| i.as_str()).map(String::from);
+    let resolved = |node: &Value| node.get("resolved").and_then(|r| r.as_str()).map(String::from);
+
+    let mut found = Vec::new();
+    if let Some(packages) = lock.get("packages").and_then(|p| p.as_object()) {
+        let mut declared = HashSet::new();
+        if let Some(project) = packages.get("") {
+            for section in DEPENDENCY_SECTIONS {
+                if let Some(obj) = project.get(section).and_then(|d| d.as_object()) {
+                    declared.extend(obj.keys().cloned());
+                }
+            }
+        }
+        for (location, node) in packages {
+            // Workspace folders and `link: true` entries point at other locations.
+            let Some((_, installed_as)) = package_dir_from_module_path(location) else {
+                continue;
+            };
+            if node.get("link").and_then(|l| l.as_bool()) == Some(true)
+                || !in_scan_scope(node, scope)
+            {
+                continue;
+            }
+            let Some(version) = node.get("version").and_then(|v| v.as_str()) else {
+                continue;
+            };
+            let name = node.get("name").and_then(|n| n.as_str()).unwrap_or(&installed_as);
+            let direct =
+                declared.contains(&installed_as) && *location == format!("node_modules/{installed_as}");
+            found.push(LockedPackage {
+                name: name.to_string(),
+                version: version.to_string(),
+                direct,
+                kind: DepKind::of(node),
+                integrity: integrity(node),
+                resolved: resolved(node),
+                license: license_of(node),
+            });
+        }
+    } else if let Some(deps) = lock.get("dependencies").and_then(|d| d.as_object()) {
+        let mut declared = HashSet::new();
+        let manifest = path.with_file_name("package.json");
+        if let Some(project) = fs::read_to_string(&manifest)
+            .ok()
+            .and_then(|d| serde_json::from_str::<Value>(&d).ok())
+        {
+            for section in DEPENDENCY_SECTIONS {
+                if let Some(obj) = project.get(section).and_then(|d| d.as_object()) {
+                    declared.extend(obj.keys().cloned());
+                }
+            }
+        }
+        fn walk(
+            deps: &serde_json::Map<String, Value>,
+            top_level: bool,
+            declared: &HashSet<String>,
+            scope: &str,
+            found: &mut Vec<LockedPackage>,
+        ) {
+            for (installed_as, node) in deps {
+                if !in_scan_scope(node, scope) {
+                    continue;
+                }
+                if let Some(version) = node.get("version").and_then(|v| v.as_str()) {
+                    let (name, version) = version
+                        .strip_prefix("npm:")
+                        .and_then(|aliased| {
+                            let at = aliased.rfind('@').filter(|&i| i > 0)?;
+                            Some((&aliased[..at], &aliased[at + 1..]))
+                        })
+                        .unwrap_or((installed_as.as_str(), version));
+                    found.push(LockedPackage {
+                        name: name.to_string(),
+                        version: version.to_string(),
+                        direct: top_level && declared.contains(installed_as),
+                        kind: DepKind::of(node),
+                        integrity: node.get("integrity").and_then(|i| i.as_str()).map(String::from),
+                        resolved: node.get("resolved").and_then(|r| r.as_str()).map(String::from),
+                        license: None,
+                    });
+                }
+                if let Some(nested) = node.get("dependencies").and_then(|d| d.as_object()) {
+                    walk(nested, false, declared, scope, found);
+                }
+            }
+        }
+        walk(deps, true, &declared, scope, &mut found);
+    } else {
+        return Err(anyhow!(
+            "Not a package-lock.json (neither `packages` nor `dependencies` at the top level)"
+        ));
+    }
+    Ok(found)
+}
+
+/// Collect dependencies from a yarn.lock, classic (v1) or Berry (v2+, YAML).
+///
+/// Both formats are blocks keyed by comma-separated descriptors at column 0
+/// (`"@babel/core@^7.0.0", "@babel/core@^7.1.0":`) with two-space-indented
+/// fields: `version "7.23.0"` in v1, `version: 7.23.0` in Berry, which is
+/// recognized by its `__metadata:` block. In v1 an alias descriptor
+/// (`foo@npm:bar@1.2.3`) names the real package; Berry's `resolution` always
+/// does, and entries resolved by other protocols (workspace, patch, git, ...)
+/// are skipped. A package is direct when the package.json beside the lockfile
+/// declares one of its descriptors.
+fn collect_yarn_lock_deps(path: &PathBuf) -> Result<Vec<Dep>> {
+    let content = fs::read_to_string(path)?;
+    let berry = content.lines().any(|l| l.trim_end() == "__metadata:");
+
+    // `name@range` -> (name, range), keeping a scope's leading '@'.
+    fn split_descriptor(descriptor: &str) -> Option<(&str, &str)> {
+        let at = descriptor.get(1..)?.find('@')? + 1;
+        Some((&descriptor[..at], &descriptor[at + 1..]))
+    }
+
+    let mut declared: HashMap<String, String> = HashMap::new();
+    let manifest = path.with_file_name("package.json");
+    if let Some(project) = fs::read_to_string(&manifest)
+        .ok()
+        .and_then(|d| serde_json::from_str::<Value>(&d).ok())
+    {
+        for section in DEPENDENCY_SECTIONS {
+            for (name, range) in project.get(section).and_then(|d| d.as_object()).into_iter().flatten() {
+                if let Some(range) = range.as_str() {
+                    declared.insert(name.clone(), range.to_string());
+                }
+            }
+        }
+    }
+
+    // (descriptors, fields) per block
+    let mut blocks: Vec<(String, HashMap<String, String>)> = Vec::new();
+    for (lineno, line) in content.lines().enumerate() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        if !line.starts_with(' ') {
+            let key = line
+                .trim_end()
+                .strip_suffix(':')
+                .ok_or_else(|| anyhow!("Line {}: expected `<descriptors>:`, got '{}'", lineno + 1, line))?;
+            blocks.push((key.to_string(), HashMap::new()));
+            continue;
+        }
+        // Only the block's own fields; deeper lines are dependency maps.
+        let Some(field) = line.strip_prefix("  ").filter(|f| !f.starts_with(' ')) else {
+            continue;
+        };
+        let Some((_, fields)) = blocks.last_mut() else {
+            return Err(anyhow!("Line {}: field outside any entry", lineno + 1));
+        };
+        let split = if berry {
+            field.split_once(": ")
+        } else {
+            field.split_once(' ')
+        };
+        if let Some((key, value)) = split {
+            fields.insert(key.trim().to_string(), value.trim().trim_matches('"').to_string());
+        }
+    }
+
+    let mut acc: Vec<Dep> = Vec::new();
+    let mut seen: HashMap<(String, String), usize> = HashMap::new();
+    for (key, fields) in &blocks {
+        let descriptors: Vec<&str> = key.split(", ").map(|d| d.trim().trim_matches('"')).collect();
+        if descriptors.iter().any(|d| d.starts_with("__metadata")) {
+            continue;
+        }
+        let Some(version) = fields.get("version") else {
+            continue;
+        };
+        let name = if berry {
+            let resolution = fields.get("resolution").map(String::as_str).unwrap_or_default();
+            match split_descriptor(resolution) {
+                Some((name, reference)) if reference.starts_with("npm:") => name,
+                _ => continue,
+            }
+        } else {
+            let Some((installed_as, range)) = descriptors.first().and_then(|d| split_descriptor(d))
+            else {
+                continue;
+            };
+            match range.strip_prefix("npm:").and_then(split_descriptor) {
+                Some((real, _)) => real,
+                None => installed_as,
+            }
+        };
+        let direct = descriptors.iter().filter_map(|d| split_descriptor(d)).any(|(n, r)| {
+            declared
+                .get(n)
+                .is_some_and(|want| r == want || r.strip_prefix("npm:") == Some(want.as_str()))
+        });
+        let key = (name.to_string(), version.clone());
+        if let Some(&idx) = seen.get(&key) {
+            acc[idx].direct |= direct;
+        } else {
+            seen.insert(key.clone(), acc.len());
+            acc.push(Dep {
+                name: key.0,
+                version: key.1,
+                direct,
+                kind: DepKind::Prod,
+            });
+        }
+    }
+    acc.sort();
+    Ok(acc)
+}
+
+/// Collect dependencies from a pnpm-lock.yaml.
+///
+/// Every key of `packages` (and of v9's `snapshots`) is a resolved package:
+/// `/name/1.0.0` in lockfile 5.x, `/name@1.0.0` in 6.x, `name@1.0.0` in 9,
+/// scoped names included, with peer suffixes (`_peer@1.0.0` in 5.x,
+/// `(peer@1.0.0)` later) stripped. Keys that resolve to no registry version
+/// (`file:`, git, tarball URLs) are skipped. A package is direct when the root
+/// project depends on it: the top-level `dependencies` sections of a
+/// single-project lockfile, or those of the `.` importer. `link:` (workspace)
+/// dependencies are skipped.
+///
+/// Only the block-mapping subset of YAML that pnpm writes is read: each line's
+/// key is placed by its indentation, and flow values are kept as text.
+fn collect_pnpm_lock_deps(path: &PathBuf) -> Result<Vec<Dep>> {
+    let content = fs::read_to_string(path)?;
+    let unquote = |s: &str| s.trim().trim_matches(|c| c == '\'' || c == '"').to_string();
+    let mut lockfile_version = String::new();
+    let mut keys = Vec::new();
+    let mut direct = HashSet::new();
+
+    // `name@version` or, for 5.x, `name/version`, after dropping the leading
+    // '/' and any peer suffix. None unless the version is a registry version.
+    let v5 = |version: &str| version.starts_with('5');
+    let split_key = |key: &str, v5: bool| -> Option<(String, String)> {
+        let key = key.strip_prefix('/').unwrap_or(key);
+        let key = key.split('(').next().unwrap_or(key);
+        let sep = if v5 { '/' } else { '@' };
+        let at = key.rfind(sep).filter(|&i| i > 0)?;
+        let (name, version) = (&key[..at], &key[at + 1..]);
+        let version = if v5 { version.split('_').next().unwrap_or(version) } else { version };
+        if !version.starts_with(|c: char| c.is_ascii_digit()) || version.contains([':', '/']) {
+            return None;
+        }
+        Some((name.to_string(), version.to_string()))
+    };
+
+    let mut stack: Vec<(usize, String)> = Vec::new();
+    for (lineno, line) in content.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with('-') {
+            continue;
+        }
+        let indent = line.len() - line.trim_start().len();
+        // A key ends at the first ':' followed by a space or the end of the line;
+        // quoted keys may contain both.
+        let quote = trimmed.chars().next().filter(|c| *c == '\'' || *c == '"');
+        let (key, value) = if let Some(quote) = quote {
+            let end = trimmed[1..]
+                .find(quote)
+                .ok_or_else(|| anyhow!("Line {}: unterminated quoted key", lineno + 1))?;
+            let rest = trimmed[end + 2..].strip_prefix(':').unwrap_or_default();
+            (trimmed[1..end + 1].to_string(), rest.trim().to_string())
+        } else {
+            match trimmed.split_once(": ") {
+                Some((key, value)) => (key.to_string(), value.trim().to_string()),
+                None => (trimmed.trim_end_matches(':').to_string(), String::new()),
+            }
+        };
+        while stack.last().is_some_and(|(i, _)| *i >= indent) {
+            stack.pop();
+        }
+        let parents: Vec<&str> = stack.iter().map(|(_, k)| k.as_str()).collect();
+        let dep_section =
+            |s: &str| matches!(s, "dependencies" | "devDependencies" | "optionalDependencies");
+        match parents.as_slice() {
+            [] if key == "lockfileVersion" => lockfile_version = unquote(&value),
+            ["packages" | "snapshots"] => keys.push(key.clone()),
+            // `name: version` (5.x) or `name:` with a nested `version:` (6.x, 9).
+            [section] | ["importers", ".", section]
+                if dep_section(section) && !value.is_empty() =>
+            {
+                direct.insert((key.clone(), unquote(&value)));
+            }
+            [section, name] | ["importers", ".", section, name]
+                if dep_section(section) && key == "version" =>
+            {
+                direct.insert((name.to_string(), unquote(&value)));
+            }
+            _ => {}
+        }
+        stack.push((indent, key));
+    }
+    if lockfile_version.is_empty() {
+        return Err(anyhow!("Not a pnpm-lock.yaml (no lockfileVersion)"));
+    }
+    let v5 = v5(&lockfile_version);
+
+    // Direct deps as (name, version); a `version` naming another package is an alias.
+    let direct: HashSet<(String, String)> = direct
+        .into_iter()
+        .filter(|(_, version)| !version.starts_with("link:"))
+        .filter_map(|(name, version)| {
+            let version = version.split(['(', '_']).next().unwrap_or_default();
+            if version.starts_with('/') || version.contains('@') {
+                split_key(version, v5)
+            } else {
+                Some((name, version.to_string()))
+            }
+        })
+        .collect();
+
+    let mut acc: Vec<Dep> = Vec::new();
+    let mut seen = HashSet::new();
+    for key in &keys {
+        let Some((name, version)) = split_key(key, v5) else {
+            continue;
+        };
+        if seen.insert((name.clone(), version.clone())) {
+            let is_direct = direct.contains(&(name.clone(), version.clone()));
+            acc.push(Dep {
+                name,
+                version,
+                direct: is_direct,
+                kind: DepKind::Prod,
+            });
+        }
+    }
+    acc.sort();
+    Ok(acc)
+}
+
+/// True if any dependency declared in the manifest (any section) is a listed name.
+fn manifest_declares_listed_name(
+    path: &PathBuf,
+    lists: &CompromisedList,
+    match_basename: bool,
+) -> Result<bool> {
+    let data = fs::read_to_string(path).context(format!("Failed to read manifest: {:?}", path))?;
+    let manifest: Value =
+        serde_json::from_str(&data).context(format!("Failed to parse manifest: {:?}", path))?;
+    let declared = DEPENDENCY_SECTIONS
+        .iter()
+        .filter_map(|section| manifest.get(section).and_then(|d| d.as_object()))
+        .flat_map(|obj| obj.keys());
+    for name in declared {
+        let base = scoped_basename(name).filter(|_| match_basename);
+        if lists.names.contains(name) || base.is_some_and(|b| lists.names.contains(b)) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// package.json sections that declare dependencies.
+const DEPENDENCY_SECTIONS: [&str; 4] = [
+    "dependencies",
+    "devDependencies",
+    "optionalDependencies",
+    "peerDependencies",
+];
+
+/// A package directory found by `--scan-node-modules`.
+struct InstalledPackage {
+    name: String,
+    version: String,
+    direct: bool,
+    dir: PathBuf,
+}
+
+/// Walk a node_modules directory, nested `node_modules` and `@scope`
+/// directories included, reading each package's own package.json for its
+/// real name and version. Symlinked packages (pnpm, `npm link`) are followed,
+/// and the node_modules holding their target is walked too, which is where
+/// pnpm keeps their dependencies; every real directory is visited once, so
+/// symlink cycles end. Top-level packages that the package.json beside
+/// node_modules declares are direct. An unreadable or malformed package.json
+/// is skipped with a warning.
+fn collect_node_modules_packages(root: &std::path::Path) -> Result<Vec<InstalledPackage>> {
+    let mut declared = HashSet::new();
+    let manifest = root.parent().unwrap_or(root).join("package.json");
+    if let Some(project) = fs::read_to_string(&manifest)
+        .ok()
+        .and_then(|d| serde_json::from_str::<Value>(&d).ok())
+    {
+        for section in DEPENDENCY_SECTIONS {
+            if let Some(obj) = project.get(section).and_then(|d| d.as_object()) {
+                declared.extend(obj.keys().cloned());
+            }
+        }
+    }
+    let root_canonical = fs::canonicalize(root)?;
+
+    let mut found = Vec::new();
+    let mut visited = HashSet::new();
+    let mut pending = vec![root_canonical.clone()];
+    while let Some(node_modules) = pending.pop() {
+        if !visited.insert(node_modules.clone()) {
+            continue;
+        }
+        let top_level = node_modules == root_canonical;
+        let mut package_dirs = Vec::new();
+        let entries = match fs::read_dir(&node_modules) {
+            Ok(entries) => entries,
+            Err(e) if top_level => return Err(e.into()),
+            Err(e) => {
+                diag!("Warning: skipping {}: {}", node_modules.display(), e);
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            // `.bin`, `.package-lock.json`, pnpm's `.pnpm` store, ...
+            if file_name.starts_with('.') {
+                continue;
+            }
+            if file_name.starts_with('@') {
+                for scoped in fs::read_dir(entry.path()).into_iter().flatten().flatten() {
+                    let scoped_name = scoped.file_name().to_string_lossy().into_owned();
+                    package_dirs.push((format!("{file_name}/{scoped_name}"), scoped.path()));
+                }
+            } else {
+                package_dirs.push((file_name, entry.path()));
+            }
+        }
+        package_dirs.sort();
+
+        for (installed_as, dir) in package_dirs {
+            let Ok(real_dir) = fs::canonicalize(&dir) else {
+                diag!("Warning: skipping {}: broken symlink", dir.display());
+                continue;
+            };
+            if !real_dir.is_dir() || !visited.insert(real_dir.clone()) {
+                continue;
+            }
+            let manifest_path = dir.join("package.json");
+            let manifest = fs::read_to_string(&manifest_path)
+                .map_err(anyhow::Error::from)
+                .and_then(|d| Ok(serde_json::from_str::<Value>(&d)?));
+            let manifest = match manifest {
+                Ok(manifest) => manifest,
+                Err(e) => {
+                    diag!("Warning: skipping {}: {}", manifest_path.display(), e);
+                    continue;
+                }
+            };
+            let field = |key: &str| manifest.get(key).and_then(|v| v.as_str()).map(String::from);
+            found.push(InstalledPackage {
+                name: field("name").unwrap_or_else(|| installed_as.clone()),
+                version: field("version").unwrap_or_else(|| UNKNOWN_VERSION.to_string()),
+                direct: top_level && (declared.is_empty() || declared.contains(&installed_as)),
+                dir: dir.clone(),
+            });
+
+            let nested = real_dir.join("node_modules");
+            if nested.is_dir() {
+                pending.push(nested);
+            }
+            if fs::symlink_metadata(&dir).is_ok_and(|m| m.file_type().is_symlink()) {
+                // Siblings of a symlinked package's target are its dependencies (pnpm).
+                let holder = if installed_as.starts_with('@') {
+                    real_dir.parent().and_then(|p| p.parent())
+                } else {
+                    real_dir.parent()
+                };
+                if let Some(holder) = holder.filter(|h| h.ends_with("node_modules")) {
+                    pending.push(holder.to_path_buf());
+                }
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Deps from `collect_node_modules_packages`, one per name and version.
+fn dedup_installed_packages(installed: Vec<InstalledPackage>) -> Vec<Dep> {
+    let mut acc: Vec<Dep> = Vec::new();
+    let mut seen: HashMap<(String, String), usize> = HashMap::new();
+    for p in installed {
+        let key = (p.name, p.version);
+        if let Some(&idx) = seen.get(&key) {
+            acc[idx].direct |= p.direct;
+        } else {
+            seen.insert(key.clone(), acc.len());
+            acc.push(Dep {
+                name: key.0,
+                version: key.1,
+                direct: p.direct,
+                kind: DepKind::Prod,
+            });
+        }
+    }
+    acc.sort();
+    acc
+}
+
+/// Warn about packages on disk that the npm ls tree does not list, or lists
+/// at other versions.
+fn report_node_modules_discrepancies(installed: &[InstalledPackage], tree_deps: &[Dep]) {
+    let mut tree_versions: HashMap<&str, BTreeSet<&str>> = HashMap::new();
+    for d in tree_deps {
+        tree_versions.entry(&d.name).or_default().insert(&d.version);
+    }
+    let mut reported = HashSet::new();
+    for p in installed {
+        if !reported.insert((&p.name, &p.version)) {
+            continue;
+        }
+        match tree_versions.get(p.name.as_str()) {
+            None => diag!(
+                "Warning: {}@{} is on disk ({}) but not in the npm ls tree",
+                p.name,
+                p.version,
+                p.dir.display()
+            ),
+            Some(versions) if !versions.contains(p.version.as_str()) => diag!(
+                "Warning: {}@{} is on disk ({}) but the npm ls tree has {}",
+                p.name,
+                p.version,
+                p.dir.display(),
+                versions.iter().copied().collect::<Vec<_>>().join(", ")
+            ),
+            Some(_) => {}
+        }
+    }
+}
+
+/// Collect dependencies from a JSON dump of `require.cache` module paths.
+///
+/// Accepts either an array of paths (`Object.keys(require.cache)`) or the
+/// object itself, keyed by path. Each path is reduced to its innermost
+/// `node_modules/<pkg>` directory, whose package.json supplies the version.
+fn collect_require_cache_deps(path: &PathBuf) -> Result<Vec<Dep>> {
+    let data = fs::read_to_string(path)?;
+    let v: Value = serde_json::from_str(&data).context("Failed to parse require.cache JSON")?;
+    let paths: Vec<&str> = match &v {
+        Value::Array(items) => items.iter().filter_map(|i| i.as_str()).collect(),
+        Value::Object(obj) => obj.keys().map(|k| k.as_str()).collect(),
+        _ => return Err(anyhow!("Expected a JSON array or object of module paths")),
+    };
+
+    let mut acc = Vec::new();
+    let mut seen = HashSet::new();
+    for module_path in paths {
+        let Some((pkg_dir, name)) = package_dir_from_module_path(module_path) else {
+            continue;
+        };
+        let version =
+            read_package_version(&pkg_dir).unwrap_or_else(|| UNKNOWN_VERSION.to_string());
+        if seen.insert((name.clone(), version.clone())) {
+            acc.push(Dep {
+                name,
+                version,
+                direct: false,
+                kind: DepKind::Prod,
+            });
+        }
+    }
+    acc.sort();
+    Ok(acc)
+}
+
+/// Collect dependencies from a generic JSON array of package objects:
+/// `name` and `version` strings are required, `direct` (bool) is optional.
+/// Any malformed object fails the whole load, naming its index.
+fn collect_generic_deps(path: &PathBuf) -> Result<Vec<Dep>> {
+    let data = fs::read_to_string(path)?;
+    let v: Value = serde_json::from_str(&data).context("Failed to parse dependency JSON")?;
+    let items = v
+        .as_array()
+        .ok_or_else(|| anyhow!("Expected a JSON array of {{\"name\", \"version\"}} objects"))?;
+
+    let mut acc: Vec<Dep> = Vec::new();
+    let mut seen: HashMap<(String, String), usize> = HashMap::new();
+    for (i, item) in items.iter().enumerate() {
+        let field = |key: &str| {
+            item.get(key)
+                .and_then(|f| f.as_str())
+                .filter(|f| !f.is_empty())
+                .ok_or_else(|| anyhow!("Entry {} has no non-empty string `{}`: {}", i, key, item))
+        };
+        let (name, version) = (field("name")?, field("version")?);
+        let direct = match item.get("direct") {
+            None => false,
+            Some(Value::Bool(b)) => *b,
+            Some(other) => return Err(anyhow!("Entry {} has a non-boolean `direct`: {}", i, other)),
+        };
+        let key = (name.to_string(), version.to_string());
+        if let Some(&idx) = seen.get(&key) {
+            acc[idx].direct |= direct;
+        } else {
+            seen.insert(key.clone(), acc.len());
+            acc.push(Dep {
+                name: key.0,
+                version: key.1,
+                direct,
+                kind: DepKind::Prod,
+            });
+        }
+    }
+    acc.sort();
+    Ok(acc)
+}
+
+/// Collect the bundled dependencies of an `npm pack` tarball.
+///
+/// The archive is read in memory rather than extracted. Every
+/// `node_modules/<pkg>/package.json` supplies a dependency; it is direct when
+/// it sits in the top-level `node_modules` and the packed package.json declares it.
+fn collect_tarball_deps(path: &PathBuf, verbose: bool) -> Result<Vec<Dep>> {
+    let file = fs::File::open(path)?;
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+    let mut root = None;
+    let mut bundled = Vec::new(); // (name, version, top-level)
+    for entry in archive.entries().context("Not a gzipped tar archive")? {
+        let mut entry = entry?;
+        let entry_path = entry.path()?.to_string_lossy().replace('\\', "/");
+        let Some(dir) = entry_path.strip_suffix("/package.json") else {
+            continue;
+        };
+        let is_root = !dir.is_empty() && !dir.contains('/');
+        let bundled_pkg = package_dir_from_module_path(dir)
+            .filter(|(pkg_dir, _)| pkg_dir.to_string_lossy() == dir);
+        if !is_root && bundled_pkg.is_none() {
+            continue;
+        }
+        let mut data = String::new();
+        entry.read_to_string(&mut data)?;
+        let manifest: Value = serde_json::from_str(&data)
+            .context(format!("Failed to parse {} in tarball", entry_path))?;
+        match bundled_pkg {
+            Some((_, name)) => {
+                let version = manifest
+                    .get("version")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or(UNKNOWN_VERSION)
+                    .to_string();
+                let top_level = dir.matches("node_modules").count() == 1;
+                bundled.push((name, version, top_level));
+            }
+            None => root = Some(manifest),
+        }
+    }
+    let root = root.ok_or_else(|| anyhow!("No package.json at the top of the tarball"))?;
+
+    let mut declared = HashSet::new();
+    for section in DEPENDENCY_SECTIONS {
+        if let Some(obj) = root.get(section).and_then(|d| d.as_object()) {
+            declared.extend(obj.keys().cloned());
+        }
+    }
+    if verbose {
+        eprintln!(
+            "Scanning {}@{} with {} bundled package(s).",
+            root.get("name").and_then(|n| n.as_str()).unwrap_or("unnamed"),
+            root.get("version").and_then(|v| v.as_str()).unwrap_or(UNKNOWN_VERSION),
+            bundled.len()
+        );
+    }
+
+    let mut acc: Vec<Dep> = Vec::new();
+    let mut seen: HashMap<(String, String), usize> = HashMap::new();
+    for (name, version, top_level) in bundled {
+        let direct = top_level && declared.contains(&name);
+        let key = (name, version);
+        if let Some(&idx) = seen.get(&key) {
+            acc[idx].direct |= direct;
+        } else {
+            seen.insert(key.clone(), acc.len());
+            acc.push(Dep {
+                name: key.0,
+                version: key.1,
+                direct,
+                kind: DepKind::Prod,
+            });
+        }
+    }
+    acc.sort();
+    Ok(acc)
+}
+
+/// Split a module path at its last `node_modules` segment, returning the
+/// package directory and package name (`pkg` or `@scope/pkg`).
+fn package_dir_from_module_path(module_path: &str) -> Option<(PathBuf, String)> {
+    let segments: Vec<&str> = module_path.split(['/', '\\']).collect();
+    let idx = segments.iter().rposition(|s| *s == "node_modules")?;
+    let first = segments.get(idx + 1).filter(|s| !s.is_empty())?;
+    let name_len = if first.starts_with('@') { 2 } else { 1 };
+    let name_segments = segments.get(idx + 1..idx + 1 + name_len)?;
+    let name = name_segments.join("/");
+    let pkg_dir: PathBuf = segments[..idx + 1 + name_len].join("/").into();
+    Some((pkg_dir, name))
+}
+
+/// Read the `version` field of `<dir>/package.json`, if present.
+fn read_package_version(dir: &std::path::Path) -> Option<String> {
+    let data = fs::read_to_string(dir.join("package.json")).ok()?;
+    let v: Value = serde_json::from_str(&data).ok()?;
+    v.get("version").and_then(|v| v.as_str()).map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write `content` to a fresh file under the temp dir, named after the test.
+    fn temp_file(name: &str, content: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("npm-compromised-scan-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    /// (name, version, direct, kind) of each dep, for comparing parser output.
+    fn summarize(deps: &[Dep]) -> Vec<(&str, &str, bool, DepKind)> {
+        deps.iter()
+            .map(|d| (d.name.as_str(), d.version.as_str(), d.direct, d.kind))
+            .collect()
+    }
+
+    fn cli(args: &[&str]) -> Cli {
+        Cli::try_parse_from(std::iter::once("npm-compromised-scan").chain(args.iter().copied()))
+            .unwrap()
+    }
+
+    fn record(match_type: &str, name: &str, version: &str) -> MatchRecord {
+        let dep = Dep {
+            name: name.to_string(),
+            version: version.to_string(),
+            direct: false,
+            kind: DepKind::Prod,
+        };
+        MatchRecord::new(match_type, &dep, None)
+    }
+
+    #[test]
+    fn min_occurrences_counts_lockfile_installs() {
+        let lock = temp_file(
+            "min-occurrences-package-lock.json",
+            r#"{
+                "lockfileVersion": 3,
+                "packages": {
+                    "": { "dependencies": { "a": "1.0.0", "evil": "1.0.0" } },
+                    "node_modules/evil": { "version": "1.0.0" },
+                    "node_modules/a": { "version": "1.0.0" },
+                    "node_modules/a/node_modules/evil": { "version": "1.0.0" },
+                    "node_modules/b/node_modules/evil": { "version": "1.0.0" },
+                    "node_modules/rare": { "version": "1.0.0" }
+                }
+            }"#,
+        );
+        let with_lock = cli(&[
+            "--package-lock",
+            lock.to_str().unwrap(),
+            "--min-occurrences",
+            "2",
+        ]);
+        let mut matches = vec![
+            record("name", "evil", "1.0.0"),
+            record("name", "rare", "1.0.0"),
+        ];
+        filter_min_occurrences(&with_lock, None, &with_lock.package_lock, &mut matches).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].name, "evil");
+
+        let without_tree = cli(&["--min-occurrences", "2"]);
+        assert!(filter_min_occurrences(&without_tree, None, &[], &mut matches).is_err());
+    }
+
+    #[test]
+    fn project_filters_drop_waived_and_baselined_matches_then_redact() {
+        let ignore = temp_file("project-filters-ignore.txt", "evil\n");
+        let baseline = temp_file(
+            "project-filters-baseline.json",
+            r#"{
+                "baseline_format_version": 1,
+                "matches": [{ "match_type": "exact", "name": "old", "version": "1.0.0" }]
+            }"#,
+        );
+        let cli = cli(&[
+            "--ignore-file",
+            ignore.to_str().unwrap(),
+            "--baseline",
+            baseline.to_str().unwrap(),
+            "--redact-versions",
+        ]);
+        let filters = ProjectFilters::load(&cli).unwrap();
+        let mut matches = vec![
+            record("exact", "evil", "1.0.0"),
+            record("exact", "old", "1.0.0"),
+            record("exact", "new", "2.0.0"),
+        ];
+        let dir = std::path::Path::new("app");
+        filters.apply(&cli, dir, None, &[], &mut matches).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].name, "new");
+        assert_eq!(matches[0].version, REDACTED_VERSION);
+    }
+
+    #[test]
+    fn recursive_lockfile_projects_get_lockfile_checks_and_filters() {
+        let digest = format!("sha512-{}==", "A".repeat(86));
+        let lock = temp_file(
+            "recursive-package-lock.json",
+            &r#"{
+                "lockfileVersion": 3,
+                "packages": {
+                    "": { "dependencies": { "x": "1.0.0", "y": "1.0.0" } },
+                    "node_modules/x": {
+                        "version": "1.0.0",
+                        "resolved": "https://evil.example/x/-/x-1.0.0.tgz",
+                        "integrity": "DIGEST"
+                    },
+                    "node_modules/y": { "version": "1.0.0" }
+                }
+            }"#
+            .replace("DIGEST", &digest),
+        );
+        let list = temp_file("recursive-list.txt", &format!("integrity:{digest}\ny\n"));
+        let ignore = temp_file("recursive-ignore.txt", "y\n");
+        let cli = cli(&[
+            "--list",
+            list.to_str().unwrap(),
+            "--allowed-registry",
+            "https://registry.npmjs.org/",
+            "--ignore-file",
+            ignore.to_str().unwrap(),
+            "--redact-versions",
+        ]);
+        let (lists, _) = load_compromised_list(&cli).unwrap();
+        let filters = ProjectFilters::load(&cli).unwrap();
+        let matches =
+            scan_lockfile(&cli, &lock, &lists, None, &filters, &match_options(&cli)).unwrap();
+        let found: Vec<(&str, &str, &str)> = matches
+            .iter()
+            .map(|m| (m.match_type.as_str(), m.name.as_str(), m.version.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("integrity", "x", REDACTED_VERSION),
+                ("registry", "x", REDACTED_VERSION)
+            ]
+        );
+    }
+
+    #[test]
+    fn redaction_masks_versions_the_match_repeats() {
+        let mut range = record("range", "evil", "1.2.3");
+        range.entry = Some("evil@^1.0.0".to_string());
+        range.resolved = Some("https://registry.npmjs.org/evil/-/evil-1.2.3.tgz".to_string());
+        range.safe_version = Some("1.2.4".to_string());
+        range.upgrade_risk = Some("patch".to_string());
+        range.below_floor = Some("evil@>=2.0.0".to_string());
+        redact_match(&mut range);
+        assert_eq!(range.version, REDACTED_VERSION);
+        assert_eq!(range.entry.as_deref(), Some("evil@x.x.x"));
+        assert_eq!(
+            range.resolved.as_deref(),
+            Some("https://registry.npmjs.org/evil/-/evil-x.x.x.tgz")
+        );
+        assert_eq!(
+            (range.safe_version, range.upgrade_risk, range.below_floor),
+            (None, None, None)
+        );
+
+        let mut integrity = record("advisory-integrity", "evil", "1.2.3");
+        integrity.entry = Some("evil@1.2.3#sha512-AAAA".to_string());
+        redact_match(&mut integrity);
+        assert_eq!(integrity.entry.as_deref(), Some("evil@x.x.x#sha512-AAAA"));
+
+        let mut repo = record("repo", "evil", "1.2.3");
+        repo.entry = Some("git+ssh://git@github.com/evil/evil.git".to_string());
+        redact_match(&mut repo);
+        assert_eq!(
+            repo.entry.as_deref(),
+            Some("git+ssh://git@github.com/evil/evil.git")
+        );
+    }
+
+    #[test]
+    fn redaction_masks_the_listed_build_of_an_exact_match() {
+        let list = temp_file("build-metadata-list.txt", "evil@1.0.0+build.5\n");
+        let cli = cli(&["--list", list.to_str().unwrap(), "--redact-versions"]);
+        let (lists, _) = load_compromised_list(&cli).unwrap();
+        let dep = Dep {
+            name: "evil".to_string(),
+            version: "1.0.0".to_string(),
+            direct: true,
+            kind: DepKind::Prod,
+        };
+        let mut matches = find_matches_with(&[dep], &lists, &match_options(&cli));
+        assert_eq!(matches[0].entry.as_deref(), Some("evil@1.0.0+build.5"));
+        redact_match(&mut matches[0]);
+        assert_eq!(matches[0].entry.as_deref(), Some("evil@x.x.x"));
+        assert!(!format_text_match(&matches[0]).contains("1.0.0"));
+    }
+
+    #[test]
+    fn redaction_masks_pattern_entries_and_their_echo() {
+        let list = temp_file("pattern-list.txt", "evil-*@1.2.3\n@ctrl/*\n");
+        let cli = cli(&["--list", list.to_str().unwrap(), "--redact-versions"]);
+        let (lists, _) = load_compromised_list(&cli).unwrap();
+        let deps = [Dep {
+            name: "evil-pkg".to_string(),
+            version: "1.2.3".to_string(),
+            direct: true,
+            kind: DepKind::Prod,
+        }];
+        let mut matches = find_matches_with(&deps, &lists, &match_options(&cli));
+        matches.iter_mut().for_each(redact_match);
+        assert_eq!(matches[0].entry.as_deref(), Some("evil-*@x.x.x"));
+        let report = JsonReport {
+            deps: &deps,
+            lists: &lists,
+            matches: &matches,
+            sample: None,
+            suppressed: None,
+            omitted: None,
+            existing: None,
+            stale_baseline: None,
+            skipped_nodes: None,
+            flagged_nodes: None,
+            problems: None,
+            duplicates: None,
+            result_hash: None,
+            sources: &[],
+            list_sources: &[],
+        };
+        let json = json_report(&cli, &report).unwrap();
+        assert_eq!(
+            json["compromised_patterns"],
+            json!(["@ctrl/*", "evil-*@x.x.x"])
+        );
+        assert_eq!(json["unmatched_entries"], json!(["@ctrl/*"]));
+    }
+
+    #[test]
+    fn package_lock_v1_walks_nested_dependencies() {
+        temp_file(
+            "lock-v1/package.json",
+            r#"{ "dependencies": { "a": "^1.0.0" } }"#,
+        );
+        let lock = temp_file(
+            "lock-v1/package-lock.json",
+            r#"{
+                "lockfileVersion": 1,
+                "dependencies": {
+                    "a": {
+                        "version": "1.0.0",
+                        "dependencies": {
+                            "b": { "version": "2.0.0" },
+                            "c": { "version": "npm:real@3.0.0" }
+                        }
+                    },
+                    "b": { "version": "1.0.0" }
+                }
+            }"#,
+        );
+        let deps = collect_package_lock_deps(&lock, "all").unwrap();
+        assert_eq!(
+            summarize(&deps),
+            [
+                ("a", "1.0.0", true, DepKind::Prod),
+                ("b", "1.0.0", false, DepKind::Prod),
+                ("b", "2.0.0", false, DepKind::Prod),
+                ("real", "3.0.0", false, DepKind::Prod),
+            ]
+        );
+    }
+
+    #[test]
+    fn package_lock_v3_reads_nested_node_modules_keys() {
+        let lock = temp_file(
+            "lock-v3/package-lock.json",
+            r#"{
+                "lockfileVersion": 3,
+                "packages": {
+                    "": { "dependencies": { "a": "^1.0.0" } },
+                    "node_modules/a": { "version": "1.0.0" },
+                    "node_modules/a/node_modules/b": { "version": "2.0.0" },
+                    "node_modules/@s/c": { "version": "1.0.0" },
+                    "node_modules/@s/c/node_modules/b": { "version": "2.0.0" },
+                    "node_modules/b": { "version": "1.0.0" },
+                    "packages/app": { "version": "0.1.0" }
+                }
+            }"#,
+        );
+        let deps = collect_package_lock_deps(&lock, "all").unwrap();
+        assert_eq!(
+            summarize(&deps),
+            [
+                ("@s/c", "1.0.0", false, DepKind::Prod),
+                ("a", "1.0.0", true, DepKind::Prod),
+                ("b", "1.0.0", false, DepKind::Prod),
+                ("b", "2.0.0", false, DepKind::Prod),
+            ]
+        );
+    }
+
+    #[test]
+    fn package_lock_dev_flags_follow_scan_scope() {
+        let lock = temp_file(
+            "lock-scope/package-lock.json",
+            r#"{
+                "lockfileVersion": 3,
+                "packages": {
+                    "": {},
+                    "node_modules/p": { "version": "1.0.0" },
+                    "node_modules/d": { "version": "1.0.0", "dev": true },
+                    "node_modules/o": { "version": "1.0.0", "optional": true },
+                    "node_modules/do": { "version": "1.0.0", "devOptional": true }
+                }
+            }"#,
+        );
+        let all = collect_package_lock_deps(&lock, "all").unwrap();
+        assert_eq!(
+            summarize(&all),
+            [
+                ("d", "1.0.0", false, DepKind::Dev),
+                ("do", "1.0.0", false, DepKind::DevOptional),
+                ("o", "1.0.0", false, DepKind::Optional),
+                ("p", "1.0.0", false, DepKind::Prod),
+            ]
+        );
+        let names = |scope: &str| -> Vec<String> {
+            let deps = collect_package_lock_deps(&lock, scope).unwrap();
+            deps.into_iter().map(|d| d.name).collect()
+        };
+        assert_eq!(names("dev"), ["d", "do"]);
+        assert_eq!(names("optional"), ["do", "o"]);
+        assert_eq!(names("prod"), ["do", "o", "p"]);
+    }
+
+    #[test]
+    fn yarn_classic_reads_multi_specifier_headers_and_aliases() {
+        temp_file(
+            "yarn-v1/package.json",
+            r#"{ "dependencies": { "@scope/a": "^1.0.1" } }"#,
+        );
+        let lock = temp_file(
+            "yarn-v1/yarn.lock",
+            r#"# yarn lockfile v1
+
+"@scope/a@^1", "@scope/a@^1.0.1":
+  version "1.0.2"
+  resolved "https://registry.yarnpkg.com/@scope/a/-/a-1.0.2.tgz"
+  dependencies:
+    b "^2.0.0"
+
+b@^2.0.0:
+  version "2.0.0"
+
+"c@npm:real@^3":
+  version "3.0.0"
+"#,
+        );
+        let deps = collect_yarn_lock_deps(&lock).unwrap();
+        assert_eq!(
+            summarize(&deps),
+            [
+                ("@scope/a", "1.0.2", true, DepKind::Prod),
+                ("b", "2.0.0", false, DepKind::Prod),
+                ("real", "3.0.0", false, DepKind::Prod),
+            ]
+        );
+    }
+
+    #[test]
+    fn yarn_berry_reads_npm_resolutions_and_skips_workspaces() {
+        temp_file(
+            "yarn-berry/package.json",
+            r#"{ "dependencies": { "@scope/a": "^1" } }"#,
+        );
+        let lock = temp_file(
+            "yarn-berry/yarn.lock",
+            r#"__metadata:
+  version: 8
+  cacheKey: 10
+
+"@scope/a@npm:^1, @scope/a@npm:^1.0.1":
+  version: 1.0.2
+  resolution: "@scope/a@npm:1.0.2"
+  dependencies:
+    alias: "npm:real@^2"
+
+"alias@npm:real@^2":
+  version: 2.0.0
+  resolution: "real@npm:2.0.0"
+
+"app@workspace:.":
+  version: 0.0.0-use.local
+  resolution: "app@workspace:."
+"#,
+        );
+        let deps = collect_yarn_lock_deps(&lock).unwrap();
+        assert_eq!(
+            summarize(&deps),
+            [
+                ("@scope/a", "1.0.2", true, DepKind::Prod),
+                ("real", "2.0.0", false, DepKind::Prod),
+            ]
+        );
+    }
+
+    const PNPM_DEPS: [(&str, &str, bool, DepKind); 2] = [
+        ("@s/peer", "2.0.0", false, DepKind::Prod),
+        ("a", "1.0.0", true, DepKind::Prod),
+    ];
+
+    #[test]
+    fn pnpm_5_keys_split_on_slash_and_drop_peer_suffix() {
+        let lock = temp_file(
+            "pnpm-5.yaml",
+            r#"lockfileVersion: 5.4
+
+specifiers:
+  a: ^1.0.0
+
+dependencies:
+  a: 1.0.0_@s+peer@2.0.0
+
+packages:
+
+  /a/1.0.0_@s+peer@2.0.0:
+    resolution: {integrity: sha512-AAAA}
+    dependencies:
+      '@s/peer': 2.0.0
+
+  /@s/peer/2.0.0:
+    resolution: {integrity: sha512-BBBB}
+"#,
+        );
+        let deps = collect_pnpm_lock_deps(&lock).unwrap();
+        assert_eq!(summarize(&deps), PNPM_DEPS);
+    }
+
+    #[test]
+    fn pnpm_6_keys_split_on_at_and_drop_peer_parentheses() {
+        let lock = temp_file(
+            "pnpm-6.yaml",
+            r#"lockfileVersion: '6.0'
+
+dependencies:
+  a:
+    specifier: ^1.0.0
+    version: 1.0.0(@s/peer@2.0.0)
+
+packages:
+
+  /a@1.0.0(@s/peer@2.0.0):
+    resolution: {integrity: sha512-AAAA}
+    dependencies:
+      '@s/peer': 2.0.0
+
+  /@s/peer@2.0.0:
+    resolution: {integrity: sha512-BBBB}
+"#,
+        );
+        let deps = collect_pnpm_lock_deps(&lock).unwrap();
+        assert_eq!(summarize(&deps), PNPM_DEPS);
+    }
+
+    #[test]
+    fn pnpm_9_reads_snapshot_keys_and_the_root_importer() {
+        let lock = temp_file(
+            "pnpm-9.yaml",
+            r#"lockfileVersion: '9.0'
+
+importers:
+
+  .:
+    dependencies:
+      a:
+        specifier: ^1.0.0
+        version: 1.0.0(@s/peer@2.0.0)
+
+packages:
+
+  a@1.0.0:
+    resolution: {integrity: sha512-AAAA}
+
+  '@s/peer@2.0.0':
+    resolution: {integrity: sha512-BBBB}
+
+snapshots:
+
+  a@1.0.0(@s/peer@2.0.0):
+    dependencies:
+      '@s/peer': 2.0.0
+
+  '@s/peer@2.0.0': {}
+"#,
+        );
+        let deps = collect_pnpm_lock_deps(&lock).unwrap();
+        assert_eq!(summarize(&deps), PNPM_DEPS);
+    }
+
+    #[test]
+    fn occurrences_exempt_keeps_version_confirmed_lockfile_matches() {
+        let tree = json!({ "dependencies": { "evil": { "version": "1.0.0" } } });
+        let cli = cli(&["--min-occurrences", "2", "--occurrences-exact-exempt"]);
+        let mut matches = vec![
+            record("integrity", "evil", "1.0.0"),
+            record("advisory-integrity-prefix", "evil", "1.0.0"),
+            record("signature-mismatch", "evil", "1.0.0"),
+            record("exact", "evil", "1.0.0"),
+            record("name", "evil", "1.0.0"),
+        ];
+        filter_min_occurrences(&cli, Some(&tree), &[], &mut matches).unwrap();
+        let kept: Vec<&str> = matches.iter().map(|m| m.match_type.as_str()).collect();
+        assert_eq!(
+            kept,
+            [
+                "integrity",
+                "advisory-integrity-prefix",
+                "signature-mismatch",
+                "exact"
+            ]
+        );
     }
-    let any = !matches.is_empty();
-    (matches, any)
 }
@@ -0,0 +1,207 @@
+//! Presentation of the text report and of stderr diagnostics: `--color`, the
+//! summary line, `--quiet`, `--warnings-as-errors`, and the `--verbose` list
+//! counts and phase timings.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use npm_compromised_scan::{CompromisedList, MatchRecord};
+
+use super::{is_version_confirmed, ADVISORY_PREFIX, MATCH_CONFIDENCE};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+static WARNINGS_AS_ERRORS: AtomicBool = AtomicBool::new(false);
+static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Exit code of `--warnings-as-errors` when a warning was emitted.
+pub const WARNINGS_EXIT_CODE: i32 = 44;
+
+/// Silence `diag!` for the rest of the run (`--quiet`).
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// `eprintln!` for warnings and notes, which `--quiet` drops. Errors still go
+/// through `Result`, so they are always printed.
+macro_rules! diag {
+    ($($arg:tt)*) => {
+        $crate::output::emit(format!($($arg)*))
+    };
+}
+pub(crate) use diag;
+
+/// Print a `diag!` message, keeping `Warning:` ones for `--warnings-as-errors`.
+pub fn emit(message: String) {
+    if !is_quiet() {
+        eprintln!("{message}");
+    }
+    if message.starts_with("Warning:") {
+        record_warning(message);
+    }
+}
+
+/// Count a warning for `--warnings-as-errors` without printing it, for
+/// conditions the report itself already shows.
+pub fn record_warning(message: String) {
+    WARNINGS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(message);
+}
+
+/// Make any warning of the run fail it (`--warnings-as-errors`).
+pub fn set_warnings_as_errors(enabled: bool) {
+    WARNINGS_AS_ERRORS.store(enabled, Ordering::Relaxed);
+}
+
+/// With `--warnings-as-errors`, exit with `WARNINGS_EXIT_CODE` after listing
+/// the run's warnings, if there were any; called once the report is written.
+pub fn fail_on_warnings() {
+    if !WARNINGS_AS_ERRORS.load(Ordering::Relaxed) {
+        return;
+    }
+    let warnings = WARNINGS.lock().unwrap_or_else(|e| e.into_inner());
+    if warnings.is_empty() {
+        return;
+    }
+    eprintln!(
+        "Error: {} warning(s) with --warnings-as-errors:",
+        warnings.len()
+    );
+    for warning in warnings.iter() {
+        eprintln!("  {}", warning.trim_start_matches("Warning: "));
+    }
+    std::process::exit(WARNINGS_EXIT_CODE);
+}
+
+/// `std::process::exit` for a scan result, letting `--warnings-as-errors` win.
+pub fn exit(code: i32) -> ! {
+    fail_on_warnings();
+    std::process::exit(code)
+}
+
+/// ANSI colors for text output, or none.
+#[derive(Debug, Clone, Copy)]
+pub struct Palette {
+    enabled: bool,
+}
+
+impl Palette {
+    /// `--color`: `always`, `never`, or `auto`, which colors a terminal unless
+    /// `NO_COLOR` is set to a non-empty value.
+    pub fn new(choice: &str, to_terminal: bool) -> Self {
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        let enabled = match choice {
+            "always" => true,
+            "never" => false,
+            _ => to_terminal && !no_color,
+        };
+        Palette { enabled }
+    }
+
+    fn paint(&self, code: &str, text: &str) -> String {
+        if self.enabled {
+            format!("\x1b[{code}m{text}\x1b[0m")
+        } else {
+            text.to_string()
+        }
+    }
+
+    pub fn green(&self, text: &str) -> String {
+        self.paint("32", text)
+    }
+
+    /// Red for a version-confirmed match, yellow for name-level findings and
+    /// advisory matches.
+    pub fn match_line(&self, m: &MatchRecord, line: &str) -> String {
+        let advisory = m.match_type.starts_with(ADVISORY_PREFIX);
+        let code = if !advisory && is_version_confirmed(&m.match_type) {
+            "31"
+        } else {
+            "33"
+        };
+        self.paint(code, line)
+    }
+}
+
+/// `Scanned 1,842 dependencies — 2 exact, 1 name match(es)`, match types in
+/// `MATCH_CONFIDENCE` order with advisory matches last.
+pub fn summary_line(dep_count: usize, matches: &[MatchRecord]) -> String {
+    let scanned = format!("Scanned {} dependencies", group_thousands(dep_count));
+    if matches.is_empty() {
+        return format!("{scanned} — no matches");
+    }
+    let rank = |match_type: &str| {
+        let category = match_type.strip_prefix(ADVISORY_PREFIX);
+        let position = MATCH_CONFIDENCE
+            .iter()
+            .position(|t| *t == category.unwrap_or(match_type))
+            .unwrap_or(MATCH_CONFIDENCE.len());
+        (category.is_some(), position, match_type.to_string())
+    };
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    for m in matches {
+        match counts.iter_mut().find(|(t, _)| *t == m.match_type) {
+            Some((_, n)) => *n += 1,
+            None => counts.push((&m.match_type, 1)),
+        }
+    }
+    counts.sort_by_key(|(t, _)| rank(t));
+    let parts: Vec<String> = counts.iter().map(|(t, n)| format!("{n} {t}")).collect();
+    format!("{scanned} — {} match(es)", parts.join(", "))
+}
+
+/// `1842` as `1,842`.
+fn group_thousands(n: usize) -> String {
+    let digits = n.to_string();
+    let mut out = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Entries loaded per rule category, for `--verbose`.
+pub fn list_counts(lists: &CompromisedList) -> String {
+    format!(
+        "List entries loaded: {} exact, {} range, {} name, {} pattern, {} repo, {} integrity",
+        lists.exact.len(),
+        lists.ranges.len(),
+        lists.bare_names.len(),
+        lists.patterns.len(),
+        lists.repos.len(),
+        lists.integrities.len()
+    )
+}
+
+/// Wall-clock time of consecutive phases, printed with `--verbose`.
+pub struct PhaseTimer {
+    enabled: bool,
+    last: Instant,
+}
+
+impl PhaseTimer {
+    pub fn start(enabled: bool) -> Self {
+        PhaseTimer {
+            enabled,
+            last: Instant::now(),
+        }
+    }
+
+    /// End the phase running since the previous call (or `start`).
+    pub fn finish(&mut self, phase: &str) {
+        let now = Instant::now();
+        if self.enabled {
+            eprintln!("Phase {phase}: {:.1?}", now - self.last);
+        }
+        self.last = now;
+    }
+}